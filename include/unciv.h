@@ -0,0 +1,133 @@
+#ifndef UNCIV_H
+#define UNCIV_H
+
+/* Generated from src/ffi.rs by cbindgen: don't edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define UNCIV_OK 0
+
+// Reading a file failed.
+#define UNCIV_ERR_IO -1
+
+// An archive or image is corrupt, or not what it should be.
+#define UNCIV_ERR_INVALID_DATA -2
+
+// An argument was NULL, out of range, or otherwise invalid.
+#define UNCIV_ERR_INVALID_ARGUMENT -3
+
+// The buffer given is too small for what was to be written to it.
+#define UNCIV_ERR_BUFFER_TOO_SMALL -4
+
+// There's no entry with the name given.
+#define UNCIV_ERR_NOT_FOUND -5
+
+// Something went wrong inside unciv itself (a bug, rather than a problem
+// with the arguments or files given).
+#define UNCIV_ERR_PANIC -6
+
+// An open archive.
+typedef struct UncivArchive UncivArchive;
+
+// Details of an entry in an archive.
+typedef struct {
+  // The entry's name, which is valid until the archive is closed.
+  const char *name;
+  uint32_t offset;
+  uint32_t size;
+  // When the entry was modified, in seconds since 1970.
+  uint64_t timestamp;
+  // The entry's flags, as they are in the file table.
+  uint32_t flags;
+} UncivEntry;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Describes the last error on this thread. The string is valid until the
+// next call into unciv on this thread.
+const char *unciv_last_error(void);
+
+// Opens the archive at `path` for reading, returning NULL if it can't be.
+//
+// # Safety
+//
+// `path` must be NULL or a NUL-terminated string.
+UncivArchive *unciv_archive_open(const char *path);
+
+// Closes an archive. Closing NULL does nothing.
+//
+// # Safety
+//
+// `archive` must be NULL or have come from `unciv_archive_open()`, and not
+// have been closed already.
+void unciv_archive_close(UncivArchive *archive);
+
+// The number of entries in an archive.
+//
+// # Safety
+//
+// `archive` must be an open archive.
+size_t unciv_archive_len(const UncivArchive *archive);
+
+// Fills in `entry` with the details of the entry at `index`.
+//
+// # Safety
+//
+// `archive` must be an open archive, and `entry` must point to an
+// `UncivEntry`.
+int unciv_archive_entry(const UncivArchive *archive, size_t index, UncivEntry *entry);
+
+// Finds the entry called exactly `name`, and stores its index in
+// `index`.
+//
+// # Safety
+//
+// `archive` must be an open archive, `name` a NUL-terminated string, and
+// `index` must point to a `size_t`.
+int unciv_archive_find(const UncivArchive *archive, const char *name, size_t *index);
+
+// Reads the data of the entry at `index` into `buffer`, which must be at
+// least as big as the entry (see `unciv_archive_entry()`).
+//
+// # Safety
+//
+// `archive` must be an open archive, and `buffer` must point to at least
+// `buffer_len` writable bytes.
+int unciv_archive_read(UncivArchive *archive, size_t index, uint8_t *buffer, size_t buffer_len);
+
+// Reads the size of the RIM image in `data` (e.g. an entry's data from
+// `unciv_archive_read()`).
+//
+// # Safety
+//
+// `data` must point to `len` readable bytes, and `width` and `height` to
+// `uint16_t`s.
+int unciv_rim_info(const uint8_t *data, size_t len, uint16_t *width, uint16_t *height);
+
+// Decodes the RIM image in `data` into 8-bit RGBA pixels, which need
+// width × height × 4 bytes of `rgba`.
+//
+// `color_key` says which pixels become transparent: "none" (or NULL),
+// "magenta", "zero", or a raw pixel value like "0x7c1f".
+//
+// # Safety
+//
+// `data` must point to `len` readable bytes, `color_key` must be NULL or a
+// NUL-terminated string, and `rgba` must point to `rgba_len` writable bytes.
+int unciv_rim_decode_rgba(const uint8_t *data,
+                          size_t len,
+                          const char *color_key,
+                          uint8_t *rgba,
+                          size_t rgba_len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* UNCIV_H */
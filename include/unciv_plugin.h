@@ -0,0 +1,82 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+/*
+ * The interface a decoder plugin exports, for unciv (built with the plugins
+ * feature) to load with "unciv extract --plugin <library>".
+ *
+ * Every entry extracted is offered to each plugin in turn with
+ * unciv_plugin_probe(), and the first to claim it converts it with
+ * unciv_plugin_decode(). The result is written next to where the entry would
+ * have been, with the plugin's extension added. These functions may be called
+ * from several threads at once.
+ *
+ * This is kept in step with src/plugin.rs by hand.
+ */
+
+#ifndef UNCIV_PLUGIN_H
+#define UNCIV_PLUGIN_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+// Returned by unciv_plugin_abi_version(). This changes whenever anything in
+// this file does, and unciv refuses plugins built for another version.
+#define UNCIV_PLUGIN_ABI_VERSION 1
+
+// How many bytes of each entry's data (at most) unciv_plugin_probe() is given.
+#define UNCIV_PLUGIN_PROBE_LEN 64
+
+typedef struct UncivPluginOutput {
+  // The converted data, which stays valid until unciv_plugin_free().
+  uint8_t *data;
+  size_t len;
+  // The extension for the converted data, without the dot (e.g. "png").
+  const char *extension;
+  // If decoding failed, why (or NULL).
+  const char *error;
+} UncivPluginOutput;
+
+// Returns UNCIV_PLUGIN_ABI_VERSION.
+uint32_t unciv_plugin_abi_version(void);
+
+// Returns the plugin's name, which is shown as entries are converted.
+const char *unciv_plugin_name(void);
+
+// Returns nonzero if the plugin converts the entry called `name`, whose data
+// starts with the `header_len` bytes at `header`.
+int unciv_plugin_probe(const char *name, const uint8_t *header, size_t header_len);
+
+// Converts the `len` bytes of the entry called `name`, filling in `output`.
+// Returns 0 on success, or nonzero (with output->error set, if possible) if
+// the entry can't be converted.
+int unciv_plugin_decode(const char *name, const uint8_t *data, size_t len, UncivPluginOutput *output);
+
+// Frees whatever unciv_plugin_decode() put in `output`. This is called after
+// every unciv_plugin_decode(), whether or not it succeeded.
+void unciv_plugin_free(UncivPluginOutput *output);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif
+
+#endif  // UNCIV_PLUGIN_H
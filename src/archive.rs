@@ -0,0 +1,177 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+use validate::Problem;
+use ExtractOptions;
+use ExtractReport;
+use OpenMode;
+use ZfsEntry;
+use ZfsFile;
+
+/// A ZFS archive which owns its reader, for random access to its entries.
+///
+/// This is a thin wrapper around `ZfsFile` which keeps the reader alongside
+/// the file table, and indexes the entries by name.
+pub struct ZfsArchive<R : Read + Seek>
+{
+    reader : R,
+    zfs_file : ZfsFile,
+    index : HashMap<String, usize>,
+}
+
+impl<R : Read + Seek> ZfsArchive<R>
+{
+    pub fn new(mut reader : R) -> io::Result<ZfsArchive<R>> {
+        let zfs_file = ZfsFile::from_stream(&mut reader)?;
+        Ok(ZfsArchive::with_file(reader, zfs_file))
+    }
+
+    /// Wraps a reader whose file table has already been read, e.g. with
+    /// `ZfsFile::open_indexed()`.
+    pub fn with_file(reader : R, zfs_file : ZfsFile) -> ZfsArchive<R> {
+        let mut index = HashMap::new();
+        for (i, entry) in zfs_file.files.iter().enumerate() {
+            // If an archive somehow has duplicate names, the first one wins.
+            index.entry(entry.name.clone()).or_insert(i);
+        }
+        ZfsArchive { reader, zfs_file, index }
+    }
+
+    pub fn len(&self) -> usize {
+        self.zfs_file.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.zfs_file.files.is_empty()
+    }
+
+    pub fn entries(&self) -> &[ZfsEntry] {
+        &self.zfs_file.files
+    }
+
+    pub fn by_index(&self, index : usize) -> Option<&ZfsEntry> {
+        self.zfs_file.files.get(index)
+    }
+
+    pub fn by_name(&self, name : &str) -> Option<&ZfsEntry> {
+        self.index_of(name).map(|i| &self.zfs_file.files[i])
+    }
+
+    pub fn index_of(&self, name : &str) -> Option<usize> {
+        self.index.get(name).cloned()
+    }
+
+    /// Returns a reader over the data of the entry at `index`.
+    pub fn reader(&mut self, index : usize) -> io::Result<io::Take<&mut R>> {
+        match self.zfs_file.files.get(index) {
+            Some(entry) => entry.reader(&mut self.reader),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, format!("No entry {} in archive", index))),
+        }
+    }
+
+    /// Reads the full contents of the entry at `index`.
+    pub fn read_data(&mut self, index : usize) -> io::Result<Vec<u8>> {
+        match self.zfs_file.files.get(index) {
+            Some(entry) => entry.read_data(&mut self.reader),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, format!("No entry {} in archive", index))),
+        }
+    }
+
+    /// Reads the full contents of the entry called `name`.
+    pub fn read_by_name(&mut self, name : &str) -> io::Result<Vec<u8>> {
+        match self.index_of(name) {
+            Some(i) => self.read_data(i),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, format!("No entry named \"{}\" in archive", name))),
+        }
+    }
+
+    /// Extracts every entry into `dir`, creating it if needed.
+    pub fn extract_to_dir(&mut self, dir : &Path) -> io::Result<ExtractReport> {
+        self.zfs_file.extract_to_dir(&mut self.reader, dir)
+    }
+
+    /// Extracts entries with the given options.
+    pub fn extract_with(&mut self, options : &ExtractOptions) -> ExtractReport {
+        self.zfs_file.extract_all_with(&mut self.reader, options)
+    }
+
+    /// Extracts the entries for which `selected` returns true (given their
+    /// index), with the given options.
+    pub fn extract_where(&mut self, options : &ExtractOptions, selected : impl Fn(usize) -> bool) -> ExtractReport {
+        self.zfs_file.extract_where(&mut self.reader, options, selected)
+    }
+
+    /// Extracts entries with the given options, using up to `jobs` threads,
+    /// each with its own reader from `open_reader`.
+    pub fn extract_parallel<F>(&self, open_reader : F, options : &ExtractOptions, jobs : usize) -> io::Result<ExtractReport>
+    where R : Send, F : Fn() -> io::Result<R>
+    {
+        self.zfs_file.extract_all_parallel(open_reader, options, jobs)
+    }
+
+    /// Checks the archive's structure. See `ZfsFile::validate()`.
+    pub fn validate(&mut self) -> io::Result<Vec<Problem>> {
+        self.zfs_file.validate(&mut self.reader)
+    }
+
+    pub fn zfs_file(&self) -> &ZfsFile {
+        &self.zfs_file
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl ZfsArchive<File>
+{
+    /// Opens the archive at `path`, which must be opened `ReadWrite` if
+    /// entries are to be replaced.
+    pub fn open(path : impl AsRef<Path>, mode : OpenMode) -> io::Result<ZfsArchive<File>> {
+        ZfsArchive::new(mode.open(path)?)
+    }
+}
+
+impl ZfsArchive<io::BufReader<File>>
+{
+    /// Opens the archive at `path` for reading, using its index if it has an
+    /// up-to-date one (see `ZfsFile::open_indexed()`).
+    pub fn open_indexed(path : impl AsRef<Path>) -> io::Result<ZfsArchive<io::BufReader<File>>> {
+        let path = path.as_ref();
+        let zfs_file = ZfsFile::open_indexed(path)?;
+        Ok(ZfsArchive::with_file(io::BufReader::new(OpenMode::ReadOnly.open(path)?), zfs_file))
+    }
+}
+
+impl<R : Read + Write + Seek> ZfsArchive<R>
+{
+    /// Replaces the contents of the entry called `name`. See
+    /// `ZfsFile::replace_entry()` for details.
+    pub fn replace_entry(&mut self, name : &str, data : &[u8]) -> io::Result<()> {
+        self.zfs_file.replace_entry(name, data, &mut self.reader)
+    }
+}
@@ -0,0 +1,143 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Sprite sheets ("atlases") of animation frames, which the game stores as
+//! numbered sequences of separate RIM images (`up01.rim`, `up02.rim`, …).
+
+use json::JsonValue;
+use rim::{ColorKey, RimImage};
+use std::collections::BTreeMap;
+use std::io;
+
+/// Splits an entry name into the base name of the sequence it's a frame of,
+/// and its frame number: "up01.rim" is frame 1 of "up". Returns None if the
+/// name doesn't end in a number (ignoring its extension).
+pub fn frame_of(name : &str) -> Option<(&str, u32)> {
+    let stem = match name.rfind('.') {
+        Some(dot) => &name[..dot],
+        None => name,
+    };
+    let base = stem.trim_end_matches(|c : char| c.is_ascii_digit());
+    let number = stem[base.len()..].parse().ok()?;
+    Some((base, number))
+}
+
+/// Groups names into sequences of frames by their base names (see
+/// `frame_of()`), giving the position of each frame in `names`, in frame
+/// order. Names which aren't numbered are left out.
+pub fn group_frames<'a>(names : impl IntoIterator<Item = &'a str>) -> BTreeMap<String, Vec<usize>> {
+    let mut groups : BTreeMap<String, Vec<(u32, usize)>> = BTreeMap::new();
+    for (position, name) in names.into_iter().enumerate() {
+        if let Some((base, number)) = frame_of(name) {
+            groups.entry(base.to_string()).or_default().push((number, position));
+        }
+    }
+    groups.into_iter().map(|(base, mut frames)| {
+        frames.sort();
+        (base, frames.into_iter().map(|(_, position)| position).collect())
+    }).collect()
+}
+
+/// Where one frame ended up in an atlas.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AtlasFrame
+{
+    pub name : String,
+    pub x : u32,
+    pub y : u32,
+    pub width : u32,
+    pub height : u32,
+}
+
+/// Frames packed into a single 8-bit RGBA image.
+#[derive(Clone, Debug)]
+pub struct Atlas
+{
+    pub width : u32,
+    pub height : u32,
+    pub rgba : Vec<u8>,
+    /// The frames, in the order they were given.
+    pub frames : Vec<AtlasFrame>,
+}
+
+impl Atlas
+{
+    /// Packs `frames` (each a name and an image) into an atlas, left to right
+    /// in rows, in the order given, with `padding` transparent pixels between
+    /// them so that they don't bleed into each other when filtered.
+    ///
+    /// The rows are about as wide as the atlas is tall, so that it stays
+    /// roughly square.
+    pub fn pack(frames : &[(String, RimImage)], color_key : ColorKey, padding : u32) -> io::Result<Atlas> {
+        if frames.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "An atlas needs at least one frame"));
+        }
+        let area : u64 = frames.iter().map(|(_, image)| (image.width as u64 + padding as u64) * (image.height as u64 + padding as u64)).sum();
+        let widest = frames.iter().map(|(_, image)| image.width as u32).max().unwrap_or(0);
+        let row_width = widest.max((area as f64).sqrt().ceil() as u32);
+
+        // Lay the frames out first, to find how big the atlas is.
+        let mut placed = Vec::with_capacity(frames.len());
+        let (mut x, mut y, mut row_height) = (0, 0, 0);
+        let (mut width, mut height) = (0, 0);
+        for (name, image) in frames {
+            let (frame_width, frame_height) = (image.width as u32, image.height as u32);
+            if x > 0 && x + frame_width > row_width {
+                x = 0;
+                y += row_height + padding;
+                row_height = 0;
+            }
+            placed.push(AtlasFrame { name : name.clone(), x, y, width : frame_width, height : frame_height });
+            width = width.max(x + frame_width);
+            height = height.max(y + frame_height);
+            x += frame_width + padding;
+            row_height = row_height.max(frame_height);
+        }
+
+        let mut rgba = vec![0; width as usize * height as usize * 4];
+        for ((_, image), frame) in frames.iter().zip(&placed) {
+            if frame.width == 0 || frame.height == 0 {
+                continue;
+            }
+            let pixels = image.to_rgba_bytes_with(color_key)?;
+            let row_bytes = frame.width as usize * 4;
+            for (row, src) in pixels.chunks_exact(row_bytes).enumerate().take(frame.height as usize) {
+                let start = ((frame.y as usize + row) * width as usize + frame.x as usize) * 4;
+                rgba[start..start + row_bytes].copy_from_slice(src);
+            }
+        }
+        Ok(Atlas { width, height, rgba, frames : placed })
+    }
+
+    /// Describes where each frame is, for loading the atlas from
+    /// `image_name`.
+    pub fn to_json(&self, image_name : &str) -> JsonValue {
+        JsonValue::object(vec![
+            ("image", image_name.into()),
+            ("width", self.width.into()),
+            ("height", self.height.into()),
+            ("frames", JsonValue::Array(self.frames.iter().map(|frame| JsonValue::object(vec![
+                ("name", frame.name.as_str().into()),
+                ("x", frame.x.into()),
+                ("y", frame.y.into()),
+                ("width", frame.width.into()),
+                ("height", frame.height.into()),
+            ])).collect())),
+        ])
+    }
+}
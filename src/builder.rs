@@ -0,0 +1,247 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Writing new ZFS archives from scratch.
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+use std::time::SystemTime;
+use timestamp;
+use {ZfsHeader, RECORD_FIELDS_SIZE};
+
+struct BuilderEntry
+{
+    name : String,
+    data : Vec<u8>,
+    timestamp : SystemTime,
+    flags : u32,
+}
+
+/// Builds a ZFS archive in memory, to be written out in one go.
+///
+/// The archive is laid out as the game's own are: each file table is
+/// followed by the data of the entries in it. By default, entries with
+/// identical contents share a single copy of their data.
+pub struct ZfsBuilder
+{
+    version : u32,
+    max_filename_len : u32,
+    files_per_table : u32,
+    dedup : bool,
+    entries : Vec<BuilderEntry>,
+}
+
+impl Default for ZfsBuilder
+{
+    fn default() -> ZfsBuilder {
+        ZfsBuilder { version : 1, max_filename_len : 16, files_per_table : 100, dedup : true, entries : Vec::new() }
+    }
+}
+
+impl ZfsBuilder
+{
+    /// Creates a builder with the layout used by the game's archives.
+    pub fn new() -> ZfsBuilder {
+        ZfsBuilder::default()
+    }
+
+    /// Creates a builder with a different layout, e.g. to match an
+    /// existing archive.
+    pub fn with_layout(version : u32, max_filename_len : u32, files_per_table : u32) -> io::Result<ZfsBuilder> {
+        if max_filename_len == 0 || files_per_table == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "ZFS file name length and table size must not be zero"));
+        }
+        Ok(ZfsBuilder { version, max_filename_len, files_per_table, dedup : true, entries : Vec::new() })
+    }
+
+    /// Sets whether entries with identical contents share their data.
+    pub fn set_dedup(&mut self, dedup : bool) {
+        self.dedup = dedup;
+    }
+
+    /// The longest entry name the archive can hold, in bytes.
+    pub fn max_filename_len(&self) -> u32 {
+        self.max_filename_len
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Adds an entry. Names must fit in the archive's name field, and can't
+    /// be empty or contain NUL bytes.
+    pub fn add(&mut self, name : &str, data : Vec<u8>, timestamp : SystemTime) -> io::Result<()> {
+        self.add_with_flags(name, data, timestamp, 0)
+    }
+
+    /// Adds an entry with the given record flags.
+    pub fn add_with_flags(&mut self, name : &str, data : Vec<u8>, timestamp : SystemTime, flags : u32) -> io::Result<()> {
+        if name.is_empty() || name.contains('\0') {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("\"{}\" can't be used as an entry name", name)));
+        }
+        if name.len() > self.max_filename_len as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("Entry name \"{}\" is longer than the archive's limit of {} bytes", name, self.max_filename_len)));
+        }
+        self.entries.push(BuilderEntry { name : name.to_string(), data, timestamp, flags });
+        Ok(())
+    }
+
+    /// How many entries' data would be shared with an earlier entry's,
+    /// rather than written again.
+    pub fn duplicates(&self) -> usize {
+        if !self.dedup {
+            return 0;
+        }
+        let mut seen = HashMap::new();
+        self.entries.iter().filter(|entry| !entry.data.is_empty() && seen.insert(&entry.data[..], ()).is_some()).count()
+    }
+
+    /// Writes the archive out.
+    pub fn write(&self, writer : &mut impl Write) -> io::Result<()> {
+        let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "ZFS archive would be too large");
+        let record_size = (self.max_filename_len + RECORD_FIELDS_SIZE) as u64;
+        let table_size = 4 + record_size * self.files_per_table as u64;
+        let tables : Vec<&[BuilderEntry]> = self.entries.chunks(self.files_per_table as usize).collect();
+
+        // Work out where each table and entry will go. Duplicate entries
+        // point at the first copy of their data, and aren't written again.
+        let mut table_offsets = Vec::with_capacity(tables.len());
+        let mut data_offsets = Vec::with_capacity(self.entries.len());
+        let mut written = Vec::with_capacity(self.entries.len());
+        let mut first_copies : HashMap<&[u8], u64> = HashMap::new();
+        let mut offset = 28u64;
+        for table in &tables {
+            table_offsets.push(offset);
+            offset += table_size;
+            for entry in table.iter() {
+                match first_copies.get(&entry.data[..]) {
+                    Some(&first_offset) if self.dedup && !entry.data.is_empty() => {
+                        data_offsets.push(first_offset);
+                        written.push(false);
+                    },
+                    _ => {
+                        first_copies.entry(&entry.data[..]).or_insert(offset);
+                        data_offsets.push(offset);
+                        written.push(true);
+                        offset += entry.data.len() as u64;
+                    },
+                }
+            }
+        }
+        if offset > u32::MAX as u64 {
+            return Err(too_large());
+        }
+        // Even an empty archive has one (empty) table.
+        if tables.is_empty() {
+            table_offsets.push(offset);
+        }
+
+        ZfsHeader {
+            version : self.version,
+            max_filename_len : self.max_filename_len,
+            files_per_table : self.files_per_table,
+            // Every entry has a record, so if the offsets fit, so does this.
+            num_files : self.entries.len() as u32,
+            unknown : 0,
+            filetable_offset : table_offsets[0] as u32,
+            record_fields_size : RECORD_FIELDS_SIZE,
+        }.write(writer)?;
+
+        let mut index = 0;
+        for (table_num, table) in tables.iter().enumerate() {
+            let next_table = table_offsets.get(table_num + 1).cloned().unwrap_or(0);
+            writer.write_u32::<LittleEndian>(next_table as u32)?;
+            for (i, entry) in table.iter().enumerate() {
+                let mut name = entry.name.as_bytes().to_vec();
+                name.resize(self.max_filename_len as usize, 0);
+                writer.write_all(&name)?;
+                writer.write_u32::<LittleEndian>(data_offsets[index + i] as u32)?;
+                writer.write_u32::<LittleEndian>(0)?;
+                writer.write_u32::<LittleEndian>(entry.data.len() as u32)?;
+                writer.write_u32::<LittleEndian>(timestamp::to_zfs(entry.timestamp))?;
+                writer.write_u32::<LittleEndian>(entry.flags)?;
+            }
+            // Unused records are zeroed, which also marks the end of the table.
+            let unused = (self.files_per_table as usize - table.len()) as u64 * record_size;
+            writer.write_all(&vec![0; unused as usize])?;
+            for (i, entry) in table.iter().enumerate() {
+                if written[index + i] {
+                    writer.write_all(&entry.data)?;
+                }
+            }
+            index += table.len();
+        }
+        if tables.is_empty() {
+            writer.write_u32::<LittleEndian>(0)?;
+            writer.write_all(&vec![0; (table_size - 4) as usize])?;
+        }
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+    use ZfsFile;
+
+    #[test]
+    fn built_archives_read_back() {
+        // Small tables, so that the archive needs several of them.
+        let mut builder = ZfsBuilder::with_layout(1, 16, 2).unwrap();
+        let timestamp = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        builder.add("readme.txt", b"hello world\n".to_vec(), timestamp).unwrap();
+        builder.add_with_flags("up01.rim", b"RIMF not really".to_vec(), timestamp, 4).unwrap();
+        builder.add("copy.txt", b"hello world\n".to_vec(), timestamp).unwrap();
+        builder.add("empty", Vec::new(), timestamp).unwrap();
+        builder.add("sounds\\snd.wav", vec![7; 1000], timestamp).unwrap();
+        assert_eq!(builder.duplicates(), 1);
+
+        let mut archive = Vec::new();
+        builder.write(&mut archive).unwrap();
+        let mut reader = io::Cursor::new(archive);
+        let zfs = ZfsFile::from_stream(&mut reader).unwrap();
+
+        let names : Vec<&str> = zfs.files.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["readme.txt", "up01.rim", "copy.txt", "empty", "sounds\\snd.wav"]);
+        let expected : [&[u8]; 5] = [b"hello world\n", b"RIMF not really", b"hello world\n", b"", &[7; 1000]];
+        for (entry, expected) in zfs.files.iter().zip(expected) {
+            assert_eq!(entry.read_data(&mut reader).unwrap(), expected);
+            assert_eq!(entry.timestamp, timestamp);
+        }
+        assert_eq!(zfs.files[1].flags.raw(), 4);
+        // The duplicate shares its data, rather than having a copy.
+        assert_eq!(zfs.files[0].offset, zfs.files[2].offset);
+    }
+
+    #[test]
+    fn long_names_are_refused() {
+        let mut builder = ZfsBuilder::with_layout(1, 8, 100).unwrap();
+        assert!(builder.add("12345678", Vec::new(), UNIX_EPOCH).is_ok());
+        assert!(builder.add("123456789", Vec::new(), UNIX_EPOCH).is_err());
+        assert!(builder.add("", Vec::new(), UNIX_EPOCH).is_err());
+    }
+}
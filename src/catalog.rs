@@ -0,0 +1,157 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! A catalog of every entry in an install, saved so that searches (like
+//! finding similar images) don't have to open and decode every archive.
+//!
+//! The catalog is a tab-separated text file. After a "# unciv catalog 2"
+//! line and a line of column names, there is one line per entry, giving its
+//! archive, index, name, size, type, (for images) width, height, and
+//! perceptual hash in hex, and the CRC-32 of its data in hex.
+//!
+//! Image columns are empty for entries which aren't images, or if the
+//! catalog was built without hashes, and the CRC-32 is empty if it was built
+//! without checksums. Version 1 catalogs, which have no CRC-32 column, can
+//! still be read. Loose files have an empty archive
+//! column, and their path as the name. Tabs, newlines, and backslashes in
+//! names are escaped with backslashes.
+
+use kind::EntryKind;
+use std::io;
+use std::io::{BufRead, Write};
+
+const CATALOG_HEADER : &str = "# unciv catalog 2";
+const CATALOG_HEADER_V1 : &str = "# unciv catalog 1";
+const CATALOG_COLUMNS : &str = "archive\tindex\tname\tsize\ttype\twidth\theight\tdhash\tcrc32";
+
+/// The size and perceptual hash (see `phash::dhash()`) of an image entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageInfo
+{
+    pub width : u16,
+    pub height : u16,
+    pub dhash : u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CatalogEntry
+{
+    /// The archive's path, relative to the install, or empty for a loose file.
+    pub archive : String,
+    pub index : usize,
+    pub name : String,
+    pub size : usize,
+    pub kind : EntryKind,
+    pub image : Option<ImageInfo>,
+    /// The CRC-32 of the entry's data, for checking it hasn't been damaged.
+    pub crc32 : Option<u32>,
+}
+
+impl CatalogEntry
+{
+    /// Where the entry is, as "archive:name" (or just the path of a loose file).
+    pub fn location(&self) -> String {
+        if self.archive.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{}:{}", self.archive, self.name)
+        }
+    }
+}
+
+pub(crate) fn escape(text : &str) -> String {
+    text.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+pub(crate) fn unescape(text : &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('t') => out.push('\t'),
+                Some('n') => out.push('\n'),
+                Some(other) => out.push(other),
+                None => out.push('\\'),
+            },
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+pub fn write_catalog(writer : &mut impl Write, entries : &[CatalogEntry]) -> io::Result<()> {
+    writeln!(writer, "{}", CATALOG_HEADER)?;
+    writeln!(writer, "{}", CATALOG_COLUMNS)?;
+    for entry in entries {
+        write!(writer, "{}\t{}\t{}\t{}\t{}", escape(&entry.archive), entry.index, escape(&entry.name), entry.size, entry.kind)?;
+        match entry.image {
+            Some(image) => write!(writer, "\t{}\t{}\t{:016x}", image.width, image.height, image.dhash)?,
+            None => write!(writer, "\t\t\t")?,
+        }
+        match entry.crc32 {
+            Some(crc32) => writeln!(writer, "\t{:08x}", crc32)?,
+            None => writeln!(writer, "\t")?,
+        }
+    }
+    Ok(())
+}
+
+pub fn read_catalog(reader : impl BufRead) -> io::Result<Vec<CatalogEntry>> {
+    let mut entries = Vec::new();
+    let mut columns = 9;
+    for (line_num, line) in reader.lines().enumerate() {
+        let line = line?;
+        let invalid = |message : &str| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", line_num + 1, message));
+        match line_num {
+            0 if line == CATALOG_HEADER_V1 => columns = 8,
+            0 if line != CATALOG_HEADER => return Err(invalid("not an unciv catalog (or from a newer version)")),
+            _ => {},
+        }
+        if line_num < 2 {
+            continue;
+        }
+        let fields : Vec<&str> = line.split('\t').collect();
+        if fields.len() != columns {
+            return Err(invalid(&format!("expected {} columns", columns)));
+        }
+        let crc32 = match fields.get(8) {
+            Some(crc32) if !crc32.is_empty() => Some(u32::from_str_radix(crc32, 16).map_err(|_| invalid("bad CRC-32"))?),
+            _ => None,
+        };
+        let image = if fields[5].is_empty() {
+            None
+        } else {
+            Some(ImageInfo {
+                width : fields[5].parse().map_err(|_| invalid("bad width"))?,
+                height : fields[6].parse().map_err(|_| invalid("bad height"))?,
+                dhash : u64::from_str_radix(fields[7], 16).map_err(|_| invalid("bad hash"))?,
+            })
+        };
+        entries.push(CatalogEntry {
+            archive : unescape(fields[0]),
+            index : fields[1].parse().map_err(|_| invalid("bad index"))?,
+            name : unescape(fields[2]),
+            size : fields[3].parse().map_err(|_| invalid("bad size"))?,
+            kind : fields[4].parse().map_err(|_| invalid("bad type"))?,
+            image,
+            crc32,
+        });
+    }
+    Ok(entries)
+}
@@ -0,0 +1,97 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Packing numbered sequences of images into sprite sheets.
+
+use cli::{usage_error, Args};
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use unciv::atlas::{group_frames, Atlas};
+use unciv::encode::write_png;
+use unciv::glob::glob_match;
+use unciv::{ColorKey, EntryKind, RimImage, ZfsArchive};
+
+pub fn run(args : &[String]) -> io::Result<()> {
+    let mut path = None;
+    let mut out_dir = PathBuf::from(".");
+    let mut color_key = ColorKey::None;
+    let mut padding = 1;
+    let mut min_frames = 2;
+    let mut patterns = Vec::new();
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output-dir" => out_dir = PathBuf::from(args.value(&arg)?),
+            "--color-key" => color_key = args.value(&arg)?.parse()?,
+            "--padding" => padding = args.value(&arg)?.parse().map_err(|_| usage_error("--padding requires a number of pixels"))?,
+            "--min-frames" => min_frames = args.value(&arg)?.parse().map_err(|_| usage_error("--min-frames requires a number"))?,
+            _ if path.is_none() => path = Some(arg),
+            _ => patterns.push(arg),
+        }
+    }
+    let path = path.ok_or_else(|| usage_error("No archive given"))?;
+    let mut archive = ZfsArchive::open_indexed(&path)?;
+
+    let images : Vec<usize> = (0..archive.len()).filter(|&index| {
+        let name = &archive.entries()[index].name;
+        EntryKind::from_name(name) == EntryKind::Rim && (patterns.is_empty() || patterns.iter().any(|pattern| glob_match(pattern, name)))
+    }).collect();
+    let groups = group_frames(images.iter().map(|&index| archive.entries()[index].name.as_str()));
+
+    std::fs::create_dir_all(&out_dir)?;
+    let archive_stem = Path::new(&path).file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut written = 0;
+    for (base, positions) in &groups {
+        if positions.len() < min_frames {
+            continue;
+        }
+        let mut frames = Vec::with_capacity(positions.len());
+        for &position in positions {
+            let index = images[position];
+            let name = archive.entries()[index].name.clone();
+            match RimImage::from_stream(&mut archive.reader(index)?) {
+                Ok(image) => frames.push((name, image)),
+                Err(err) => eprintln!("unciv: skipping \"{}\": {}", name, err),
+            }
+        }
+        if frames.is_empty() {
+            continue;
+        }
+        let atlas = Atlas::pack(&frames, color_key, padding)?;
+
+        // Names like "up01" leave a base of "up", but "01" leaves nothing.
+        let atlas_name = match base.trim_end_matches(['_', '-', ' ']) {
+            "" => archive_stem.clone(),
+            trimmed => trimmed.to_string(),
+        };
+        let png_name = format!("{}.png", atlas_name);
+        println!("Writing {} frames of \"{}\" to \"{}\" ({}×{})…",
+                 atlas.frames.len(), base, out_dir.join(&png_name).display(), atlas.width, atlas.height);
+        let mut writer = io::BufWriter::new(File::create(out_dir.join(&png_name))?);
+        write_png(&mut writer, atlas.width, atlas.height, &atlas.rgba)?;
+        writer.flush()?;
+        std::fs::write(out_dir.join(format!("{}.json", atlas_name)), atlas.to_json(&png_name).to_pretty_string())?;
+        written += 1;
+    }
+    if written == 0 {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("No sequences of at least {} numbered images found", min_frames)));
+    }
+    Ok(())
+}
@@ -0,0 +1,148 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Building a catalog of everything in an install.
+
+use cli::{find_files, has_extension, unexpected_argument, usage_error, Args};
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use unciv::catalog::{write_catalog, CatalogEntry, ImageInfo};
+use unciv::phash::dhash;
+use unciv::{Crc32Reader, EntryKind, OpenMode, RimImage, ZfsArchive};
+
+/// Decodes and hashes an image, or returns None if it can't be decoded.
+pub fn image_info(image : &RimImage) -> io::Result<Option<ImageInfo>> {
+    if image.width == 0 || image.height == 0 {
+        return Ok(None);
+    }
+    Ok(Some(ImageInfo {
+        width : image.width,
+        height : image.height,
+        dhash : dhash(image.width as u32, image.height as u32, &image.to_rgba_bytes()?)?,
+    }))
+}
+
+/// Works out the CRC-32 of everything `reader` reads.
+fn crc32_of(reader : impl Read) -> io::Result<u32> {
+    let mut reader = Crc32Reader::new(reader);
+    io::copy(&mut reader, &mut io::sink())?;
+    Ok(reader.crc32())
+}
+
+fn catalog_archive(install_dir : &Path, path : &Path, hashes : bool, checksums : bool, catalog : &mut Vec<CatalogEntry>) -> io::Result<()> {
+    let mut archive = ZfsArchive::new(io::BufReader::new(OpenMode::ReadOnly.open(path)?))?;
+    let display_path = path.strip_prefix(install_dir).unwrap_or(path);
+    for index in 0..archive.len() {
+        let mut header = Vec::with_capacity(16);
+        archive.reader(index)?.take(16).read_to_end(&mut header)?;
+        let entry = &archive.entries()[index];
+        let kind = EntryKind::detect(&entry.name, &header);
+        let (name, size) = (entry.name.clone(), entry.size);
+        // Images which can't be decoded are still listed, just without a
+        // hash: self-check is for finding those.
+        let image = match (hashes, kind) {
+            (true, EntryKind::Rim) => match RimImage::from_stream(&mut archive.reader(index)?) {
+                Ok(image) => image_info(&image)?,
+                Err(_) => None,
+            },
+            _ => None,
+        };
+        let crc32 = match checksums {
+            true => Some(crc32_of(archive.reader(index)?)?),
+            false => None,
+        };
+        catalog.push(CatalogEntry {
+            archive : display_path.display().to_string(),
+            index,
+            name,
+            size,
+            kind,
+            image,
+            crc32,
+        });
+    }
+    Ok(())
+}
+
+/// Lists every entry of every archive in an install, and every loose RIM,
+/// optionally with image sizes and hashes, and CRC-32s.
+pub fn build_catalog(install_dir : &Path, hashes : bool, checksums : bool) -> io::Result<Vec<CatalogEntry>> {
+    let mut catalog = Vec::new();
+    for path in find_files(install_dir, &|path| has_extension(path, "zfs"))? {
+        // Keep going through the rest of the install.
+        if let Err(err) = catalog_archive(install_dir, &path, hashes, checksums, &mut catalog) {
+            eprintln!("unciv: {}: {}", path.display(), err);
+        }
+    }
+    for path in find_files(install_dir, &|path| has_extension(path, "rim"))? {
+        let image = match hashes {
+            true => match RimImage::from_stream(&mut io::BufReader::new(File::open(&path)?)) {
+                Ok(image) => image_info(&image)?,
+                Err(_) => None,
+            },
+            false => None,
+        };
+        let crc32 = match checksums {
+            true => Some(crc32_of(File::open(&path)?)?),
+            false => None,
+        };
+        catalog.push(CatalogEntry {
+            archive : String::new(),
+            index : 0,
+            name : path.strip_prefix(install_dir).unwrap_or(&path).display().to_string(),
+            size : path.metadata()?.len() as usize,
+            kind : EntryKind::Rim,
+            image,
+            crc32,
+        });
+    }
+    Ok(catalog)
+}
+
+pub fn run(args : &[String]) -> io::Result<()> {
+    let mut install_dir = None;
+    let mut output_path = PathBuf::from("unciv-catalog.tsv");
+    let mut hashes = false;
+    let mut checksums = false;
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => output_path = PathBuf::from(args.value(&arg)?),
+            "--hashes" => hashes = true,
+            "--checksums" => checksums = true,
+            _ if install_dir.is_none() => install_dir = Some(PathBuf::from(arg)),
+            _ => return Err(unexpected_argument(&arg)),
+        }
+    }
+    let install_dir = install_dir.ok_or_else(|| usage_error("No install directory given"))?;
+
+    let catalog = build_catalog(&install_dir, hashes, checksums)?;
+    let mut writer = io::BufWriter::new(File::create(&output_path)?);
+    write_catalog(&mut writer, &catalog)?;
+    writer.flush()?;
+
+    let images = catalog.iter().filter(|entry| entry.image.is_some()).count();
+    if hashes {
+        println!("Catalogued {} entries ({} hashed images) to {}", catalog.len(), images, output_path.display());
+    } else {
+        println!("Catalogued {} entries to {}", catalog.len(), output_path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,107 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Extracting just what a patch changed.
+
+use cli::{find_files, follow_job, has_extension, load_config, unexpected_argument, usage_error, Args, Verbosity};
+use std::collections::HashSet;
+use std::io;
+use std::path::PathBuf;
+use unciv::diff::{diff_archives, ChangeKind, EntryChange};
+use unciv::json::JsonValue;
+use unciv::{ExtractOptions, OpenMode, ZfsArchive};
+
+fn change_json(change : &EntryChange) -> JsonValue {
+    JsonValue::object(vec![
+        ("name", change.name.as_str().into()),
+        ("change", change.kind.name().into()),
+        ("old_size", change.old_size.into()),
+        ("new_size", change.new_size.into()),
+    ])
+}
+
+pub fn run(args : &[String]) -> io::Result<()> {
+    let mut base_dir = None;
+    let mut patch_dir = None;
+    let mut out_dir = None;
+    let mut config_path = None;
+    let mut options = ExtractOptions::default();
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output-dir" => out_dir = Some(PathBuf::from(args.value(&arg)?)),
+            "--config" => config_path = Some(PathBuf::from(args.value(&arg)?)),
+            "--format" | "--image-format" => options.image_format = args.value(&arg)?.parse()?,
+            _ if base_dir.is_none() => base_dir = Some(PathBuf::from(arg)),
+            _ if patch_dir.is_none() => patch_dir = Some(PathBuf::from(arg)),
+            _ => return Err(unexpected_argument(&arg)),
+        }
+    }
+    let (base_dir, patch_dir, out_dir) = match (base_dir, patch_dir, out_dir) {
+        (Some(base_dir), Some(patch_dir), Some(out_dir)) => (base_dir, patch_dir, out_dir),
+        _ => return Err(usage_error("A base install, a patch, and an output directory (-o) are needed")),
+    };
+    options.config = load_config(config_path)?;
+    let (job_progress, progress_thread) = follow_job(false, false, Verbosity::Normal);
+    options.progress = job_progress;
+
+    // Archives in the patch replace the ones at the same place in the install.
+    let patch_archives = find_files(&patch_dir, &|path| has_extension(path, "zfs"))?;
+    let mut archives_json = Vec::new();
+    let mut failed = 0;
+    for patch_path in &patch_archives {
+        let relative = patch_path.strip_prefix(&patch_dir).unwrap_or(patch_path);
+        let base_path = base_dir.join(relative);
+        let mut patched = ZfsArchive::new(io::BufReader::new(OpenMode::ReadOnly.open(patch_path)?))?;
+        let changes = if base_path.is_file() {
+            let mut base = ZfsArchive::new(io::BufReader::new(OpenMode::ReadOnly.open(&base_path)?))?;
+            diff_archives(&mut base, &mut patched)?
+        } else {
+            // A new archive: everything in it is new.
+            patched.entries().iter().enumerate().map(|(index, entry)| EntryChange {
+                name : entry.name.clone(),
+                kind : ChangeKind::Added,
+                new_index : Some(index),
+                old_size : None,
+                new_size : Some(entry.size),
+            }).collect()
+        };
+        println!("{}: {} entries changed", relative.display(), changes.len());
+
+        options.output_dir = out_dir.join(relative);
+        let changed : HashSet<usize> = changes.iter().filter_map(|change| change.new_index).collect();
+        let report = patched.extract_where(&options, |index| changed.contains(&index));
+        failed += report.stats.failed;
+
+        archives_json.push(JsonValue::object(vec![
+            ("archive", relative.to_string_lossy().into_owned().into()),
+            ("new_archive", (!base_path.is_file()).into()),
+            ("changes", JsonValue::Array(changes.iter().map(change_json).collect())),
+        ]));
+    }
+    drop(options);
+    let _ = progress_thread.join();
+
+    std::fs::create_dir_all(&out_dir)?;
+    let summary = JsonValue::object(vec![("archives", JsonValue::Array(archives_json))]);
+    std::fs::write(out_dir.join("changes.json"), summary.to_pretty_string())?;
+    if failed > 0 {
+        return Err(io::Error::other(format!("{} entries could not be extracted", failed)));
+    }
+    Ok(())
+}
@@ -0,0 +1,183 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! The self-check command, which runs every parser we have over a whole
+//! install of the game, to find anything we can't yet handle.
+
+use cli::{find_files, has_extension, unexpected_argument, usage_error, Args};
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use unciv::{EntryKind, OpenMode, RimImage, ZfsArchive};
+
+struct Failure
+{
+    /// The archive (or loose file) the problem was found in.
+    path : PathBuf,
+    /// The entry within the archive, if the problem was with one.
+    entry : Option<String>,
+    parser : &'static str,
+    reason : String,
+}
+
+/// Runs the parsers applicable to one file's data, returning the name of
+/// the parser and the error for each one that fails.
+fn check_data(name : &str, data : &[u8], exhaustive : bool) -> Vec<(&'static str, String)> {
+    let mut failures = Vec::new();
+    if EntryKind::detect(name, data) == EntryKind::Rim {
+        match RimImage::from_stream(&mut &data[..]) {
+            Ok(image) => if exhaustive {
+                if let Err(err) = image.write_png(io::sink()) {
+                    failures.push(("png", err.to_string()));
+                }
+            },
+            Err(err) => failures.push(("rim", err.to_string())),
+        }
+    }
+    failures
+}
+
+fn check_archive(path : &Path, exhaustive : bool, failures : &mut Vec<Failure>) -> usize {
+    let mut fail = |entry : Option<&str>, parser, reason : String| failures.push(Failure {
+        path : path.to_path_buf(),
+        entry : entry.map(|name| name.to_string()),
+        parser,
+        reason,
+    });
+
+    let file = match OpenMode::ReadOnly.open(path) {
+        Ok(file) => file,
+        Err(err) => {
+            fail(None, "open", err.to_string());
+            return 0;
+        },
+    };
+    let archive_size = file.metadata().map(|m| m.len()).unwrap_or(0);
+    let mut archive = match ZfsArchive::new(io::BufReader::new(file)) {
+        Ok(archive) => archive,
+        Err(err) => {
+            fail(None, "zfs", err.to_string());
+            return 0;
+        },
+    };
+
+    for index in 0..archive.len() {
+        let (name, end) = {
+            let entry = &archive.entries()[index];
+            (entry.name.clone(), entry.offset as u64 + entry.size as u64)
+        };
+        if end > archive_size {
+            fail(Some(&name), "zfs", format!("Entry ends at {}, past the end of the archive ({})", end, archive_size));
+            continue;
+        }
+        // Without --exhaustive, only look at enough of each entry to check its header.
+        let data = if exhaustive {
+            archive.read_data(index)
+        } else {
+            let mut header = Vec::new();
+            archive.reader(index).and_then(|reader| reader.take(16).read_to_end(&mut header)).map(|_| header)
+        };
+        match data {
+            Ok(data) => {
+                let header_only = !exhaustive && EntryKind::detect(&name, &data) == EntryKind::Rim;
+                if header_only {
+                    // A RIM's header is all we need to know its full size.
+                    if let Err(err) = check_rim_header(&data, archive.entries()[index].size) {
+                        fail(Some(&name), "rim", err);
+                    }
+                } else {
+                    for (parser, reason) in check_data(&name, &data, exhaustive) {
+                        fail(Some(&name), parser, reason);
+                    }
+                }
+            },
+            Err(err) => fail(Some(&name), "read", err.to_string()),
+        }
+    }
+    archive.len()
+}
+
+/// Checks that a RIM header is valid, and that the entry is big enough for
+/// the image it describes.
+fn check_rim_header(header : &[u8], entry_size : usize) -> Result<(), String> {
+    if header.len() < 16 {
+        return Err("RIM header is truncated".to_string());
+    }
+    let field = |offset : usize| u16::from_le_bytes([header[offset], header[offset + 1]]) as usize;
+    let (width, height, pitch, format) = (field(8), field(10), field(12), field(14));
+    if format > 1 {
+        return Err(format!("Unknown RIM pixel format {}", format));
+    }
+    if pitch < width * 2 {
+        return Err(format!("RIM pitch {} is too small for width {}", pitch, width));
+    }
+    if 16 + pitch * height > entry_size {
+        return Err(format!("RIM image needs {} bytes, but the entry is only {}", 16 + pitch * height, entry_size));
+    }
+    Ok(())
+}
+
+pub fn run(args : &[String]) -> io::Result<()> {
+    let mut install_dir = None;
+    let mut exhaustive = false;
+    for arg in Args::new(args) {
+        match arg.as_str() {
+            "--exhaustive" => exhaustive = true,
+            _ if install_dir.is_none() => install_dir = Some(PathBuf::from(arg)),
+            _ => return Err(unexpected_argument(&arg)),
+        }
+    }
+    let install_dir = match install_dir {
+        Some(dir) => dir,
+        None => return Err(usage_error("No install directory given")),
+    };
+
+    let archives = find_files(&install_dir, &|path| has_extension(path, "zfs"))?;
+    let loose_files = find_files(&install_dir, &|path| has_extension(path, "rim"))?;
+
+    let mut failures = Vec::new();
+    let mut entries_checked = 0;
+    for path in &archives {
+        println!("Checking \"{}\"…", path.display());
+        entries_checked += check_archive(path, exhaustive, &mut failures);
+    }
+    for path in &loose_files {
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        match std::fs::read(path) {
+            Ok(data) => for (parser, reason) in check_data(&name, &data, exhaustive) {
+                failures.push(Failure { path : path.clone(), entry : None, parser, reason });
+            },
+            Err(err) => failures.push(Failure { path : path.clone(), entry : None, parser : "open", reason : err.to_string() }),
+        }
+    }
+
+    for failure in &failures {
+        match failure.entry {
+            Some(ref entry) => println!("FAIL {} [{}] ({}): {}", failure.path.display(), entry, failure.parser, failure.reason),
+            None => println!("FAIL {} ({}): {}", failure.path.display(), failure.parser, failure.reason),
+        }
+    }
+    println!("Checked {} archives ({} entries) and {} loose files{}: {} problems found.",
+             archives.len(), entries_checked, loose_files.len(),
+             if exhaustive { " exhaustively" } else { "" }, failures.len());
+
+    if !failures.is_empty() {
+        return Err(io::Error::other(format!("{} problems found", failures.len())));
+    }
+    Ok(())
+}
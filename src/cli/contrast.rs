@@ -0,0 +1,116 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Reporting UI text which is hard to read against its background.
+
+use cli::{find_files, has_extension, unexpected_argument, usage_error, Args};
+use std::collections::BTreeSet;
+use std::io;
+use std::path::PathBuf;
+use unciv::contrast::{contrast_ratio, mean_luminance, relative_luminance, text_elements};
+use unciv::ldl::LdlFile;
+use unciv::recolor::parse_color;
+use unciv::{AssetVfs, ColorKey, OpenMode, RimImage, ZfsArchive};
+
+pub fn run(args : &[String]) -> io::Result<()> {
+    let mut path = None;
+    let mut default_color = [0, 0, 0];
+    let mut min_ratio = 4.5;
+    let mut color_key = ColorKey::Magenta;
+    let mut trace_path = None;
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--text-color" => default_color = parse_color(&args.value(&arg)?).map_err(|err| usage_error(err.to_string()))?,
+            "--min-ratio" => min_ratio = match args.value(&arg)?.parse::<f64>() {
+                Ok(ratio) if ratio >= 1.0 => ratio,
+                _ => return Err(usage_error("--min-ratio requires a number of at least 1")),
+            },
+            "--color-key" => color_key = args.value(&arg)?.parse()?,
+            "--trace" => trace_path = Some(PathBuf::from(args.value(&arg)?)),
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => return Err(unexpected_argument(&arg)),
+        }
+    }
+    let path = path.ok_or_else(|| usage_error("No archive or install directory given"))?;
+
+    // Look images up the way the game would, so patched versions are used.
+    let archives = if path.is_dir() {
+        find_files(&path, &|path| has_extension(path, "zfs"))?
+    } else {
+        vec![path.clone()]
+    };
+    let mut vfs = AssetVfs::new();
+    let mut layouts = BTreeSet::new();
+    for archive_path in &archives {
+        let archive = match ZfsArchive::open(archive_path, OpenMode::ReadOnly) {
+            Ok(archive) => archive,
+            Err(err) => {
+                eprintln!("unciv: {}: {}", archive_path.display(), err);
+                continue;
+            },
+        };
+        layouts.extend(archive.entries().iter().map(|entry| entry.name.clone()).filter(|name| name.to_ascii_lowercase().ends_with(".ldl")));
+        vfs.add_archive(archive_path)?;
+    }
+    if path.is_dir() {
+        vfs.add_directory(&path)?;
+    }
+
+    if trace_path.is_some() {
+        vfs.start_recording();
+    }
+
+    println!("{:>6}  {:<6}  {:<30}  Image", "Ratio", "Status", "Element");
+    let (mut checked, mut low) = (0, 0);
+    for layout in &layouts {
+        let text = String::from_utf8_lossy(&vfs.read(layout)?).into_owned();
+        let file = match LdlFile::parse(&text) {
+            Ok(file) => file,
+            Err(err) => {
+                eprintln!("unciv: {}: {}", layout, err);
+                continue;
+            },
+        };
+        for element in text_elements(&file) {
+            let text_luminance = relative_luminance(element.text_color.unwrap_or(default_color));
+            for image_name in &element.images {
+                let background = match vfs.read(image_name).and_then(|data| RimImage::from_stream(&mut &data[..])) {
+                    Ok(image) => mean_luminance(&image, color_key),
+                    Err(err) => {
+                        eprintln!("unciv: {}: {}: {}", layout, image_name, err);
+                        continue;
+                    },
+                };
+                let Some(background) = background else { continue };
+                let ratio = contrast_ratio(text_luminance, background);
+                let status = if ratio < min_ratio { "LOW" } else { "ok" };
+                println!("{:>6.2}  {:<6}  {:<30}  {}", ratio, status, format!("{}:{}", layout, element.path), image_name);
+                checked += 1;
+                if ratio < min_ratio {
+                    low += 1;
+                }
+            }
+        }
+    }
+    println!("{} of {} text elements have a contrast ratio below {}.", low, checked, min_ratio);
+    if let (Some(trace_path), Some(trace)) = (trace_path, vfs.stop_recording()) {
+        std::fs::write(trace_path, trace.to_json().to_pretty_string())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,319 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Commands for converting loose image files.
+
+use cli::{find_files, follow_job, has_extension, load_config, unexpected_argument, usage_error, Args, Verbosity};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use unciv::job::{convert_rim_file, ConvertJob, Job};
+use unciv::{ColorKey, EntryKind, ExtractOptions, ForcedType, ImageFormat, RimFormat, RimImage};
+
+fn is_rim_file(path : &Path) -> bool {
+    has_extension(path, "rim")
+}
+
+pub fn convert_dir(args : &[String]) -> io::Result<()> {
+    let mut in_dir = None;
+    let mut out_dir = None;
+    let mut recursive = false;
+    let mut config_path = None;
+    let mut color_key = ColorKey::None;
+    let mut image_format = ImageFormat::Png;
+    let mut progress = false;
+    let mut verbosity = Verbosity::Normal;
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => config_path = Some(PathBuf::from(args.value(&arg)?)),
+            "--color-key" => color_key = args.value(&arg)?.parse()?,
+            "--format" => image_format = args.value(&arg)?.parse()?,
+            "-o" | "--output-dir" => out_dir = Some(PathBuf::from(args.value(&arg)?)),
+            "-r" | "--recursive" => recursive = true,
+            "--progress" => progress = true,
+            "-q" | "--quiet" => verbosity = Verbosity::Quiet,
+            "-v" | "--verbose" => verbosity = Verbosity::Verbose,
+            _ if in_dir.is_none() => in_dir = Some(PathBuf::from(arg)),
+            _ => return Err(unexpected_argument(&arg)),
+        }
+    }
+    let in_dir = match in_dir {
+        Some(dir) => dir,
+        None => return Err(usage_error("No input directory given")),
+    };
+    let out_dir = out_dir.unwrap_or_else(|| in_dir.clone());
+    let config = load_config(config_path)?;
+
+    let rim_files = if recursive {
+        find_files(&in_dir, &is_rim_file)?
+    } else {
+        let mut paths = Vec::new();
+        for dir_entry in std::fs::read_dir(&in_dir)? {
+            let path = dir_entry?.path();
+            if path.is_file() && is_rim_file(&path) {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        paths
+    };
+    // Each file is converted as if it were an entry named after its path
+    // within the input directory, so the output mirrors its structure, the
+    // same way extraction would.
+    let mut files : Vec<PathBuf> = rim_files.iter().map(|in_path| in_path.strip_prefix(&in_dir).unwrap_or(in_path).to_path_buf()).collect();
+    // Leave alone any files the config says aren't really RIMs.
+    files.retain(|path| match config.forced_type(&path.to_string_lossy()) {
+        None | Some(ForcedType::Kind(EntryKind::Rim)) => true,
+        Some(_) => false,
+    });
+    let total = files.len();
+
+    let options = ExtractOptions { output_dir : out_dir, config, image_format, color_key, ..ExtractOptions::default() };
+    let (job_progress, progress_thread) = follow_job(progress, true, verbosity);
+    let failures = ConvertJob { in_dir, files, options }.run(&job_progress);
+    drop(job_progress);
+    let _ = progress_thread.join();
+    let failures = failures?;
+    for (in_path, err) in &failures {
+        eprintln!("unciv: {}: {}", in_path.display(), err);
+    }
+
+    if verbosity > Verbosity::Quiet {
+        println!("Converted {} of {} RIM files.", total - failures.len(), total);
+    }
+    if !failures.is_empty() {
+        return Err(io::Error::other(format!("{} files could not be converted", failures.len())));
+    }
+    Ok(())
+}
+
+fn is_png_file(path : &Path) -> bool {
+    has_extension(path, "png")
+}
+
+/// Converts a PNG to a RIM, writing it to `out_path`.
+pub fn convert_png_file(in_path : &Path, out_path : &Path, format : RimFormat) -> io::Result<()> {
+    let image = RimImage::from_png(io::BufReader::new(File::open(in_path)?), format)?;
+    println!("Converting PNG image \"{}\" to RIM ({}, {}×{})…", in_path.display(), format.name(), image.width, image.height);
+    if let Some(parent) = out_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut out_file = io::BufWriter::new(File::create(out_path)?);
+    image.to_stream(&mut out_file)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ConvertDirection
+{
+    ToPng,
+    ToRim,
+    Both,
+}
+
+/// Works out where a file dropped into the watched folder should be converted
+/// to, or None if it isn't something we convert.
+///
+/// RIMs become `<name>.rim.png`, as with extraction, and PNGs lose their
+/// `.png` suffix (adding `.rim` if that leaves no RIM extension).
+fn daemon_output_path(path : &Path, out_dir : &Path, direction : ConvertDirection) -> Option<PathBuf> {
+    let file_name = path.file_name()?.to_string_lossy().into_owned();
+    if is_rim_file(path) && direction != ConvertDirection::ToRim {
+        Some(out_dir.join(format!("{}.png", file_name)))
+    } else if is_png_file(path) && direction != ConvertDirection::ToPng {
+        let stem = &file_name[..file_name.len() - 4];
+        if Path::new(stem).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("rim")) {
+            Some(out_dir.join(stem))
+        } else {
+            Some(out_dir.join(format!("{}.rim", stem)))
+        }
+    } else {
+        None
+    }
+}
+
+/// Counters for the conversion daemon, written out in the Prometheus text
+/// format (e.g. for node_exporter's textfile collector).
+#[derive(Default)]
+struct DaemonMetrics
+{
+    polls : u64,
+    /// Indexed by direction (to PNG, then to RIM).
+    converted : [u64; 2],
+    failed : [u64; 2],
+    seconds : [f64; 2],
+    bytes_read : u64,
+    /// Files skipped because their output was already up to date.
+    up_to_date : u64,
+}
+
+impl DaemonMetrics
+{
+    fn record(&mut self, to_rim : bool, bytes_read : u64, elapsed : Duration, ok : bool) {
+        let direction = to_rim as usize;
+        if ok {
+            self.converted[direction] += 1;
+        } else {
+            self.failed[direction] += 1;
+        }
+        self.seconds[direction] += elapsed.as_secs_f64();
+        self.bytes_read += bytes_read;
+    }
+
+    fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP unciv_daemon_polls_total Times the watched folder has been scanned.\n");
+        out.push_str("# TYPE unciv_daemon_polls_total counter\n");
+        out.push_str(&format!("unciv_daemon_polls_total {}\n", self.polls));
+        out.push_str("# HELP unciv_daemon_conversions_total Files converted, by target format and result.\n");
+        out.push_str("# TYPE unciv_daemon_conversions_total counter\n");
+        for (direction, target) in ["png", "rim"].iter().enumerate() {
+            out.push_str(&format!("unciv_daemon_conversions_total{{to=\"{}\",result=\"ok\"}} {}\n", target, self.converted[direction]));
+            out.push_str(&format!("unciv_daemon_conversions_total{{to=\"{}\",result=\"failed\"}} {}\n", target, self.failed[direction]));
+        }
+        out.push_str("# HELP unciv_daemon_conversion_seconds Time spent decoding, converting, and writing files.\n");
+        out.push_str("# TYPE unciv_daemon_conversion_seconds summary\n");
+        for (direction, target) in ["png", "rim"].iter().enumerate() {
+            out.push_str(&format!("unciv_daemon_conversion_seconds_sum{{to=\"{}\"}} {}\n", target, self.seconds[direction]));
+            out.push_str(&format!("unciv_daemon_conversion_seconds_count{{to=\"{}\"}} {}\n", target, self.converted[direction] + self.failed[direction]));
+        }
+        out.push_str("# HELP unciv_daemon_read_bytes_total Bytes of input read by conversions.\n");
+        out.push_str("# TYPE unciv_daemon_read_bytes_total counter\n");
+        out.push_str(&format!("unciv_daemon_read_bytes_total {}\n", self.bytes_read));
+        out.push_str("# HELP unciv_daemon_up_to_date_total Files skipped because their output was already newer.\n");
+        out.push_str("# TYPE unciv_daemon_up_to_date_total counter\n");
+        out.push_str(&format!("unciv_daemon_up_to_date_total {}\n", self.up_to_date));
+        out
+    }
+
+    /// Replaces the metrics file in one go, so nothing ever reads half of it.
+    fn write(&self, path : &Path) -> io::Result<()> {
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        std::fs::write(&temp_path, self.to_prometheus())?;
+        std::fs::rename(&temp_path, path)
+    }
+}
+
+pub fn convert_daemon(args : &[String]) -> io::Result<()> {
+    let mut in_dir = None;
+    let mut out_dir = None;
+    let mut direction = ConvertDirection::Both;
+    let mut rim_format = RimFormat::Rgb565;
+    let mut interval = Duration::from_secs(1);
+    let mut metrics_path = None;
+    let mut config_path = None;
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output-dir" => out_dir = Some(PathBuf::from(args.value(&arg)?)),
+            "--metrics" => metrics_path = Some(PathBuf::from(args.value(&arg)?)),
+            "--config" => config_path = Some(PathBuf::from(args.value(&arg)?)),
+            "--to" => direction = match args.value(&arg)?.as_str() {
+                "png" => ConvertDirection::ToPng,
+                "rim" => ConvertDirection::ToRim,
+                other => return Err(usage_error(format!("Unknown conversion target \"{}\"", other))),
+            },
+            "--rim-format" => rim_format = match args.value(&arg)?.as_str() {
+                "555" => RimFormat::Rgb555,
+                "565" => RimFormat::Rgb565,
+                other => return Err(usage_error(format!("Unknown RIM format \"{}\"", other))),
+            },
+            "--interval" => interval = match args.value(&arg)?.parse::<f64>() {
+                Ok(secs) if secs > 0.0 => Duration::from_secs_f64(secs),
+                _ => return Err(usage_error("--interval requires a positive number of seconds")),
+            },
+            _ if in_dir.is_none() => in_dir = Some(PathBuf::from(arg)),
+            _ => return Err(unexpected_argument(&arg)),
+        }
+    }
+    let in_dir = match in_dir {
+        Some(dir) => dir,
+        None => return Err(usage_error("No folder to watch given")),
+    };
+    let out_dir = out_dir.unwrap_or_else(|| in_dir.clone());
+    let limits = load_config(config_path)?.limits;
+    std::fs::create_dir_all(&out_dir)?;
+
+    println!("Watching \"{}\" for images to convert (press Ctrl+C to stop)…", in_dir.display());
+
+    // The size and modification time each file had on the previous poll: we
+    // only convert a file once these have settled, so that we don't pick up
+    // files which are still being copied in.
+    let mut last_seen = HashMap::<PathBuf, (u64, SystemTime)>::new();
+    // Files we've already handled (including ones we wrote ourselves), so we
+    // don't convert them again, or convert our own output back.
+    let mut handled = HashMap::<PathBuf, SystemTime>::new();
+    let mut metrics = DaemonMetrics::default();
+    loop {
+        let mut seen = HashMap::new();
+        for dir_entry in std::fs::read_dir(&in_dir)? {
+            let path = dir_entry?.path();
+            let metadata = match std::fs::metadata(&path) {
+                Ok(metadata) if metadata.is_file() => metadata,
+                _ => continue,
+            };
+            let state = (metadata.len(), metadata.modified()?);
+            seen.insert(path.clone(), state);
+
+            if last_seen.get(&path) != Some(&state) || handled.get(&path) == Some(&state.1) {
+                continue;
+            }
+            let out_path = match daemon_output_path(&path, &out_dir, direction) {
+                Some(out_path) => out_path,
+                None => continue,
+            };
+            handled.insert(path.clone(), state.1);
+
+            // Don't redo work if the output is already newer than the input.
+            if let Ok(out_modified) = std::fs::metadata(&out_path).and_then(|m| m.modified()) {
+                if out_modified >= state.1 {
+                    metrics.up_to_date += 1;
+                    continue;
+                }
+            }
+
+            let start = std::time::Instant::now();
+            let to_rim = !is_rim_file(&path);
+            let result = if to_rim {
+                convert_png_file(&path, &out_path, rim_format)
+            } else {
+                convert_rim_file(&path, &out_path, ColorKey::None, &limits).map(|message| println!("{}", message))
+            };
+            metrics.record(to_rim, state.0, start.elapsed(), result.is_ok());
+            match result {
+                Ok(()) => {
+                    if let Ok(out_modified) = std::fs::metadata(&out_path).and_then(|m| m.modified()) {
+                        handled.insert(out_path, out_modified);
+                    }
+                },
+                Err(err) => eprintln!("unciv: {}: {}", path.display(), err),
+            }
+        }
+        last_seen = seen;
+        metrics.polls += 1;
+        if let Some(ref metrics_path) = metrics_path {
+            if let Err(err) = metrics.write(metrics_path) {
+                eprintln!("unciv: {}: {}", metrics_path.display(), err);
+            }
+        }
+        std::thread::sleep(interval);
+    }
+}
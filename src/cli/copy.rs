@@ -0,0 +1,92 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Copying images from archives to the system clipboard.
+
+use cli::{unexpected_argument, usage_error, Args};
+use std::io;
+use unciv::{ColorKey, RimImage, ZfsArchive};
+
+/// Puts an image on the clipboard.
+///
+/// On Linux, the clipboard only holds on to its contents for as long as the
+/// program which put them there is running. So unless `wait` is set, this
+/// runs `args` again (with `--wait`) in the background, and leaves that to
+/// keep the image available until something else is copied.
+#[cfg(feature = "clipboard")]
+fn put_on_clipboard(args : &[String], width : u16, height : u16, rgba : Vec<u8>, wait : bool) -> io::Result<()> {
+    let clipboard_error = |err : arboard::Error| io::Error::other(format!("Couldn't use the clipboard: {}", err));
+    let mut clipboard = arboard::Clipboard::new().map_err(clipboard_error)?;
+    let image = arboard::ImageData { width : width as usize, height : height as usize, bytes : rgba.into() };
+
+    #[cfg(target_os = "linux")]
+    {
+        use arboard::SetExtLinux;
+        if wait {
+            return clipboard.set().wait().image(image).map_err(clipboard_error);
+        }
+        std::process::Command::new(std::env::current_exe()?)
+            .arg("copy")
+            .arg("--wait")
+            .args(args)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()?;
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (args, wait);
+        clipboard.set_image(image).map_err(clipboard_error)
+    }
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn put_on_clipboard(_args : &[String], _width : u16, _height : u16, _rgba : Vec<u8>, _wait : bool) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "unciv was built without clipboard support (rebuild it with --features clipboard)"))
+}
+
+pub fn run(command_args : &[String]) -> io::Result<()> {
+    let mut path = None;
+    let mut name = None;
+    let mut color_key = ColorKey::None;
+    let mut wait = false;
+    let mut args = Args::new(command_args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--color-key" => color_key = args.value(&arg)?.parse()?,
+            "--wait" => wait = true,
+            _ if path.is_none() => path = Some(arg),
+            _ if name.is_none() => name = Some(arg),
+            _ => return Err(unexpected_argument(&arg)),
+        }
+    }
+    let (Some(path), Some(name)) = (path, name) else {
+        return Err(usage_error("An archive and the name of an image in it are needed"));
+    };
+
+    let mut archive = ZfsArchive::open_indexed(&path)?;
+    let image = RimImage::from_stream(&mut &archive.read_by_name(&name)?[..])?;
+    let rgba = image.to_rgba_bytes_with(color_key)?;
+    put_on_clipboard(command_args, image.width, image.height, rgba, wait)?;
+    if !wait {
+        println!("Copied \"{}\" ({}×{}) to the clipboard.", name, image.width, image.height);
+    }
+    Ok(())
+}
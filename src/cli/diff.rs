@@ -0,0 +1,108 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Comparing two archives.
+
+use cli::{unexpected_argument, usage_error, Args};
+use std::fs::File;
+use std::io;
+use std::io::{Seek, Write};
+use std::path::PathBuf;
+use unciv::diff::{diff_archives, side_by_side, ChangeKind};
+use unciv::encode::write_png;
+use unciv::{sanitize_entry_name, ColorKey, Crc32Reader, EntryKind, OpenMode, RimImage, ZfsArchive};
+
+fn crc32(data : &[u8]) -> io::Result<u32> {
+    let mut reader = Crc32Reader::new(data);
+    io::copy(&mut reader, &mut io::sink())?;
+    Ok(reader.crc32())
+}
+
+fn open(path : &PathBuf) -> io::Result<ZfsArchive<io::BufReader<File>>> {
+    ZfsArchive::new(io::BufReader::new(OpenMode::ReadOnly.open(path)?))
+}
+
+/// Writes the old and new versions of a changed RIM side by side, returning
+/// false if either can't be decoded.
+fn write_comparison<R : io::Read + Seek>(old : &mut ZfsArchive<R>, new : &mut ZfsArchive<R>, name : &str, out_path : &PathBuf, color_key : ColorKey) -> io::Result<bool> {
+    let old_image = RimImage::from_stream(&mut &old.read_by_name(name)?[..]);
+    let new_image = RimImage::from_stream(&mut &new.read_by_name(name)?[..]);
+    let (old_image, new_image) = match (old_image, new_image) {
+        (Ok(old_image), Ok(new_image)) => (old_image, new_image),
+        _ => return Ok(false),
+    };
+    let (width, height, rgba) = side_by_side(&old_image, &new_image, color_key)?;
+    let mut writer = io::BufWriter::new(File::create(out_path)?);
+    write_png(&mut writer, width, height, &rgba)?;
+    writer.flush()?;
+    Ok(true)
+}
+
+pub fn run(args : &[String]) -> io::Result<()> {
+    let mut paths = Vec::new();
+    let mut images_dir = None;
+    let mut color_key = ColorKey::None;
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--images" => images_dir = Some(PathBuf::from(args.value(&arg)?)),
+            "--color-key" => color_key = args.value(&arg)?.parse()?,
+            _ if paths.len() < 2 => paths.push(PathBuf::from(arg)),
+            _ => return Err(unexpected_argument(&arg)),
+        }
+    }
+    let (old_path, new_path) = match &paths[..] {
+        [old_path, new_path] => (old_path, new_path),
+        _ => return Err(usage_error("Two archives to compare are needed")),
+    };
+    let mut old = open(old_path)?;
+    let mut new = open(new_path)?;
+    let changes = diff_archives(&mut old, &mut new)?;
+    if let Some(ref images_dir) = images_dir {
+        std::fs::create_dir_all(images_dir)?;
+    }
+
+    for change in &changes {
+        match change.kind {
+            ChangeKind::Added => println!("A  {:<24}  {} bytes", change.name, change.new_size.unwrap_or(0)),
+            ChangeKind::Removed => println!("R  {:<24}  {} bytes", change.name, change.old_size.unwrap_or(0)),
+            ChangeKind::Modified => {
+                let old_crc = crc32(&old.read_by_name(&change.name)?)?;
+                let new_crc = crc32(&new.read_by_name(&change.name)?)?;
+                println!("M  {:<24}  {} -> {} bytes, CRC-32 {:08x} -> {:08x}", change.name,
+                         change.old_size.unwrap_or(0), change.new_size.unwrap_or(0), old_crc, new_crc);
+            },
+        }
+        let Some(ref images_dir) = images_dir else { continue };
+        if change.kind != ChangeKind::Modified || EntryKind::from_name(&change.name) != EntryKind::Rim {
+            continue;
+        }
+        // Keep all the comparisons in one folder, whatever the entry names.
+        let Some(file_name) = sanitize_entry_name(&change.name).and_then(|path| path.file_name().map(|name| name.to_owned())) else { continue };
+        let mut out_path = images_dir.join(file_name).into_os_string();
+        out_path.push(".png");
+        let out_path = PathBuf::from(out_path);
+        if !write_comparison(&mut old, &mut new, &change.name, &out_path, color_key)? {
+            eprintln!("unciv: couldn't decode both versions of \"{}\" to compare", change.name);
+        }
+    }
+
+    let count = |kind| changes.iter().filter(|change| change.kind == kind).count();
+    println!("{} added, {} removed, {} modified.", count(ChangeKind::Added), count(ChangeKind::Removed), count(ChangeKind::Modified));
+    Ok(())
+}
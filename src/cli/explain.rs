@@ -0,0 +1,183 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Explaining everything unciv knows about a single entry, for debugging.
+
+use cli::{find_files, format_timestamp, has_extension, load_config, unexpected_argument, usage_error, Args};
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use unciv::{plan_output_paths, AssetSource, AssetVfs, EntryKind, ExtractOptions, ForcedType, Limits, RimFormat, RimImage, ZfsArchive};
+
+/// How many bytes of the entry's data are shown.
+const PREVIEW_BYTES : usize = 32;
+
+/// Prints bytes as a hex dump, 16 to a line, with each line followed by the
+/// bytes as ASCII (with dots for anything unprintable). The first line is
+/// prefixed with `label`, and the rest indented to match.
+fn print_hex_dump(label : &str, bytes : &[u8]) {
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let hex : Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let text : String = chunk.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }).collect();
+        let label = if line == 0 { label } else { "" };
+        println!("  {:<12} {:<47}  |{}|", label, hex.join(" "), text);
+    }
+}
+
+/// Describes a RIM image's header, without needing the rest of it to be
+/// valid, along with any problems decoding it.
+fn explain_rim(data : &[u8], limits : &Limits) {
+    if data.len() < 16 {
+        println!("  RIM header:  truncated ({} bytes)", data.len());
+        return;
+    }
+    let field = |offset : usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+    let version = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+    let (width, height, pitch, raw_format) = (field(8), field(10), field(12), field(14));
+    let format = match RimFormat::from_raw(raw_format) {
+        Ok(format) => format.name().to_string(),
+        Err(_) => format!("unknown format {}", raw_format),
+    };
+    println!("  RIM header:  v{}, {}, {}×{}, pitch {} bytes", version, format, width, height, pitch);
+    let row_bytes = width as usize * 2;
+    if pitch as usize > row_bytes {
+        println!("  Row padding: {} bytes per row", pitch as usize - row_bytes);
+    }
+    let expected = 16 + pitch as usize * height as usize;
+    if expected != data.len() {
+        println!("  Size:        header says {} bytes, entry is {}", expected, data.len());
+    }
+    if let Err(err) = RimImage::from_stream_with_limits(&mut &data[..], limits) {
+        println!("  Decoding:    fails: {}", err);
+    }
+}
+
+/// Lists every copy of `name` in the archives (and loose files) under
+/// `install_dir`, in the order the game looks for them.
+fn explain_copies(install_dir : &Path, archive_path : &Path, name : &str) -> io::Result<()> {
+    let mut vfs = AssetVfs::new();
+    for path in find_files(install_dir, &|path| has_extension(path, "zfs"))? {
+        // Damaged archives don't stop us checking the rest.
+        if let Err(err) = vfs.add_archive(&path) {
+            eprintln!("unciv: {}: {}", path.display(), err);
+        }
+    }
+    vfs.add_directory(install_dir)?;
+
+    let same_file = |path : &Path| match (path.canonicalize(), archive_path.canonicalize()) {
+        (Ok(path), Ok(archive_path)) => path == archive_path,
+        _ => false,
+    };
+    println!("Copies under {} (the first is what the game uses):", install_dir.display());
+    for (position, source) in vfs.resolve_all(name).iter().enumerate() {
+        let description = match source {
+            AssetSource::Archive { path, index } if same_file(path) => format!("{} (entry {}, this one)", path.display(), index),
+            AssetSource::Archive { path, index } => format!("{} (entry {})", path.display(), index),
+            AssetSource::File(path) => format!("{} (loose file)", path.display()),
+        };
+        let status = if position == 0 { "used" } else { "shadowed" };
+        println!("  {:<9} {}", status, description);
+    }
+    Ok(())
+}
+
+pub fn run(args : &[String]) -> io::Result<()> {
+    let mut path = None;
+    let mut name = None;
+    let mut install_dir = None;
+    let mut config_path = None;
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--install" => install_dir = Some(PathBuf::from(args.value(&arg)?)),
+            "--config" => config_path = Some(PathBuf::from(args.value(&arg)?)),
+            _ if path.is_none() => path = Some(arg),
+            _ if name.is_none() => name = Some(arg),
+            _ => return Err(unexpected_argument(&arg)),
+        }
+    }
+    let (Some(path), Some(name)) = (path, name) else {
+        return Err(usage_error("An archive and the name of an entry in it are needed"));
+    };
+    let options = ExtractOptions { config : load_config(config_path)?, ..ExtractOptions::default() };
+
+    let mut archive = ZfsArchive::open_indexed(&path)?;
+    // Fall back to ignoring case, as the game does.
+    let index = archive.index_of(&name)
+        .or_else(|| archive.entries().iter().position(|entry| entry.name.eq_ignore_ascii_case(&name)))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("No entry named \"{}\" in {}", name, path)))?;
+    let entry = archive.entries()[index].clone();
+    let record_size = archive.zfs_file().record_size() as usize;
+
+    println!("\"{}\" is entry {} of {} in {}", entry.name, index, archive.len(), path);
+    println!();
+    println!("File table record:");
+    let mut record = vec![0; record_size];
+    let file = archive.get_mut();
+    file.seek(SeekFrom::Start(entry.table_entry_offset))?;
+    file.read_exact(&mut record)?;
+    println!("  Location:    0x{:08x} ({} bytes)", entry.table_entry_offset, record_size);
+    print_hex_dump("Raw:", &record);
+    println!("  Data:        0x{:08x} to 0x{:08x} ({} bytes)", entry.offset, entry.offset + entry.size, entry.size);
+    println!("  Modified:    {}", format_timestamp(entry.timestamp));
+    println!("  Flags:       {}", entry.flags);
+    println!("  Unknown:     0x{:08x}", entry.unknown);
+
+    let data = archive.read_data(index)?;
+    let kind = EntryKind::detect(&entry.name, &data);
+    let name_kind = EntryKind::from_name(&entry.name);
+    println!();
+    println!("Contents:");
+    print_hex_dump("Starts with:", &data[..data.len().min(PREVIEW_BYTES)]);
+    if kind == name_kind {
+        println!("  Type:        {}", kind);
+    } else {
+        println!("  Type:        {} (from its contents, although the name suggests {})", kind, name_kind);
+    }
+    match options.config.forced_type(&entry.name) {
+        Some(ForcedType::Skip) => println!("  Config:      skipped when extracting"),
+        Some(ForcedType::Kind(forced)) => println!("  Config:      extracted as {}", forced),
+        None => {},
+    }
+    if kind == EntryKind::Rim {
+        explain_rim(&data, &options.config.limits);
+    }
+
+    println!();
+    let install_dir = match install_dir {
+        Some(install_dir) => install_dir,
+        None => Path::new(&path).parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new(".")).to_path_buf(),
+    };
+    explain_copies(&install_dir, Path::new(&path), &entry.name)?;
+
+    println!();
+    println!("To extract it:");
+    // The suggested command extracts every entry with this name, so others
+    // with the same name may be renamed or skipped.
+    let options = ExtractOptions { patterns : vec![entry.name.clone()], ..options };
+    if let Some(out_path) = plan_output_paths(archive.entries(), &options).get(&index) {
+        println!("  unciv extract {} '{}'  (writes {})", path, entry.name, out_path.display());
+    }
+    println!("  unciv extract --index {} --name-template '{{index}}.{{ext}}' {}", index, path);
+    match kind {
+        EntryKind::Rim => println!("  unciv copy {} '{}' --color-key magenta", path, entry.name),
+        EntryKind::Text => println!("  unciv cat {} '{}'", path, entry.name),
+        _ => {},
+    }
+    Ok(())
+}
@@ -0,0 +1,272 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! The extract command (which is also what happens if no command is given).
+
+use cli::{follow_job, human_bytes, load_config, usage_error, Args, Verbosity};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use unciv::catalog::read_catalog;
+use unciv::decoder::Decoder;
+use unciv::job::{ExtractJob, Job, Progress};
+use unciv::json::JsonValue;
+use unciv::{ExtractOptions, ExtractReport, ExtractStatus, OpenMode, ZfsFile, ZfsStream};
+
+fn print_summary(report : &ExtractReport, verbosity : Verbosity) {
+    let stats = &report.stats;
+    for record in &report.records {
+        if let ExtractStatus::Failed(ref reason) = record.status {
+            eprintln!("unciv: failed to extract \"{}\": {}", record.name, reason);
+        }
+    }
+    if verbosity == Verbosity::Quiet {
+        return;
+    }
+    println!("Processed {} entries: {} extracted, {} converted, {} skipped, {} failed.",
+             stats.processed, stats.extracted, stats.converted, stats.skipped, stats.failed);
+    println!("Read {}, wrote {} in {:.2}s ({}/s).",
+             human_bytes(stats.bytes_read as f64), human_bytes(stats.bytes_written as f64),
+             stats.elapsed.as_secs_f64(), human_bytes(stats.throughput()));
+}
+
+fn report_json(archive : &str, report : &ExtractReport) -> JsonValue {
+    let stats = &report.stats;
+    let entries = report.records.iter().map(|record| {
+        let error = match record.status {
+            ExtractStatus::Failed(ref reason) => JsonValue::from(reason.as_str()),
+            _ => JsonValue::Null,
+        };
+        JsonValue::object(vec![
+            ("index", record.index.into()),
+            ("name", record.name.as_str().into()),
+            ("status", record.status.name().into()),
+            ("bytes_read", record.bytes_read.into()),
+            ("bytes_written", record.bytes_written.into()),
+            ("crc32", record.crc32.map_or(JsonValue::Null, |crc32| format!("{:08x}", crc32).as_str().into())),
+            ("error", error),
+        ])
+    }).collect::<Vec<_>>();
+    JsonValue::object(vec![
+        ("archive", archive.into()),
+        ("entries", JsonValue::Array(entries)),
+        ("summary", JsonValue::object(vec![
+            ("processed", stats.processed.into()),
+            ("extracted", stats.extracted.into()),
+            ("converted", stats.converted.into()),
+            ("skipped", stats.skipped.into()),
+            ("failed", stats.failed.into()),
+            ("bytes_read", stats.bytes_read.into()),
+            ("bytes_written", stats.bytes_written.into()),
+            ("wall_time_secs", stats.elapsed.as_secs_f64().into()),
+            ("throughput_bytes_per_sec", stats.throughput().into()),
+        ])),
+    ])
+}
+
+/// Reads the CRC-32s of an archive's entries from a catalog made with
+/// `unciv catalog --checksums`, by index.
+///
+/// The archive is found in the catalog by its file name (or, if the install
+/// had several archives with that name, by the end of its path), and its
+/// entries must have the same names as they did when it was catalogued.
+fn catalogued_crc32s(catalog_path : &str, archive_path : &str, zfs_file : &ZfsFile) -> io::Result<BTreeMap<usize, u32>> {
+    let catalog = read_catalog(io::BufReader::new(File::open(catalog_path)?))?;
+    let file_name = Path::new(archive_path).file_name().unwrap_or_default();
+    let mut archives : Vec<&str> = catalog.iter()
+        .map(|entry| entry.archive.as_str())
+        .filter(|archive| Path::new(archive).file_name().is_some_and(|name| name.eq_ignore_ascii_case(file_name)))
+        .collect();
+    archives.dedup();
+    if archives.len() > 1 {
+        archives.retain(|archive| Path::new(archive_path).ends_with(archive));
+    }
+    let archive = match archives[..] {
+        [archive] => archive,
+        [] => return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} isn't in the catalog {}", archive_path, catalog_path))),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("The catalog {} has several archives called {}", catalog_path, archive_path))),
+    };
+
+    let mut crc32s = BTreeMap::new();
+    for entry in catalog.iter().filter(|entry| entry.archive == archive) {
+        if zfs_file.files.get(entry.index).map_or(true, |file| file.name != entry.name) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("{} has changed since it was catalogued (there's no \"{}\" at index {})", archive_path, entry.name, entry.index)));
+        }
+        match entry.crc32 {
+            Some(crc32) => crc32s.insert(entry.index, crc32),
+            None => return Err(usage_error(format!("The catalog {} has no CRC-32s: rebuild it with --checksums", catalog_path))),
+        };
+    }
+    Ok(crc32s)
+}
+
+#[cfg(feature = "plugins")]
+fn load_plugin(path : &str) -> io::Result<Arc<dyn Decoder>> {
+    Ok(Arc::new(unciv::plugin::Plugin::load(Path::new(path))?))
+}
+
+#[cfg(not(feature = "plugins"))]
+fn load_plugin(_path : &str) -> io::Result<Arc<dyn Decoder>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "unciv was built without plugin support (rebuild it with --features plugins)"))
+}
+
+/// Parses an index or range of indices, like "5", "100..200", "100..=199",
+/// "100..", or "..200".
+fn parse_index_range(text : &str) -> io::Result<Range<usize>> {
+    let invalid = || usage_error(format!("Invalid index range \"{}\"", text));
+    let parse = |index : &str| index.trim().parse::<usize>().map_err(|_| invalid());
+    let range = match text.split_once("..") {
+        None => {
+            let index = parse(text)?;
+            index..index.checked_add(1).ok_or_else(invalid)?
+        },
+        Some((start, end)) => {
+            let start = if start.is_empty() { 0 } else { parse(start)? };
+            let end = match end.strip_prefix('=') {
+                Some(end) => parse(end)?.checked_add(1).ok_or_else(invalid)?,
+                None if end.is_empty() => usize::MAX,
+                None => parse(end)?,
+            };
+            start..end
+        },
+    };
+    if range.is_empty() {
+        return Err(invalid());
+    }
+    Ok(range)
+}
+
+pub fn run(args : &[String]) -> io::Result<()> {
+    let mut path = None;
+    let mut report_path = None;
+    let mut config_path = None;
+    let mut xattrs = false;
+    let mut progress = false;
+    let mut verbosity = Verbosity::Normal;
+    let mut verify_catalog = None;
+    let mut jobs = std::thread::available_parallelism().map(|jobs| jobs.get()).unwrap_or(1);
+    let mut options = ExtractOptions::default();
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => config_path = Some(PathBuf::from(args.value(&arg)?)),
+            "--format" | "--image-format" => options.image_format = args.value(&arg)?.parse()?,
+            "--color-key" => options.color_key = args.value(&arg)?.parse()?,
+            "--name-template" => options.name_template = Some(args.value(&arg)?.parse()?),
+            "--on-duplicate" => options.on_duplicate = args.value(&arg)?.parse()?,
+            "--report" => report_path = Some(args.value(&arg)?),
+            "-o" | "--output-dir" => options.output_dir = PathBuf::from(args.value(&arg)?),
+            "--xattrs" => xattrs = true,
+            "--progress" => progress = true,
+            "-q" | "--quiet" => verbosity = Verbosity::Quiet,
+            "-v" | "--verbose" => verbosity = Verbosity::Verbose,
+            "--verify-read" => verify_catalog = Some(args.value(&arg)?),
+            "--plugin" => options.decoders.push(load_plugin(&args.value(&arg)?)?),
+            "--index" => for range in args.value(&arg)?.split(',') {
+                options.indices.push(parse_index_range(range)?);
+            },
+            "-j" | "--jobs" => jobs = match args.value(&arg)?.parse() {
+                Ok(jobs) if jobs > 0 => jobs,
+                _ => return Err(usage_error("The number of jobs must be a positive whole number")),
+            },
+            _ if path.is_none() => path = Some(arg),
+            // Anything after the archive name is a pattern to match entries against.
+            _ => options.patterns.push(arg),
+        }
+    }
+    let path = match path {
+        Some(path) => path,
+        None => return Err(usage_error("No archive given")),
+    };
+    // Entries in a stream can't be counted until they've been found.
+    if path == "-" && !options.indices.is_empty() {
+        return Err(usage_error("--index can't be used when reading from standard input"));
+    }
+    if path == "-" && verify_catalog.is_some() {
+        return Err(usage_error("--verify-read can't be used when reading from standard input"));
+    }
+
+    options.config = load_config(config_path)?;
+    if xattrs {
+        options.provenance = Some(path.clone());
+    }
+    std::fs::create_dir_all(&options.output_dir)?;
+
+    if verbosity > Verbosity::Quiet {
+        println!("File: {}", path);
+    }
+
+    // The bar replaces the line per entry, which would only scroll it away.
+    let (job_progress, progress_thread) = follow_job(progress, false, verbosity);
+
+    let report = if path == "-" {
+        // Standard input can't seek, so read the archive as a stream.
+        let stdin = io::stdin();
+        let mut zfs_stream = ZfsStream::with_limits(io::BufReader::new(stdin.lock()), options.config.limits)?;
+        options.progress = job_progress;
+        let report = zfs_stream.extract_all_with(&options)?;
+        options.progress = Progress::none();
+        report
+    } else {
+        if let Some(ref catalog_path) = verify_catalog {
+            let zfs_file = ZfsFile::from_stream_with_limits(&mut OpenMode::ReadOnly.open(&path)?, &options.config.limits)?;
+            options.expected_crc32s = catalogued_crc32s(catalog_path, &path, &zfs_file)?;
+        }
+        let job = ExtractJob { path : PathBuf::from(&path), options, jobs };
+        let report = job.run(&job_progress)?;
+        drop(job_progress);
+        report
+    };
+    let _ = progress_thread.join();
+    print_summary(&report, verbosity);
+
+    if let Some(report_path) = report_path {
+        std::fs::write(report_path, report_json(&path, &report).to_pretty_string())?;
+    }
+    if report.stats.failed > 0 {
+        return Err(io::Error::other(format!("{} entries could not be extracted", report.stats.failed)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn index_ranges_parse() {
+        assert_eq!(parse_index_range("5").unwrap(), 5..6);
+        assert_eq!(parse_index_range("100..200").unwrap(), 100..200);
+        assert_eq!(parse_index_range("100..=199").unwrap(), 100..200);
+        assert_eq!(parse_index_range("100..").unwrap(), 100..usize::MAX);
+        assert_eq!(parse_index_range("..200").unwrap(), 0..200);
+        assert_eq!(parse_index_range(" 3 .. 4 ").unwrap(), 3..4);
+    }
+
+    #[test]
+    fn bad_index_ranges_are_refused() {
+        for text in &["", "x", "5..5", "6..5", "-1", "1..=x", "..=18446744073709551615", "18446744073709551615"] {
+            assert!(parse_index_range(text).is_err(), "{:?} parsed", text);
+        }
+    }
+}
@@ -0,0 +1,367 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Bringing trees of files extracted by older tools into unciv's layout: a
+//! folder of files named after their entries for each archive, with a
+//! manifest for `unciv pack --manifest`.
+
+use cli::convert::convert_png_file;
+use cli::{unexpected_argument, usage_error, Args};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use unciv::manifest::{write_manifest, ManifestEntry, MANIFEST_FILE_NAME};
+use unciv::timestamp;
+use unciv::{RimFormat, RimImage, ZfsArchive, ZfsBuilder};
+
+/// How a tool laid out the files it extracted. In each, a folder holds the
+/// entries of one archive.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Convention
+{
+    /// unciv's own: images as "name.rim.png" (with the original "name.rim"
+    /// alongside, for --format rim-copy), and everything else unchanged.
+    Unciv,
+    /// ctp2unpacker's: images as PNGs with ".rim" replaced by ".png" (with
+    /// the original "name.rim" alongside, if it kept it), and everything else
+    /// unchanged.
+    Ctp2Unpacker,
+    /// Every file exactly as it was in the archive.
+    Raw,
+}
+
+impl Convention
+{
+    fn parse(name : &str) -> io::Result<Convention> {
+        match name.to_ascii_lowercase().as_str() {
+            "unciv" => Ok(Convention::Unciv),
+            "ctp2unpacker" => Ok(Convention::Ctp2Unpacker),
+            "raw" => Ok(Convention::Raw),
+            _ => Err(usage_error(format!("Unknown convention \"{}\" (expected unciv, ctp2unpacker, or raw)", name))),
+        }
+    }
+}
+
+/// What to do with a file in an old tree.
+enum Mapping
+{
+    /// Copy it, as the entry with this name.
+    Copy(String),
+    /// Convert it from PNG to RIM, as the entry with this name. If the
+    /// original RIM was kept alongside it (the file named second), that's
+    /// used instead, unless the PNG has been edited.
+    FromPng(String, Option<String>),
+    /// Leave it to the PNG it was converted to, which decides whether it's
+    /// used.
+    Converted,
+    /// Leave it out, for this reason.
+    Skip(String),
+}
+
+/// The PNG that a RIM called `rim_name` would have been extracted as.
+fn converted_name(convention : Convention, rim_name : &str) -> Option<String> {
+    match convention {
+        Convention::Unciv => Some(format!("{}.png", rim_name)),
+        Convention::Ctp2Unpacker => Some(format!("{}.png", &rim_name[..rim_name.len() - ".rim".len()])),
+        Convention::Raw => None,
+    }
+}
+
+/// Works out what a file in an old tree was extracted from, given the names
+/// of the files alongside it (lowercased, mapped to how they're really
+/// written).
+fn map_file(convention : Convention, file_name : &str, siblings : &HashMap<String, String>) -> Mapping {
+    let lower_name = file_name.to_ascii_lowercase();
+    if file_name.starts_with('.') || lower_name == "thumbs.db" || file_name == MANIFEST_FILE_NAME {
+        return Mapping::Skip("it isn't part of the game's data".to_string());
+    }
+    let has_png = |png_name : String| siblings.contains_key(&png_name.to_ascii_lowercase());
+    if lower_name.ends_with(".rim") && converted_name(convention, file_name).is_some_and(has_png) {
+        return Mapping::Converted;
+    }
+    let from_png = |rim_name : String| {
+        let original = siblings.get(&rim_name.to_ascii_lowercase()).cloned();
+        Mapping::FromPng(rim_name, original)
+    };
+    match convention {
+        Convention::Unciv => {
+            if let Some(rim_name) = lower_name.strip_suffix(".png").filter(|stem| stem.ends_with(".rim")) {
+                return from_png(file_name[..rim_name.len()].to_string());
+            }
+            for extension in [".rim.tga", ".rim.bmp", ".rim.qoi", ".rim.raw16"] {
+                if lower_name.ends_with(extension) {
+                    return Mapping::Skip("only images extracted as PNG can be converted back to RIM".to_string());
+                }
+            }
+            if let Some(stem) = lower_name.strip_suffix(".json").filter(|stem| siblings.contains_key(&format!("{}.raw16", stem))) {
+                return Mapping::Skip(format!("it describes {}.raw16", &file_name[..stem.len()]));
+            }
+            Mapping::Copy(file_name.to_string())
+        },
+        Convention::Ctp2Unpacker => match lower_name.strip_suffix(".png") {
+            Some(stem) => from_png(format!("{}.rim", &file_name[..stem.len()])),
+            None => Mapping::Copy(file_name.to_string()),
+        },
+        Convention::Raw => Mapping::Copy(file_name.to_string()),
+    }
+}
+
+/// How many files importing one folder did what with.
+#[derive(Default)]
+struct ImportCounts
+{
+    copied : usize,
+    converted : usize,
+    /// Images whose original RIM was kept alongside their PNG, and used, as
+    /// the PNG hadn't been edited.
+    originals : usize,
+    /// Images converted with the default RIM format, as there was no
+    /// original to take theirs from.
+    defaulted : usize,
+    skipped : usize,
+    failed : usize,
+}
+
+/// Finds the archive called `<name>.zfs` (ignoring case) in `dir`.
+fn find_original(dir : &Path, name : &str) -> io::Result<Option<PathBuf>> {
+    let file_name = format!("{}.zfs", name);
+    for dir_entry in std::fs::read_dir(dir)? {
+        let path = dir_entry?.path();
+        if path.file_name().is_some_and(|other| other.to_string_lossy().eq_ignore_ascii_case(&file_name)) {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// The format of the RIM image called `name` in `original`, if it has one.
+fn original_format(original : &mut ZfsArchive<io::BufReader<File>>, name : &str) -> Option<RimFormat> {
+    let index = original.entries().iter().position(|entry| entry.name.eq_ignore_ascii_case(name))?;
+    let mut header = [0; 16];
+    original.reader(index).ok()?.read_exact(&mut header).ok()?;
+    if &header[..4] != b"RIMF" {
+        return None;
+    }
+    RimFormat::from_raw(u16::from_le_bytes([header[14], header[15]])).ok()
+}
+
+/// Whether the PNG at `png_path` is still just the RIM at `rim_path`
+/// converted, so the RIM can be used as it is: either the PNG is no newer, or
+/// it has the same pixels. Also returns the RIM's format, for converting the
+/// PNG if it has been edited.
+fn png_unchanged(png_path : &Path, rim_path : &Path) -> io::Result<(bool, RimFormat)> {
+    let rim = RimImage::from_stream(&mut File::open(rim_path)?)?;
+    if png_path.metadata()?.modified()? <= rim_path.metadata()?.modified()? {
+        return Ok((true, rim.format));
+    }
+    Ok((rim.same_as_png(io::BufReader::new(File::open(png_path)?))?, rim.format))
+}
+
+/// Imports the files directly in `in_dir` into `out_dir`, writing its
+/// manifest. Folders within it are skipped, quietly if they're `archives` of
+/// their own.
+///
+/// PNGs are converted to the format the image has in the `original` archive
+/// the tree was extracted from, if there is one, or `rim_format` otherwise.
+fn import_folder(in_dir : &Path, out_dir : &Path, convention : Convention, rim_format : RimFormat,
+                 mut original : Option<ZfsArchive<io::BufReader<File>>>, archives : bool) -> io::Result<ImportCounts> {
+    let mut paths = Vec::new();
+    for dir_entry in std::fs::read_dir(in_dir)? {
+        paths.push(dir_entry?.path());
+    }
+    paths.sort();
+    let siblings : HashMap<String, String> = paths.iter().filter_map(|path| path.file_name()).map(|name| {
+        let name = name.to_string_lossy();
+        (name.to_ascii_lowercase(), name.into_owned())
+    }).collect();
+    let max_name_len = ZfsBuilder::new().max_filename_len() as usize;
+    std::fs::create_dir_all(out_dir)?;
+
+    let mut counts = ImportCounts::default();
+    let mut entries = Vec::new();
+    let mut names = HashSet::new();
+    for path in &paths {
+        let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+            eprintln!("unciv: skipping {}: its name isn't valid UTF-8", path.display());
+            counts.skipped += 1;
+            continue;
+        };
+        if path.is_dir() {
+            if !archives {
+                eprintln!("unciv: skipping {}: archives can't hold folders", path.display());
+                counts.skipped += 1;
+            }
+            continue;
+        }
+        let (name, from_png, original_rim) = match map_file(convention, file_name, &siblings) {
+            Mapping::Copy(name) => (name, false, None),
+            Mapping::FromPng(name, original_rim) => (name, true, original_rim),
+            // Counted with its PNG.
+            Mapping::Converted => continue,
+            Mapping::Skip(reason) => {
+                eprintln!("unciv: skipping {}: {}", path.display(), reason);
+                counts.skipped += 1;
+                continue;
+            },
+        };
+        if name.len() > max_name_len {
+            eprintln!("unciv: skipping {}: \"{}\" is longer than the {} bytes an entry name can have", path.display(), name, max_name_len);
+            counts.skipped += 1;
+            continue;
+        }
+        // The game ignores case, so names differing only in case would clash.
+        if !names.insert(name.to_ascii_lowercase()) {
+            eprintln!("unciv: skipping {}: there's already an entry called \"{}\"", path.display(), name);
+            counts.skipped += 1;
+            continue;
+        }
+
+        let out_path = out_dir.join(&name);
+        // The file the entry is really taken from, which is the original RIM
+        // if that's kept alongside an unedited PNG.
+        let mut source = path.clone();
+        let result = (|| {
+            if !from_png {
+                return std::fs::copy(path, &out_path).map(|_| false);
+            }
+            let mut format = None;
+            if let Some(ref original_rim) = original_rim {
+                let rim_path = in_dir.join(original_rim);
+                let (unchanged, rim_format) = png_unchanged(path, &rim_path)?;
+                if unchanged {
+                    std::fs::copy(&rim_path, &out_path)?;
+                    source = rim_path;
+                    return Ok(false);
+                }
+                format = Some(rim_format);
+            }
+            let format = format.or_else(|| original.as_mut().and_then(|original| original_format(original, &name)));
+            if format.is_none() {
+                counts.defaulted += 1;
+            }
+            convert_png_file(path, &out_path, format.unwrap_or(rim_format))?;
+            Ok(true)
+        })();
+        // Keep the old file's time, rather than when it was copied.
+        match result.and_then(|converted| Ok((converted, source.metadata()?.modified()?))) {
+            Ok((converted, modified)) => {
+                entries.push(ManifestEntry { name, file : out_path, timestamp : timestamp::to_zfs(modified), flags : 0 });
+                if converted {
+                    counts.converted += 1;
+                } else if source != *path {
+                    counts.originals += 1;
+                } else {
+                    counts.copied += 1;
+                }
+            },
+            Err(err) => {
+                eprintln!("unciv: couldn't import {}: {}", path.display(), err);
+                counts.failed += 1;
+            },
+        }
+    }
+
+    let mut manifest = io::BufWriter::new(File::create(out_dir.join(MANIFEST_FILE_NAME))?);
+    write_manifest(&mut manifest, &entries, out_dir)?;
+    manifest.flush()?;
+    Ok(counts)
+}
+
+pub fn run(args : &[String]) -> io::Result<()> {
+    let mut in_dir = None;
+    let mut out_dir = None;
+    let mut convention = None;
+    let mut rim_format = RimFormat::Rgb565;
+    let mut originals_dir = None;
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output-dir" => out_dir = Some(PathBuf::from(args.value(&arg)?)),
+            "--convention" => convention = Some(Convention::parse(&args.value(&arg)?)?),
+            "--originals" => originals_dir = Some(PathBuf::from(args.value(&arg)?)),
+            "--rim-format" => rim_format = match args.value(&arg)?.as_str() {
+                "555" => RimFormat::Rgb555,
+                "565" => RimFormat::Rgb565,
+                other => return Err(usage_error(format!("Unknown RIM format \"{}\"", other))),
+            },
+            _ if in_dir.is_none() => in_dir = Some(PathBuf::from(arg)),
+            _ => return Err(unexpected_argument(&arg)),
+        }
+    }
+    let in_dir = in_dir.ok_or_else(|| usage_error("No folder to import given"))?;
+    let out_dir = out_dir.ok_or_else(|| usage_error("No output folder given (use -o)"))?;
+    let convention = convention.ok_or_else(|| usage_error("No convention given (use --convention unciv, ctp2unpacker, or raw)"))?;
+
+    // Each folder in the tree is an archive, and so are any files at the top
+    // of it (for trees holding a single archive).
+    let mut folders = Vec::new();
+    let mut has_files = false;
+    for dir_entry in std::fs::read_dir(&in_dir)? {
+        let path = dir_entry?.path();
+        if path.is_dir() {
+            folders.push(path);
+        } else {
+            has_files = true;
+        }
+    }
+    folders.sort();
+    let mut archives : Vec<(PathBuf, PathBuf)> = folders.into_iter().map(|folder| {
+        let out = out_dir.join(folder.file_name().unwrap_or_default());
+        (folder, out)
+    }).collect();
+    if has_files {
+        let name = in_dir.canonicalize()?.file_name().map_or_else(|| PathBuf::from("archive"), PathBuf::from);
+        if archives.iter().any(|(_, out)| out.file_name() == Some(name.as_os_str())) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("{} has both files and a folder called {}, so they can't be told apart", in_dir.display(), name.display())));
+        }
+        archives.insert(0, (in_dir.clone(), out_dir.join(name)));
+    }
+
+    let mut failed = 0;
+    for (folder, out) in &archives {
+        let archive_name = out.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let original = match originals_dir {
+            Some(ref dir) => match find_original(dir, &archive_name)? {
+                Some(path) => Some(ZfsArchive::open_indexed(&path)?),
+                None => {
+                    eprintln!("unciv: there's no {}.zfs in {}", archive_name, dir.display());
+                    None
+                },
+            },
+            None => None,
+        };
+        let counts = import_folder(folder, out, convention, rim_format, original, *folder == in_dir)?;
+        println!("Imported {} into {}: {} copied, {} converted to RIM, {} unedited images taken from their original RIMs, {} skipped, {} failed.",
+                 folder.display(), out.display(), counts.copied, counts.converted, counts.originals, counts.skipped, counts.failed);
+        if counts.defaulted > 0 {
+            eprintln!("unciv: warning: {} of the images in {} had no original to take their format from, so they were converted to {}",
+                      counts.defaulted, folder.display(), rim_format.name());
+        }
+        failed += counts.failed;
+    }
+    if !archives.is_empty() {
+        println!("Pack each folder with \"unciv pack --manifest <folder>/{} -o <archive>.zfs\".", MANIFEST_FILE_NAME);
+    }
+    if failed > 0 {
+        return Err(io::Error::other(format!("{} files could not be imported", failed)));
+    }
+    Ok(())
+}
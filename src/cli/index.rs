@@ -0,0 +1,63 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Writing and removing archives' index caches.
+
+use cli::usage_error;
+use std::io;
+use std::path::PathBuf;
+use unciv::index::index_path;
+use unciv::{OpenMode, ZfsFile};
+
+pub fn run(args : &[String]) -> io::Result<()> {
+    let mut paths = Vec::new();
+    let mut remove = false;
+    for arg in args {
+        match arg.as_str() {
+            "--remove" => remove = true,
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    if paths.is_empty() {
+        return Err(usage_error("No archive given"));
+    }
+
+    let mut failed = 0;
+    for path in &paths {
+        let result = if remove {
+            match std::fs::remove_file(index_path(path)) {
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok("has no index".to_string()),
+                result => result.map(|()| "index removed".to_string()),
+            }
+        } else {
+            ZfsFile::from_stream(&mut io::BufReader::new(OpenMode::ReadOnly.open(path)?))
+                .and_then(|zfs_file| zfs_file.write_index(path).map(|()| format!("indexed {} entries", zfs_file.files.len())))
+        };
+        match result {
+            Ok(message) => println!("{}: {}", path.display(), message),
+            Err(err) => {
+                eprintln!("unciv: {}: {}", path.display(), err);
+                failed += 1;
+            },
+        }
+    }
+    if failed > 0 {
+        return Err(io::Error::other(format!("{} archives could not be indexed", failed)));
+    }
+    Ok(())
+}
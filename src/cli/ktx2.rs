@@ -0,0 +1,110 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Exporting an archive's images as KTX2 texture arrays.
+
+use cli::{usage_error, Args};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use unciv::glob::glob_match;
+use unciv::json::JsonValue;
+use unciv::ktx2::write_texture_array;
+use unciv::{ColorKey, EntryKind, OpenMode, RimImage, ZfsArchive};
+
+/// The images of one size, which are exported as one texture array.
+#[derive(Default)]
+struct TextureArray
+{
+    names : Vec<String>,
+    layers : Vec<Vec<u8>>,
+}
+
+pub fn run(args : &[String]) -> io::Result<()> {
+    let mut path = None;
+    let mut out_dir = PathBuf::from(".");
+    let mut color_key = ColorKey::None;
+    let mut patterns = Vec::new();
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output-dir" => out_dir = PathBuf::from(args.value(&arg)?),
+            "--color-key" => color_key = args.value(&arg)?.parse()?,
+            _ if path.is_none() => path = Some(arg),
+            _ => patterns.push(arg),
+        }
+    }
+    let path = match path {
+        Some(path) => path,
+        None => return Err(usage_error("No archive given")),
+    };
+    let mut archive = ZfsArchive::new(io::BufReader::new(OpenMode::ReadOnly.open(&path)?))?;
+
+    // Group the images by size: each size becomes one texture array.
+    let mut groups : BTreeMap<(u16, u16), TextureArray> = BTreeMap::new();
+    for index in 0..archive.len() {
+        let name = archive.entries()[index].name.clone();
+        if EntryKind::from_name(&name) != EntryKind::Rim {
+            continue;
+        }
+        if !patterns.is_empty() && !patterns.iter().any(|pattern| glob_match(pattern, &name)) {
+            continue;
+        }
+        let image = match RimImage::from_stream(&mut archive.reader(index)?) {
+            Ok(image) => image,
+            Err(err) => {
+                eprintln!("unciv: skipping \"{}\": {}", name, err);
+                continue;
+            },
+        };
+        let rgba = image.to_rgba_bytes_with(color_key)?;
+        let group = groups.entry((image.width, image.height)).or_default();
+        group.names.push(name);
+        group.layers.push(rgba);
+    }
+    if groups.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "No RIM images to export"));
+    }
+
+    std::fs::create_dir_all(&out_dir)?;
+    let stem = Path::new(&path).file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    for (&(width, height), group) in &groups {
+        let base = out_dir.join(format!("{}_{}x{}", stem, width, height));
+        let ktx2_path = base.with_extension("ktx2");
+        println!("Writing {} layers of {}×{} to \"{}\"…", group.layers.len(), width, height, ktx2_path.display());
+
+        let mut writer = io::BufWriter::new(File::create(&ktx2_path)?);
+        write_texture_array(&mut writer, width as u32, height as u32, &group.layers)?;
+        writer.flush()?;
+
+        // Record which entry ended up in which layer.
+        let layer_map = JsonValue::object(vec![
+            ("archive", path.as_str().into()),
+            ("width", (width as u32).into()),
+            ("height", (height as u32).into()),
+            ("layers", JsonValue::Array(group.names.iter().enumerate().map(|(layer, name)| JsonValue::object(vec![
+                ("layer", layer.into()),
+                ("name", name.as_str().into()),
+            ])).collect())),
+        ]);
+        std::fs::write(base.with_extension("json"), layer_map.to_pretty_string())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,45 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Dumping .ldl layout files as JSON.
+
+use cli::{unexpected_argument, usage_error, Args};
+use std::io;
+use unciv::ldl::LdlFile;
+use unciv::ZfsArchive;
+
+pub fn run(args : &[String]) -> io::Result<()> {
+    let mut args = Args::new(args);
+    let path = match args.next() {
+        Some(path) => path,
+        None => return Err(usage_error("No .ldl file given")),
+    };
+    // Either a loose file, or an archive and the name of an entry in it.
+    let data = match args.next() {
+        Some(name) => ZfsArchive::open_indexed(&path)?.read_by_name(&name)?,
+        None => std::fs::read(&path)?,
+    };
+    if let Some(arg) = args.next() {
+        return Err(unexpected_argument(&arg));
+    }
+
+    // The game's text files aren't necessarily UTF-8.
+    let ldl = LdlFile::parse(&String::from_utf8_lossy(&data))?;
+    print!("{}", ldl.to_json().to_pretty_string());
+    Ok(())
+}
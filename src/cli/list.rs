@@ -0,0 +1,241 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Commands for looking inside archives without extracting them.
+
+use cli::{find_files, format_timestamp, has_extension, human_bytes, unexpected_argument, usage_error, Args};
+use std::collections::BTreeMap;
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+use unciv::glob::glob_match;
+use unciv::json::JsonValue;
+use unciv::{EntryKind, OpenMode, ZfsArchive};
+
+/// Parses the arguments shared by the commands here: an archive, followed
+/// by patterns to match entry names against.
+fn archive_and_patterns(args : &[String]) -> io::Result<(String, Vec<String>)> {
+    let mut args = Args::new(args);
+    let path = match args.next() {
+        Some(path) => path,
+        None => return Err(usage_error("No archive given")),
+    };
+    Ok((path, args.collect()))
+}
+
+fn matches_any(patterns : &[String], name : &str) -> bool {
+    patterns.is_empty() || patterns.iter().any(|pattern| glob_match(pattern, name))
+}
+
+/// How `list` prints the entries.
+enum ListFormat
+{
+    Table,
+    Json,
+    Csv,
+    /// Every field of the header and each record as it's stored, in hex.
+    Raw,
+}
+
+/// Quotes a CSV field if it needs it.
+fn csv_field(field : &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+pub fn list(args : &[String]) -> io::Result<()> {
+    let mut format = ListFormat::Table;
+    let mut path = None;
+    let mut patterns = Vec::new();
+    for arg in Args::new(args) {
+        match arg.as_str() {
+            "--json" => format = ListFormat::Json,
+            "--csv" => format = ListFormat::Csv,
+            "--dump-raw" => format = ListFormat::Raw,
+            _ if path.is_none() => path = Some(arg),
+            _ => patterns.push(arg),
+        }
+    }
+    let path = path.ok_or_else(|| usage_error("No archive given"))?;
+    let mut archive = ZfsArchive::open_indexed(&path)?;
+    let matching : Vec<usize> = (0..archive.len()).filter(|&i| matches_any(&patterns, &archive.entries()[i].name)).collect();
+
+    if let ListFormat::Table = format {
+        println!("{:>6}  {:>10}  {:<5}  {:<19}  Name", "Index", "Size", "Type", "Modified");
+        for index in matching {
+            let entry = &archive.entries()[index];
+            println!("{:>6}  {:>10}  {:<5}  {:<19}  {}", index, entry.size, EntryKind::from_name(&entry.name),
+                     format_timestamp(entry.timestamp), entry.name);
+        }
+        return Ok(());
+    }
+    if let ListFormat::Raw = format {
+        let header = archive.zfs_file().raw_header();
+        println!("Header: version 0x{:08x}, name length 0x{:08x}, per table 0x{:08x}, entries 0x{:08x}, unknown 0x{:08x}, first table 0x{:08x}",
+                 header.version, header.max_filename_len, header.files_per_table, header.num_files, header.unknown, header.filetable_offset);
+        println!("{:>6}  {:>10}  {:>10}  {:>10}  {:>10}  {:>10}  {:>10}  Name", "Index", "Record", "Offset", "Unknown", "Size", "Timestamp", "Flags");
+        for index in matching {
+            let entry = &archive.entries()[index];
+            let raw = entry.raw();
+            println!("{:>6}  0x{:08x}  0x{:08x}  0x{:08x}  0x{:08x}  0x{:08x}  0x{:08x}  {}", index, entry.table_entry_offset,
+                     raw.offset, raw.unknown, raw.size, raw.timestamp, raw.flags, entry.name);
+        }
+        return Ok(());
+    }
+
+    // The machine-readable formats detect types from the data, like find.
+    let mut rows = Vec::with_capacity(matching.len());
+    for index in matching {
+        let mut header = Vec::with_capacity(16);
+        archive.reader(index)?.take(16).read_to_end(&mut header)?;
+        let entry = &archive.entries()[index];
+        let timestamp = entry.zfs_timestamp() as u64;
+        rows.push((index, entry.clone(), timestamp, EntryKind::detect(&entry.name, &header)));
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match format {
+        ListFormat::Json => {
+            let entries = rows.into_iter().map(|(index, entry, timestamp, kind)| JsonValue::object(vec![
+                ("index", JsonValue::from(index)),
+                ("name", JsonValue::from(entry.name)),
+                ("offset", JsonValue::from(entry.offset)),
+                ("size", JsonValue::from(entry.size)),
+                ("timestamp", JsonValue::from(timestamp)),
+                ("flags", JsonValue::from(entry.flags.raw())),
+                ("type", JsonValue::from(kind.name())),
+            ])).collect();
+            write!(out, "{}", JsonValue::object(vec![
+                ("archive", JsonValue::from(path)),
+                ("entries", JsonValue::Array(entries)),
+            ]).to_pretty_string())?;
+        },
+        _ => {
+            writeln!(out, "index,name,offset,size,timestamp,flags,type")?;
+            for (index, entry, timestamp, kind) in rows {
+                writeln!(out, "{},{},{},{},{},{},{}", index, csv_field(&entry.name), entry.offset, entry.size, timestamp, entry.flags.raw(), kind)?;
+            }
+        },
+    }
+    out.flush()
+}
+
+pub fn info(args : &[String]) -> io::Result<()> {
+    let mut args = Args::new(args);
+    let path = match args.next() {
+        Some(path) => path,
+        None => return Err(usage_error("No archive given")),
+    };
+    if let Some(arg) = args.next() {
+        return Err(unexpected_argument(&arg));
+    }
+
+    let file = OpenMode::ReadOnly.open(&path)?;
+    let archive_size = file.metadata()?.len();
+    let archive = ZfsArchive::new(file)?;
+    let zfs_file = archive.zfs_file();
+
+    let mut kinds = BTreeMap::new();
+    let mut data_size = 0u64;
+    for entry in archive.entries() {
+        let count = kinds.entry(EntryKind::from_name(&entry.name).name()).or_insert((0, 0u64));
+        count.0 += 1;
+        count.1 += entry.size as u64;
+        data_size += entry.size as u64;
+    }
+
+    println!("Archive:             {}", path);
+    println!("Size:                {} ({} bytes)", human_bytes(archive_size as f64), archive_size);
+    println!("Format version:      {}", zfs_file.version());
+    println!("Max file name:       {} bytes", zfs_file.max_filename_len());
+    println!("Entries per table:   {}", zfs_file.files_per_table());
+    println!("Record size:         {} bytes", zfs_file.record_size());
+    println!("Entries:             {}", archive.len());
+    println!("Total data:          {}", human_bytes(data_size as f64));
+    for (kind, (count, size)) in kinds {
+        println!("  {:<18} {} entries, {}", format!("{}:", kind), count, human_bytes(size as f64));
+    }
+    Ok(())
+}
+
+pub fn cat(args : &[String]) -> io::Result<()> {
+    let (path, patterns) = archive_and_patterns(args)?;
+    if patterns.is_empty() {
+        return Err(usage_error("No entry name given"));
+    }
+    let mut archive = ZfsArchive::open_indexed(&path)?;
+
+    let matching : Vec<usize> = (0..archive.len()).filter(|&i| matches_any(&patterns, &archive.entries()[i].name)).collect();
+    if matching.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "No entries match"));
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for index in matching {
+        io::copy(&mut archive.reader(index)?, &mut out)?;
+    }
+    out.flush()
+}
+
+/// Lists the entries matching the patterns in one archive, detecting their
+/// types from their contents. Returns how many there were.
+fn find_in_archive(install_dir : &Path, path : &Path, patterns : &[String]) -> io::Result<usize> {
+    let mut archive = ZfsArchive::open_indexed(path)?;
+    let display_path = path.strip_prefix(install_dir).unwrap_or(path);
+    let mut found = 0;
+    for index in 0..archive.len() {
+        if !matches_any(patterns, &archive.entries()[index].name) {
+            continue;
+        }
+        let mut header = Vec::with_capacity(16);
+        archive.reader(index)?.take(16).read_to_end(&mut header)?;
+        let entry = &archive.entries()[index];
+        println!("{:>10}  {:<5}  {}:{}", entry.size, EntryKind::detect(&entry.name, &header), display_path.display(), entry.name);
+        found += 1;
+    }
+    Ok(found)
+}
+
+pub fn find(args : &[String]) -> io::Result<()> {
+    let (install_dir, patterns) = archive_and_patterns(args)?;
+    if patterns.is_empty() {
+        return Err(usage_error("No pattern given"));
+    }
+    let install_dir = Path::new(&install_dir);
+    let archives = find_files(install_dir, &|path| has_extension(path, "zfs"))?;
+
+    println!("{:>10}  {:<5}  Archive:Name", "Size", "Type");
+    let mut found = 0;
+    for path in &archives {
+        match find_in_archive(install_dir, path, &patterns) {
+            Ok(count) => found += count,
+            // Keep looking through the rest of the install.
+            Err(err) => eprintln!("unciv: {}: {}", path.display(), err),
+        }
+    }
+    println!("Found {} matching entries in {} archives.", found, archives.len());
+    if found == 0 {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "No entries match"));
+    }
+    Ok(())
+}
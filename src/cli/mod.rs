@@ -0,0 +1,254 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Helpers shared by unciv's commands.
+
+pub mod atlas;
+pub mod catalog;
+pub mod changes;
+pub mod check;
+pub mod contrast;
+pub mod convert;
+pub mod copy;
+pub mod diff;
+pub mod explain;
+pub mod extract;
+pub mod import_tree;
+pub mod index;
+pub mod ktx2;
+pub mod ldl;
+pub mod list;
+pub mod pack;
+pub mod recolor;
+pub mod repair;
+pub mod replace;
+pub mod replay;
+pub mod similar;
+pub mod verify;
+pub mod watch;
+
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::SystemTime;
+use unciv::job::{JobEvent, LogLevel, Progress, StepOutcome};
+use unciv::Config;
+
+/// Walks through a command's arguments.
+pub struct Args
+{
+    /// The remaining arguments, in reverse order so we can pop them off.
+    remaining : Vec<String>,
+}
+
+impl Args
+{
+    pub fn new(args : &[String]) -> Args {
+        Args { remaining : args.iter().rev().cloned().collect() }
+    }
+
+    /// Returns the value following a flag, such as the file name after
+    /// `--report`.
+    pub fn value(&mut self, flag : &str) -> io::Result<String> {
+        match self.remaining.pop() {
+            Some(value) => Ok(value),
+            None => Err(usage_error(format!("{} requires a value", flag))),
+        }
+    }
+}
+
+impl Iterator for Args
+{
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        self.remaining.pop()
+    }
+}
+
+pub fn usage_error<S : Into<String>>(message : S) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.into())
+}
+
+pub fn unexpected_argument(arg : &str) -> io::Error {
+    usage_error(format!("Unexpected argument \"{}\"", arg))
+}
+
+/// How much a command says about what it's doing, from `-q`/`--quiet` and
+/// `-v`/`--verbose`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity
+{
+    /// Only warnings and errors.
+    Quiet,
+    Normal,
+    /// Debug messages too, such as each entry's record.
+    Verbose,
+}
+
+/// Formats a byte count for people, e.g. "1.5 MiB".
+fn human_bytes(bytes : f64) -> String {
+    let units = ["bytes", "KiB", "MiB", "GiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < units.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", value, units[unit])
+    } else {
+        format!("{:.1} {}", value, units[unit])
+    }
+}
+
+/// Shows a job's progress, until every copy of the `Progress` it returns
+/// has been dropped (at which point the returned thread finishes).
+///
+/// Warnings are printed to standard error as they happen. With `bar`, a
+/// progress bar is drawn on standard error instead of the job's log; without
+/// it, the job's log is printed (unless `verbosity` is `Quiet`), along with
+/// each step's description as it finishes if `log_steps` is set. Debug
+/// messages are printed to standard error when `verbosity` is `Verbose`.
+pub fn follow_job(bar : bool, log_steps : bool, verbosity : Verbosity) -> (Progress, JoinHandle<()>) {
+    const BAR_WIDTH : usize = 30;
+    let (sender, receiver) = mpsc::channel();
+    let thread = std::thread::spawn(move || {
+        let (mut total_steps, mut total_bytes) = (None, None);
+        let (mut steps, mut bytes) = (0, 0);
+        for event in receiver {
+            match event {
+                JobEvent::Begin { steps, bytes } => {
+                    total_steps = steps;
+                    total_bytes = bytes;
+                    continue;
+                },
+                JobEvent::StepStarted { .. } => continue,
+                JobEvent::StepFinished { bytes : step_bytes, outcome, .. } => {
+                    steps += 1;
+                    bytes += step_bytes;
+                    if let (true, StepOutcome::Done(Some(message))) = (log_steps && !bar && verbosity > Verbosity::Quiet, outcome) {
+                        println!("{}", message);
+                    }
+                },
+                JobEvent::Warning(message) => {
+                    if bar {
+                        eprint!("\r\x1b[K");
+                    }
+                    eprintln!("unciv: warning: {}", message);
+                },
+                JobEvent::Log(LogLevel::Info, message) => {
+                    if !bar && verbosity > Verbosity::Quiet {
+                        println!("{}", message);
+                    }
+                    continue;
+                },
+                JobEvent::Log(LogLevel::Debug, message) => {
+                    if verbosity < Verbosity::Verbose {
+                        continue;
+                    }
+                    if bar {
+                        eprint!("\r\x1b[K");
+                    }
+                    eprintln!("unciv: debug: {}", message);
+                },
+            }
+            if !bar {
+                continue;
+            }
+            let line = match (total_steps, total_bytes) {
+                (Some(total_steps), Some(total_bytes)) => {
+                    let fraction = if total_bytes > 0 { bytes as f64 / total_bytes as f64 } else { 1.0 };
+                    let filled = ((fraction * BAR_WIDTH as f64) as usize).min(BAR_WIDTH);
+                    format!("[{}{}] {:3.0}% {}/{}, {} of {}", "#".repeat(filled), " ".repeat(BAR_WIDTH - filled),
+                            fraction * 100.0, steps, total_steps, human_bytes(bytes as f64), human_bytes(total_bytes as f64))
+                },
+                _ => format!("{} done, {}", steps, human_bytes(bytes as f64)),
+            };
+            eprint!("\r\x1b[K{}", line);
+            let _ = io::stderr().flush();
+        }
+        if bar {
+            eprint!("\r\x1b[K");
+        }
+    });
+    (Progress::new(sender), thread)
+}
+
+/// Returns true if the path has the given extension, ignoring case.
+pub fn has_extension(path : &Path, extension : &str) -> bool {
+    match path.extension() {
+        Some(ext) => ext.eq_ignore_ascii_case(extension),
+        None => false,
+    }
+}
+
+/// Recursively finds all the files under `dir` matching `predicate`, sorted
+/// by path.
+pub fn find_files(dir : &Path, predicate : &dyn Fn(&Path) -> bool) -> io::Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    let mut paths = Vec::new();
+    for dir_entry in std::fs::read_dir(dir)? {
+        paths.push(dir_entry?.path());
+    }
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            found.extend(find_files(&path, predicate)?);
+        } else if predicate(&path) {
+            found.push(path);
+        }
+    }
+    Ok(found)
+}
+
+/// Formats a timestamp as a UTC date and time, e.g. "1999-03-16 12:00:00".
+pub fn format_timestamp(timestamp : SystemTime) -> String {
+    let secs = match timestamp.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs(),
+        Err(_) => return "(before 1970)".to_string(),
+    };
+    let days = (secs / 86400) as i64;
+    let time_of_day = secs % 86400;
+
+    // Convert days since the epoch to a civil date (Howard Hinnant's algorithm).
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day,
+            time_of_day / 3600, (time_of_day / 60) % 60, time_of_day % 60)
+}
+
+/// Loads the config file given with --config, or `unciv.conf` from the
+/// current directory if there is one.
+pub fn load_config(path : Option<PathBuf>) -> io::Result<Config> {
+    match path {
+        Some(path) => Config::load(&path),
+        None if Path::new("unciv.conf").is_file() => Config::load(Path::new("unciv.conf")),
+        None => Ok(Config::default()),
+    }
+}
@@ -0,0 +1,81 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Building new archives from folders of files.
+
+use cli::{follow_job, unexpected_argument, usage_error, Args, Verbosity};
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use unciv::job::{Job, PackJob};
+use unciv::manifest::{read_manifest, ManifestEntry};
+
+pub fn run(args : &[String]) -> io::Result<()> {
+    let mut in_dir = None;
+    let mut manifest_path = None;
+    let mut out_path = None;
+    let mut dedup = true;
+    let mut progress = false;
+    let mut verbosity = Verbosity::Normal;
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => out_path = Some(PathBuf::from(args.value(&arg)?)),
+            "--no-dedup" => dedup = false,
+            "--progress" => progress = true,
+            "-q" | "--quiet" => verbosity = Verbosity::Quiet,
+            "-v" | "--verbose" => verbosity = Verbosity::Verbose,
+            "--manifest" => manifest_path = Some(PathBuf::from(args.value(&arg)?)),
+            _ if in_dir.is_none() => in_dir = Some(PathBuf::from(arg)),
+            _ => return Err(unexpected_argument(&arg)),
+        }
+    }
+    let out_path = out_path.ok_or_else(|| usage_error("No output archive given (use -o)"))?;
+
+    let entries = match (in_dir, manifest_path) {
+        (None, Some(manifest_path)) => {
+            let dir = manifest_path.parent().unwrap_or(Path::new("."));
+            read_manifest(io::BufReader::new(File::open(&manifest_path)?), dir)?
+        },
+        (Some(in_dir), None) => {
+            // The game's archives are flat, so only the files directly in the
+            // folder are packed, in name order.
+            let mut paths = Vec::new();
+            for dir_entry in std::fs::read_dir(&in_dir)? {
+                let path = dir_entry?.path();
+                if path.is_file() {
+                    paths.push(path);
+                }
+            }
+            paths.sort();
+            paths.iter().map(|path| ManifestEntry::for_file(path)).collect::<io::Result<Vec<_>>>()?
+        },
+        (Some(_), Some(_)) => return Err(usage_error("Give either a folder or --manifest, not both")),
+        (None, None) => return Err(usage_error("No folder to pack given")),
+    };
+
+    let (job_progress, progress_thread) = follow_job(progress, false, verbosity);
+    let summary = PackJob { entries, output : out_path.clone(), dedup }.run(&job_progress);
+    drop(job_progress);
+    let _ = progress_thread.join();
+    let summary = summary?;
+    if verbosity > Verbosity::Quiet {
+        println!("Packed {} files into {} ({} sharing data with an identical file).", summary.files, out_path.display(), summary.duplicates);
+    }
+    Ok(())
+}
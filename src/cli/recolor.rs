@@ -0,0 +1,88 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Recolouring the images in an archive.
+
+use cli::{unexpected_argument, usage_error, Args};
+use std::io;
+use std::path::PathBuf;
+use unciv::glob::glob_match;
+use unciv::recolor::Recolor;
+use unciv::{EntryKind, OpenMode, RimImage, ZfsArchive};
+
+pub fn run(args : &[String]) -> io::Result<()> {
+    let mut map_path = None;
+    let mut filters = Vec::new();
+    let mut paths = Vec::new();
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--map" => map_path = Some(PathBuf::from(args.value(&arg)?)),
+            "--filter" => filters.push(args.value(&arg)?),
+            _ if paths.len() < 2 => paths.push(PathBuf::from(arg)),
+            _ => return Err(unexpected_argument(&arg)),
+        }
+    }
+    let map_path = map_path.ok_or_else(|| usage_error("No colour map given (use --map)"))?;
+    let (in_path, out_path) = match &paths[..] {
+        [in_path, out_path] => (in_path, out_path),
+        _ => return Err(usage_error("An input and an output archive are needed")),
+    };
+    let recolor = Recolor::load(&map_path)?;
+
+    // Copying an archive onto itself would truncate it.
+    if out_path.exists() && std::fs::canonicalize(in_path)? == std::fs::canonicalize(out_path)? {
+        return Err(usage_error("The output archive must be different from the input"));
+    }
+    std::fs::copy(in_path, out_path)?;
+
+    let mut archive = ZfsArchive::open(out_path, OpenMode::ReadWrite)?;
+    let mut recolored = 0;
+    for index in 0..archive.len() {
+        let name = archive.entries()[index].name.clone();
+        if EntryKind::from_name(&name) != EntryKind::Rim {
+            continue;
+        }
+        if !filters.is_empty() && !filters.iter().any(|filter| glob_match(filter, &name)) {
+            continue;
+        }
+        let data = archive.read_data(index)?;
+        let mut image = match RimImage::from_stream(&mut &data[..]) {
+            Ok(image) if image.width > 0 => image,
+            Ok(_) => continue,
+            Err(err) => {
+                eprintln!("unciv: {}: skipping \"{}\": {}", in_path.display(), name, err);
+                continue;
+            },
+        };
+        let changed = recolor.apply(&mut image);
+        if changed == 0 {
+            continue;
+        }
+        // Keep the original pitch, so the entry stays the same size and can
+        // be replaced where it is.
+        let pitch = u16::from_le_bytes([data[12], data[13]]);
+        let mut new_data = Vec::with_capacity(data.len());
+        image.to_stream_aligned(&mut new_data, pitch)?;
+        archive.replace_entry(&name, &new_data)?;
+        println!("Recoloured \"{}\" ({} pixels changed)", name, changed);
+        recolored += 1;
+    }
+    println!("Recoloured {} images into {}.", recolored, out_path.display());
+    Ok(())
+}
@@ -0,0 +1,68 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Rebuilding damaged archives.
+
+use cli::{unexpected_argument, usage_error, Args};
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use unciv::repair::recover;
+
+pub fn run(args : &[String]) -> io::Result<()> {
+    let mut in_path = None;
+    let mut out_path = None;
+    let mut carve = true;
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => out_path = Some(PathBuf::from(args.value(&arg)?)),
+            "--no-carve" => carve = false,
+            _ if in_path.is_none() => in_path = Some(PathBuf::from(arg)),
+            _ => return Err(unexpected_argument(&arg)),
+        }
+    }
+    let in_path = in_path.ok_or_else(|| usage_error("No archive given"))?;
+    let out_path = out_path.ok_or_else(|| usage_error("No output archive given (use -o)"))?;
+    if out_path == in_path {
+        return Err(usage_error("The repaired archive must be written somewhere else"));
+    }
+
+    let data = std::fs::read(&in_path)?;
+    let recovery = recover(&data, carve);
+    let carved = recovery.entries.iter().filter(|entry| entry.carved).count();
+    for entry in recovery.entries.iter().filter(|entry| entry.carved) {
+        println!("Carved \"{}\" ({} bytes)", entry.name, entry.size);
+    }
+
+    let builder = recovery.to_builder(&data)?;
+    let mut writer = io::BufWriter::new(File::create(&out_path)?);
+    builder.write(&mut writer)?;
+    writer.flush()?;
+
+    println!("Recovered {} entries from the file tables and carved {} more, into {}.",
+             recovery.entries.len() - carved, carved, out_path.display());
+    if recovery.dropped > 0 {
+        println!("{} table entries pointed outside the archive or repeated a name, and were dropped.", recovery.dropped);
+    }
+    if recovery.unnamed > 0 {
+        println!("{} carved files were left out, as no name for them fits in the archive's {}-byte names.", recovery.unnamed, recovery.max_filename_len);
+    }
+    Ok(())
+}
@@ -0,0 +1,66 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Editing archives in place, and undoing those edits.
+
+use cli::{unexpected_argument, usage_error, Args};
+use std::io;
+use std::path::Path;
+use unciv::undo::{journal_path, undo_last, JournaledWriter};
+use unciv::{OpenMode, ZfsArchive};
+
+pub fn run(args : &[String]) -> io::Result<()> {
+    let mut args = Args::new(args);
+    let (path, name, data_path) = match (args.next(), args.next(), args.next()) {
+        (Some(path), Some(name), Some(data_path)) => (path, name, data_path),
+        _ => return Err(usage_error("An archive, an entry name, and a file to replace it with are needed")),
+    };
+    if let Some(arg) = args.next() {
+        return Err(unexpected_argument(&arg));
+    }
+
+    let data = std::fs::read(&data_path)?;
+    // Keep anything else (like the game) from reading the archive while
+    // it's being changed.
+    let file = OpenMode::ReadWrite.open(&path)?;
+    let mut archive = ZfsArchive::new(JournaledWriter::new(file, &journal_path(Path::new(&path)))?)?;
+    if let Err(err) = archive.replace_entry(&name, &data) {
+        // Don't leave the archive half changed.
+        archive.into_inner().roll_back()?;
+        return Err(err);
+    }
+    archive.into_inner().commit()?;
+    println!("Replaced \"{}\" in {} with {} ({} bytes).", name, path, data_path, data.len());
+    println!("Run \"unciv undo {}\" to put it back.", path);
+    Ok(())
+}
+
+pub fn undo(args : &[String]) -> io::Result<()> {
+    let mut args = Args::new(args);
+    let path = args.next().ok_or_else(|| usage_error("No archive given"))?;
+    if let Some(arg) = args.next() {
+        return Err(unexpected_argument(&arg));
+    }
+
+    let mut archive = OpenMode::ReadWrite.open(&path)?;
+    if !undo_last(&mut archive, &journal_path(Path::new(&path)))? {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("There are no changes to {} to undo", path)));
+    }
+    println!("Undid the last change to {}.", path);
+    Ok(())
+}
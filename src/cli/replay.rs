@@ -0,0 +1,131 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Replaying a trace of asset reads, to benchmark the order they're made in.
+
+use cli::{human_bytes, unexpected_argument, usage_error, Args};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use unciv::trace::{Access, AccessTrace};
+use unciv::AssetSource;
+
+/// Reads what `access` read, returning the number of bytes read.
+fn replay_access(access : &Access, files : &mut HashMap<PathBuf, File>) -> io::Result<u64> {
+    let (path, offset) = match access.source {
+        Some(AssetSource::Archive { ref path, .. }) => (path, access.offset),
+        Some(AssetSource::File(ref path)) => (path, 0),
+        None => return Ok(0),
+    };
+    if !files.contains_key(path) {
+        files.insert(path.clone(), File::open(path).map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))?);
+    }
+    let file = files.get_mut(path).unwrap();
+    file.seek(SeekFrom::Start(offset))?;
+    let mut data = Vec::with_capacity(access.size as usize);
+    file.take(access.size).read_to_end(&mut data)?;
+    if data.len() as u64 != access.size {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+            format!("{} was {} bytes when recorded, but only {} could be read", access.name, access.size, data.len())));
+    }
+    Ok(data.len() as u64)
+}
+
+pub fn run(args : &[String]) -> io::Result<()> {
+    let mut trace_path = None;
+    let mut timed = false;
+    let mut by_offset = false;
+    let mut repeat = 1;
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--timed" => timed = true,
+            "--order" => by_offset = match args.value(&arg)?.as_str() {
+                "recorded" => false,
+                "offset" => true,
+                order => return Err(usage_error(format!("Unknown order \"{}\" (expected recorded or offset)", order))),
+            },
+            "--repeat" => repeat = match args.value(&arg)?.parse() {
+                Ok(repeat) if repeat > 0 => repeat,
+                _ => return Err(usage_error("The number of repeats must be a positive whole number")),
+            },
+            _ if trace_path.is_none() => trace_path = Some(PathBuf::from(arg)),
+            _ => return Err(unexpected_argument(&arg)),
+        }
+    }
+    let trace_path = trace_path.ok_or_else(|| usage_error("No trace given"))?;
+    if timed && by_offset {
+        return Err(usage_error("--timed can't be used with --order offset"));
+    }
+    let trace = AccessTrace::parse(&std::fs::read_to_string(&trace_path)?)?;
+
+    // Sorting by offset shows how fast loading could be if the data were laid
+    // out in the order it's needed (or vice versa).
+    let mut accesses : Vec<&Access> = trace.accesses.iter().collect();
+    if by_offset {
+        accesses.sort_by_key(|access| match access.source {
+            Some(AssetSource::Archive { ref path, .. }) => (Some(path.clone()), access.offset),
+            Some(AssetSource::File(ref path)) => (Some(path.clone()), 0),
+            None => (None, 0),
+        });
+    }
+    let missing = accesses.iter().filter(|access| access.source.is_none()).count();
+
+    // A jump is a read which doesn't start where the last read from the same
+    // archive finished.
+    let mut ends = HashMap::new();
+    let mut jumps = 0;
+    for access in &accesses {
+        if let Some(AssetSource::Archive { ref path, .. }) = access.source {
+            if ends.insert(path, access.offset + access.size).is_some_and(|end| end != access.offset) {
+                jumps += 1;
+            }
+        }
+    }
+
+    println!("Replaying {} reads from {} ({} of them of assets which weren't found).", accesses.len(), trace_path.display(), missing);
+    for run in 0..repeat {
+        let mut files = HashMap::new();
+        let (mut bytes, mut reading, mut slowest) = (0, Duration::ZERO, Duration::ZERO);
+        let start = Instant::now();
+        for access in &accesses {
+            if timed {
+                if let Some(wait) = access.time.checked_sub(start.elapsed()) {
+                    std::thread::sleep(wait);
+                }
+            }
+            let read_start = Instant::now();
+            bytes += replay_access(access, &mut files)?;
+            let read_time = read_start.elapsed();
+            reading += read_time;
+            slowest = slowest.max(read_time);
+        }
+        // With --timed, most of the time is spent waiting, so only the reads
+        // themselves count towards the speed.
+        let reads = (accesses.len() - missing).max(1) as f64;
+        println!("Run {}: read {} in {:.3}s ({:.3}s spent reading, {}/s); {:.1} µs per read on average, {:.1} µs at most.",
+                 run + 1, human_bytes(bytes as f64), start.elapsed().as_secs_f64(), reading.as_secs_f64(),
+                 human_bytes(bytes as f64 / reading.as_secs_f64().max(1e-9)),
+                 reading.as_secs_f64() * 1e6 / reads, slowest.as_secs_f64() * 1e6);
+    }
+    println!("{} reads jumped to somewhere else in their archive.", jumps);
+    Ok(())
+}
@@ -0,0 +1,82 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Finding archive images which look like a given picture.
+
+use cli::catalog::{build_catalog, image_info};
+use cli::{has_extension, unexpected_argument, usage_error, Args};
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+use unciv::catalog::read_catalog;
+use unciv::phash::hash_distance;
+use unciv::{RimFormat, RimImage};
+
+pub fn run(args : &[String]) -> io::Result<()> {
+    let mut needle_path = None;
+    let mut install_dir = None;
+    let mut catalog_path = None;
+    let mut top = 10;
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--top" => top = match args.value(&arg)?.parse() {
+                Ok(top) if top > 0 => top,
+                _ => return Err(usage_error("--top requires a positive whole number")),
+            },
+            "--catalog" => catalog_path = Some(PathBuf::from(args.value(&arg)?)),
+            _ if needle_path.is_none() => needle_path = Some(PathBuf::from(arg)),
+            _ if install_dir.is_none() => install_dir = Some(PathBuf::from(arg)),
+            _ => return Err(unexpected_argument(&arg)),
+        }
+    }
+    let needle_path = needle_path.ok_or_else(|| usage_error("No image to look for given"))?;
+
+    // Loose RIMs can be looked for too, not just PNGs.
+    let needle_image = if has_extension(&needle_path, "rim") {
+        RimImage::from_stream(&mut io::BufReader::new(File::open(&needle_path)?))?
+    } else {
+        RimImage::from_png(io::BufReader::new(File::open(&needle_path)?), RimFormat::Rgb565)?
+    };
+    let needle = match image_info(&needle_image)? {
+        Some(info) => info.dhash,
+        None => return Err(io::Error::new(io::ErrorKind::InvalidData, "The image to look for is empty")),
+    };
+
+    // A prebuilt catalog saves decoding every image in the install.
+    let catalog = match (catalog_path, install_dir) {
+        (Some(catalog_path), None) => read_catalog(io::BufReader::new(File::open(catalog_path)?))?,
+        (None, Some(install_dir)) => build_catalog(&install_dir, true, false)?,
+        (Some(_), Some(_)) => return Err(usage_error("Give either an install directory or --catalog, not both")),
+        (None, None) => return Err(usage_error("An install directory or --catalog is needed")),
+    };
+
+    let mut matches : Vec<_> = catalog.iter()
+        .filter_map(|entry| entry.image.map(|image| (hash_distance(needle, image.dhash), image, entry.location())))
+        .collect();
+    if matches.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "No images found to compare against (was the catalog built with --hashes?)"));
+    }
+
+    matches.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.2.cmp(&b.2)));
+    println!("{:>8}  {:>11}  Image", "Distance", "Size");
+    for (distance, image, location) in matches.iter().take(top) {
+        println!("{:>8}  {:>11}  {}", distance, format!("{}×{}", image.width, image.height), location);
+    }
+    Ok(())
+}
@@ -0,0 +1,69 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Checking archives for structural problems.
+
+use cli::{follow_job, usage_error, Verbosity};
+use std::io;
+use std::path::PathBuf;
+use unciv::job::{Job, VerifyJob, VerifyOutcome};
+
+pub fn run(args : &[String]) -> io::Result<()> {
+    let mut paths = Vec::new();
+    let mut progress = false;
+    let mut verbosity = Verbosity::Normal;
+    for arg in args {
+        match arg.as_str() {
+            "--progress" => progress = true,
+            "-q" | "--quiet" => verbosity = Verbosity::Quiet,
+            "-v" | "--verbose" => verbosity = Verbosity::Verbose,
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    if paths.is_empty() {
+        return Err(usage_error("No archive given"));
+    }
+
+    let (job_progress, progress_thread) = follow_job(progress, false, verbosity);
+    let outcomes = VerifyJob { paths }.run(&job_progress);
+    drop(job_progress);
+    let _ = progress_thread.join();
+
+    let mut total = 0;
+    for (path, outcome) in outcomes? {
+        match outcome {
+            VerifyOutcome::Unreadable(err) => {
+                println!("{}: can't be read: {}", path.display(), err);
+                total += 1;
+            },
+            VerifyOutcome::Checked { entries, problems } => {
+                if problems.is_empty() && verbosity > Verbosity::Quiet {
+                    println!("{}: OK ({} entries)", path.display(), entries);
+                }
+                for problem in &problems {
+                    println!("{}: {}", path.display(), problem);
+                }
+                total += problems.len();
+            },
+        }
+    }
+    if total > 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} problems found", total)));
+    }
+    Ok(())
+}
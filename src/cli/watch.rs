@@ -0,0 +1,159 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! `unciv watch`, which keeps an archive up to date with a folder of files
+//! being edited.
+
+use cli::{unexpected_argument, usage_error, Args};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use unciv::ZfsBuilder;
+
+/// A file's size and modification time, which change when it's edited.
+type FileState = (u64, SystemTime);
+
+/// A file's data, as it was when the archive was last built.
+struct CachedFile
+{
+    state : FileState,
+    data : Vec<u8>,
+}
+
+/// Where the archive is written before it's moved over `out_path`.
+fn temp_path(out_path : &Path) -> PathBuf {
+    let mut temp_path = out_path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    PathBuf::from(temp_path)
+}
+
+/// Lists the files directly in `dir` (which is what `pack` packs), with
+/// their states, sorted by path. The archive itself is left out, in case
+/// it's being written into the folder.
+fn scan(dir : &Path, out_path : &Path) -> io::Result<Vec<(PathBuf, FileState)>> {
+    let temp_path = temp_path(out_path);
+    let mut files = Vec::new();
+    for dir_entry in std::fs::read_dir(dir)? {
+        let path = dir_entry?.path();
+        let metadata = match std::fs::metadata(&path) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => continue,
+        };
+        if let Ok(canonical_path) = path.canonicalize() {
+            if canonical_path == out_path || canonical_path == temp_path {
+                continue;
+            }
+        }
+        files.push((path, (metadata.len(), metadata.modified()?)));
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Builds the archive from `files`, reading only those which have changed
+/// since they were cached. The archive is written next to `out_path` and
+/// then moved over it, so nothing reading it sees half an archive.
+///
+/// Returns how many files were read.
+fn rebuild(files : &[(PathBuf, FileState)], cache : &mut HashMap<PathBuf, CachedFile>, out_path : &Path, dedup : bool) -> io::Result<usize> {
+    cache.retain(|path, _| files.iter().any(|(file, _)| file == path));
+    let mut read = 0;
+    let mut builder = ZfsBuilder::new();
+    builder.set_dedup(dedup);
+    for (path, state) in files {
+        if cache.get(path).map(|cached| cached.state) != Some(*state) {
+            let data = std::fs::read(path)?;
+            cache.insert(path.clone(), CachedFile { state : *state, data });
+            read += 1;
+        }
+        let name = path.file_name().and_then(|name| name.to_str()).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("{}: its name isn't valid UTF-8", path.display()))
+        })?;
+        builder.add(name, cache[path].data.clone(), state.1)?;
+    }
+
+    let temp_path = temp_path(out_path);
+    let result = File::create(&temp_path).and_then(|file| {
+        let mut writer = io::BufWriter::new(file);
+        builder.write(&mut writer)?;
+        writer.flush()
+    }).and_then(|()| std::fs::rename(&temp_path, out_path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result.map(|()| read)
+}
+
+pub fn run(args : &[String]) -> io::Result<()> {
+    let mut in_dir = None;
+    let mut out_path = None;
+    let mut dedup = true;
+    let mut interval = Duration::from_secs(1);
+    let mut args = Args::new(args);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--no-dedup" => dedup = false,
+            "--interval" => interval = match args.value(&arg)?.parse::<f64>() {
+                Ok(secs) if secs > 0.0 => Duration::from_secs_f64(secs),
+                _ => return Err(usage_error("--interval requires a positive number of seconds")),
+            },
+            _ if in_dir.is_none() => in_dir = Some(PathBuf::from(arg)),
+            _ if out_path.is_none() => out_path = Some(PathBuf::from(arg)),
+            _ => return Err(unexpected_argument(&arg)),
+        }
+    }
+    let in_dir = in_dir.ok_or_else(|| usage_error("No folder to watch given"))?;
+    let out_path = out_path.ok_or_else(|| usage_error("No archive to build given"))?;
+    if !in_dir.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} isn't a folder", in_dir.display())));
+    }
+    // The archive may not exist yet, so find where it'll be from its folder.
+    let out_dir = match out_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.canonicalize()?,
+        _ => std::env::current_dir()?,
+    };
+    let out_path = out_dir.join(out_path.file_name().ok_or_else(|| usage_error("The archive to build needs a file name"))?);
+
+    println!("Watching \"{}\" and rebuilding {} as it changes (press Ctrl+C to stop)…", in_dir.display(), out_path.display());
+
+    // Like convert-daemon, only rebuild once the folder has stopped changing
+    // between polls, so files which are still being saved aren't picked up.
+    let mut cache = HashMap::new();
+    let mut last_seen = None;
+    let mut built = None;
+    loop {
+        let files = scan(&in_dir, &out_path)?;
+        let settled = last_seen.is_none() || last_seen.as_ref() == Some(&files);
+        if settled && built.as_ref() != Some(&files) {
+            let start = Instant::now();
+            match rebuild(&files, &mut cache, &out_path, dedup) {
+                Ok(read) => println!("Rebuilt {} with {} entries ({} read from disk) in {:.2}s.",
+                                     out_path.display(), files.len(), read, start.elapsed().as_secs_f64()),
+                Err(err) => eprintln!("unciv: couldn't rebuild {}: {}", out_path.display(), err),
+            }
+            // Whether or not that worked, wait for something else to change
+            // before trying again.
+            built = Some(files.clone());
+        }
+        last_seen = Some(files);
+        std::thread::sleep(interval);
+    }
+}
@@ -0,0 +1,234 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! unciv's configuration file.
+//!
+//! This is a simple INI-style file (which happens to also be valid TOML for
+//! the things we use), for example:
+//!
+//! ```text
+//! # Treat these as text, not opaque data.
+//! [types]
+//! "*.dat" = "text"
+//! # This one is known to be broken, so don't try to convert it.
+//! "up99.rim" = "raw"
+//! "junk*" = "skip"
+//!
+//! # Refuse anything bigger than this (see `Limits` for the rest).
+//! [limits]
+//! max_entry_size = 16777216
+//! ```
+
+use glob::glob_match;
+use kind::EntryKind;
+use limits::Limits;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A section of an INI-style file, with its keys and values in file order.
+#[derive(Clone, Debug, Default)]
+pub struct IniSection
+{
+    pub name : String,
+    pub entries : Vec<(String, String)>,
+}
+
+pub(crate) fn unquote(s : &str) -> &str {
+    let s = s.trim();
+    if s.len() >= 2 && ((s.starts_with('"') && s.ends_with('"')) || (s.starts_with('\'') && s.ends_with('\''))) {
+        &s[1..s.len() - 1]
+    } else {
+        s
+    }
+}
+
+/// Parses an INI-style file. Keys before the first `[section]` end up in a
+/// section with an empty name.
+pub fn parse_ini(text : &str) -> io::Result<Vec<IniSection>> {
+    let mut sections = vec![IniSection::default()];
+    for (line_num, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            sections.push(IniSection { name : line[1..line.len() - 1].trim().to_string(), entries : Vec::new() });
+            continue;
+        }
+        // Keys may be quoted (and contain '='), so look for the '=' after any quotes.
+        let search_from = match line.strip_prefix('"') {
+            Some(rest) => rest.find('"').map(|i| i + 2).unwrap_or(0),
+            None => 0,
+        };
+        match line[search_from..].find('=') {
+            Some(eq) => {
+                let (key, value) = line.split_at(search_from + eq);
+                sections.last_mut().unwrap().entries.push((unquote(key).to_string(), unquote(&value[1..]).to_string()));
+            },
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("line {}: expected \"key = value\" or \"[section]\"", line_num + 1))),
+        }
+    }
+    Ok(sections)
+}
+
+/// What to do with entries matching a type override.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ForcedType
+{
+    /// Handle the entry as this kind, regardless of what it looks like.
+    Kind(EntryKind),
+    /// Don't extract the entry at all.
+    Skip,
+}
+
+impl FromStr for ForcedType
+{
+    type Err = io::Error;
+
+    fn from_str(name : &str) -> io::Result<ForcedType> {
+        if name.eq_ignore_ascii_case("skip") {
+            Ok(ForcedType::Skip)
+        } else {
+            name.parse().map(ForcedType::Kind)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TypeOverride
+{
+    pub pattern : String,
+    pub forced : ForcedType,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Config
+{
+    pub type_overrides : Vec<TypeOverride>,
+    /// The limits archives are read within.
+    pub limits : Limits,
+}
+
+impl Config
+{
+    pub fn parse(text : &str) -> io::Result<Config> {
+        let mut config = Config::default();
+        for section in parse_ini(text)? {
+            match section.name.as_str() {
+                "types" => for (pattern, value) in section.entries {
+                    config.type_overrides.push(TypeOverride { pattern, forced : value.parse()? });
+                },
+                "limits" => for (key, value) in section.entries {
+                    let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Invalid value \"{}\" for {}", value, key));
+                    match key.as_str() {
+                        "max_entries" => config.limits.max_entries = value.parse().map_err(|_| invalid())?,
+                        "max_entry_size" => config.limits.max_entry_size = value.parse().map_err(|_| invalid())?,
+                        "max_image_width" => config.limits.max_image_width = value.parse().map_err(|_| invalid())?,
+                        "max_image_height" => config.limits.max_image_height = value.parse().map_err(|_| invalid())?,
+                        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown limit \"{}\"", key))),
+                    }
+                },
+                "" if section.entries.is_empty() => {},
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown config section \"{}\"", section.name))),
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn load(path : &Path) -> io::Result<Config> {
+        let text = std::fs::read_to_string(path)?;
+        Config::parse(&text).map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))
+    }
+
+    /// Finds the override for an entry name, if any. Earlier overrides in the
+    /// file take priority.
+    pub fn forced_type(&self, name : &str) -> Option<ForcedType> {
+        self.type_overrides.iter().find(|o| glob_match(&o.pattern, name)).map(|o| o.forced)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const EXAMPLE : &str = r#"
+# Treat these as text, not opaque data.
+[types]
+"*.dat" = "text"
+# This one is known to be broken, so don't try to convert it.
+"up99.rim" = "raw"
+"junk*" = "skip"
+
+# Refuse anything bigger than this (see `Limits` for the rest).
+[limits]
+max_entry_size = 16777216
+"#;
+
+    fn error(text : &str) -> String {
+        Config::parse(text).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn the_example_parses() {
+        let config = Config::parse(EXAMPLE).unwrap();
+        let overrides : Vec<(&str, ForcedType)> = config.type_overrides.iter().map(|o| (o.pattern.as_str(), o.forced)).collect();
+        assert_eq!(overrides, [
+            ("*.dat", ForcedType::Kind(EntryKind::Text)),
+            ("up99.rim", ForcedType::Kind(EntryKind::Other)),
+            ("junk*", ForcedType::Skip),
+        ]);
+        assert_eq!(config.limits, Limits { max_entry_size : 16777216, ..Limits::default() });
+    }
+
+    #[test]
+    fn quoted_keys_may_hold_equals_signs() {
+        let sections = parse_ini("[types]\n\"a=b*\" = 'text'\nplain=value = more\n").unwrap();
+        assert_eq!(sections[1].name, "types");
+        assert_eq!(sections[1].entries, [
+            ("a=b*".to_string(), "text".to_string()),
+            ("plain".to_string(), "value = more".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn earlier_overrides_win() {
+        let config = Config::parse("[types]\n\"up99.rim\" = \"raw\"\n\"*.rim\" = \"skip\"\n").unwrap();
+        assert_eq!(config.forced_type("up99.rim"), Some(ForcedType::Kind(EntryKind::Other)));
+        assert_eq!(config.forced_type("UP01.RIM"), Some(ForcedType::Skip));
+        assert_eq!(config.forced_type("readme.txt"), None);
+    }
+
+    #[test]
+    fn every_limit_can_be_set() {
+        let config = Config::parse("[limits]\nmax_entries = 10\nmax_entry_size = 20\nmax_image_width = 30\nmax_image_height = 40\n").unwrap();
+        assert_eq!(config.limits, Limits { max_entries : 10, max_entry_size : 20, max_image_width : 30, max_image_height : 40 });
+    }
+
+    #[test]
+    fn mistakes_are_reported() {
+        assert_eq!(error("[colours]\nred = 1\n"), "Unknown config section \"colours\"");
+        assert_eq!(error("stray = 1\n"), "Unknown config section \"\"");
+        assert_eq!(error("[limits]\nmax_files = 10\n"), "Unknown limit \"max_files\"");
+        assert_eq!(error("[limits]\nmax_entries = lots\n"), "Invalid value \"lots\" for max_entries");
+        assert_eq!(error("[types]\n\"*.dat\" = \"spreadsheet\"\n"), "Unknown entry type \"spreadsheet\"");
+        assert_eq!(error("[types]\njust a pattern\n"), "line 2: expected \"key = value\" or \"[section]\"");
+    }
+}
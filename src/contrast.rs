@@ -0,0 +1,118 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Checking how readable text is over the game's UI images, using the
+//! WCAG definitions of luminance and contrast ratio.
+
+use ldl::{LdlBlock, LdlFile, LdlValue};
+use recolor::parse_color;
+use rim::{ColorKey, RimImage};
+
+/// The relative luminance of an sRGB colour, from 0 (black) to 1 (white).
+pub fn relative_luminance(rgb : [u8; 3]) -> f64 {
+    let [r, g, b] = rgb.map(|c| {
+        let c = c as f64 / 255.0;
+        if c <= 0.03928 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    });
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// The contrast ratio between two luminances, from 1 (none) to 21 (black
+/// on white). WCAG asks for at least 4.5 for normal text.
+pub fn contrast_ratio(a : f64, b : f64) -> f64 {
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+/// The average luminance of an image, ignoring pixels matching `color_key`,
+/// or None if every pixel is transparent.
+pub fn mean_luminance(image : &RimImage, color_key : ColorKey) -> Option<f64> {
+    let mut total = 0.0;
+    let mut count = 0;
+    for &px in &image.data {
+        if color_key.matches(px, image.format) {
+            continue;
+        }
+        let [r, g, b, _] = image.format.decode_pixel(px);
+        total += relative_luminance([r, g, b]);
+        count += 1;
+    }
+    if count == 0 {
+        None
+    } else {
+        Some(total / count as f64)
+    }
+}
+
+/// A UI element which shows text, and the images it's drawn over.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextElement
+{
+    /// The names of the block and its parents, like "Window.Button".
+    pub path : String,
+    pub text : String,
+    /// The colour given by a "textcolor" attribute: "#rrggbb", or an int
+    /// holding 0xRRGGBB.
+    pub text_color : Option<[u8; 3]>,
+    /// The images (named by string attributes ending in .rim) of the
+    /// element, or of its nearest parent which has any.
+    pub images : Vec<String>,
+}
+
+fn block_images(block : &LdlBlock) -> Vec<String> {
+    block.attributes.iter().filter_map(|attribute| match &attribute.value {
+        LdlValue::String(value) | LdlValue::Word(value) if value.to_ascii_lowercase().ends_with(".rim") => Some(value.clone()),
+        _ => None,
+    }).collect()
+}
+
+fn text_color(block : &LdlBlock) -> Option<[u8; 3]> {
+    let attribute = block.attributes.iter().find(|attribute| attribute.name.eq_ignore_ascii_case("textcolor"))?;
+    match &attribute.value {
+        LdlValue::Int(rgb) => Some([(rgb >> 16) as u8, (rgb >> 8) as u8, *rgb as u8]),
+        LdlValue::String(value) | LdlValue::Word(value) => parse_color(value).ok(),
+        _ => None,
+    }
+}
+
+fn find_text_elements(block : &LdlBlock, parent_path : &str, parent_images : &[String], elements : &mut Vec<TextElement>) {
+    let path = if parent_path.is_empty() { block.name.clone() } else { format!("{}.{}", parent_path, block.name) };
+    let mut images = block_images(block);
+    if images.is_empty() {
+        images = parent_images.to_vec();
+    }
+    let text = block.attributes.iter().find(|attribute| attribute.name.eq_ignore_ascii_case("text"));
+    if let Some(text) = text {
+        let text = match &text.value {
+            LdlValue::String(value) | LdlValue::Word(value) => value.clone(),
+            other => format!("{:?}", other),
+        };
+        elements.push(TextElement { path : path.clone(), text, text_color : text_color(block), images : images.clone() });
+    }
+    for child in &block.children {
+        find_text_elements(child, &path, &images, elements);
+    }
+}
+
+/// Finds the blocks in a layout which have a "text" attribute.
+pub fn text_elements(file : &LdlFile) -> Vec<TextElement> {
+    let mut elements = Vec::new();
+    for block in &file.blocks {
+        find_text_elements(block, "", &[], &mut elements);
+    }
+    elements
+}
@@ -0,0 +1,75 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Decoders for formats unciv doesn't know about itself.
+//!
+//! When extracting, each entry's name and first few bytes are offered to
+//! the decoders in `ExtractOptions::decoders`, in order. The first one which
+//! claims the entry converts it, instead of unciv's own handling. Decoders
+//! can be written in Rust, or loaded from shared libraries with the
+//! `plugins` feature (see `plugin`).
+
+use std::fmt;
+use std::io;
+
+/// How many bytes from the start of an entry's data are given to `probe()`
+/// (or fewer, if the entry is smaller).
+pub const PROBE_LEN : usize = 64;
+
+/// What a decoder turned an entry into.
+#[derive(Clone, Debug)]
+pub struct Decoded
+{
+    pub data : Vec<u8>,
+    /// The extension added to the entry's name (without the dot), e.g. "png".
+    pub extension : String,
+}
+
+/// Something which converts entries of a particular format when they're
+/// extracted.
+///
+/// Decoders may be used from several extraction threads at once.
+pub trait Decoder : Send + Sync
+{
+    /// The name shown when an entry is converted by this decoder.
+    fn name(&self) -> &str;
+
+    /// Returns true if this decoder handles the entry called `name`, whose
+    /// data starts with `header` (up to `PROBE_LEN` bytes).
+    fn probe(&self, name : &str, header : &[u8]) -> bool;
+
+    /// Converts the data of the entry called `name`.
+    fn decode(&self, name : &str, data : &[u8]) -> io::Result<Decoded>;
+}
+
+impl fmt::Debug for dyn Decoder
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Decoder({:?})", self.name())
+    }
+}
+
+/// Checks that an extension from a decoder can be safely added to a file
+/// name.
+pub(crate) fn check_extension(decoder : &dyn Decoder, extension : &str) -> io::Result<()> {
+    if extension.is_empty() || extension.contains(['/', '\\', '.', '\0']) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+            format!("{} gave the extension \"{}\", which can't be used in a file name", decoder.name(), extension)));
+    }
+    Ok(())
+}
@@ -0,0 +1,127 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Comparing two versions of an archive, e.g. before and after a patch.
+
+use rim::{ColorKey, RimImage};
+use std::io;
+use std::io::{Read, Seek};
+use ZfsArchive;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeKind
+{
+    Added,
+    Removed,
+    Modified,
+}
+
+impl ChangeKind
+{
+    pub fn name(self) -> &'static str {
+        match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Modified => "modified",
+        }
+    }
+}
+
+/// An entry which differs between two archives.
+#[derive(Clone, Debug)]
+pub struct EntryChange
+{
+    pub name : String,
+    pub kind : ChangeKind,
+    /// The entry's index in the new archive, unless it was removed.
+    pub new_index : Option<usize>,
+    pub old_size : Option<usize>,
+    pub new_size : Option<usize>,
+}
+
+/// Compares the entries of two archives by name and contents.
+///
+/// Added and modified entries are listed in the new archive's order,
+/// followed by removed entries in the old archive's order. Entries which are
+/// the same in both aren't listed. Only the contents are compared, not the
+/// timestamps, as those change whenever an archive is rebuilt.
+pub fn diff_archives<A, B>(old : &mut ZfsArchive<A>, new : &mut ZfsArchive<B>) -> io::Result<Vec<EntryChange>>
+where A : Read + Seek, B : Read + Seek
+{
+    let mut changes = Vec::new();
+    for new_index in 0..new.len() {
+        let (name, new_size) = {
+            let entry = &new.entries()[new_index];
+            (entry.name.clone(), entry.size)
+        };
+        let kind = match old.index_of(&name) {
+            None => ChangeKind::Added,
+            Some(old_index) if old.entries()[old_index].size != new_size => ChangeKind::Modified,
+            Some(old_index) => {
+                if old.read_data(old_index)? == new.read_data(new_index)? {
+                    continue;
+                }
+                ChangeKind::Modified
+            },
+        };
+        changes.push(EntryChange {
+            old_size : old.by_name(&name).map(|entry| entry.size),
+            name,
+            kind,
+            new_index : Some(new_index),
+            new_size : Some(new_size),
+        });
+    }
+    for entry in old.entries() {
+        if new.index_of(&entry.name).is_none() {
+            changes.push(EntryChange {
+                name : entry.name.clone(),
+                kind : ChangeKind::Removed,
+                new_index : None,
+                old_size : Some(entry.size),
+                new_size : None,
+            });
+        }
+    }
+    Ok(changes)
+}
+
+/// The transparent gap between the images in `side_by_side()`.
+const SIDE_BY_SIDE_GAP : usize = 4;
+
+/// Puts two versions of an image next to each other (the old one on the
+/// left), for comparing by eye. Returns the width, height, and 8-bit RGBA
+/// pixels of the combined image.
+pub fn side_by_side(old : &RimImage, new : &RimImage, color_key : ColorKey) -> io::Result<(u32, u32, Vec<u8>)> {
+    let (old_width, new_width) = (old.width as usize, new.width as usize);
+    let width = old_width + SIDE_BY_SIDE_GAP + new_width;
+    let height = old.height.max(new.height) as usize;
+    let mut rgba = vec![0; width * height * 4];
+    for (image, left) in [(old, 0), (new, old_width + SIDE_BY_SIDE_GAP)] {
+        let pixels = image.to_rgba_bytes_with(color_key)?;
+        let row_bytes = image.width as usize * 4;
+        if row_bytes == 0 {
+            continue;
+        }
+        for (y, row) in pixels.chunks_exact(row_bytes).enumerate() {
+            let start = (y * width + left) * 4;
+            rgba[start..start + row_bytes].copy_from_slice(row);
+        }
+    }
+    Ok((width as u32, height as u32, rgba))
+}
@@ -0,0 +1,63 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Conversions between `RimImage` and the `image` crate's `DynamicImage`,
+//! enabled by the "image" feature.
+
+use image::{DynamicImage, RgbaImage};
+use std::convert::TryFrom;
+use std::io;
+use RimFormat;
+use RimImage;
+
+impl<'a> TryFrom<&'a RimImage> for DynamicImage
+{
+    type Error = io::Error;
+
+    fn try_from(rim : &'a RimImage) -> io::Result<DynamicImage> {
+        let rgba = rim.to_rgba_bytes()?;
+        match RgbaImage::from_raw(rim.width as u32, rim.height as u32, rgba) {
+            Some(image) => Ok(DynamicImage::ImageRgba8(image)),
+            None => Err(io::Error::new(io::ErrorKind::InvalidData, "RIM image data doesn't match its dimensions")),
+        }
+    }
+}
+
+/// Converts to RGB565, the format most of the game's images use. Use
+/// `RimImage::from_dynamic_image()` to pick the format.
+impl<'a> TryFrom<&'a DynamicImage> for RimImage
+{
+    type Error = io::Error;
+
+    fn try_from(image : &'a DynamicImage) -> io::Result<RimImage> {
+        RimImage::from_dynamic_image(image, RimFormat::Rgb565)
+    }
+}
+
+impl RimImage
+{
+    /// Converts any image to the given 16-bit format. Alpha is discarded.
+    pub fn from_dynamic_image(image : &DynamicImage, format : RimFormat) -> io::Result<RimImage> {
+        if image.width() > u16::MAX as u32 || image.height() > u16::MAX as u32 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("{}×{} is too large for a RIM image", image.width(), image.height())));
+        }
+        let rgba = image.to_rgba8();
+        RimImage::from_rgba_bytes(image.width() as u16, image.height() as u16, rgba.as_raw(), format)
+    }
+}
@@ -0,0 +1,179 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Encoders for the image formats unciv exports, working on 8-bit RGBA pixels.
+//!
+//! Other than PNG, these only write the simplest form of each format
+//! (uncompressed 32-bit TGA and BMP, and QOI), which is all that's needed to
+//! export images.
+
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+use std::io;
+use std::io::Write;
+
+fn check_rgba(width : u32, height : u32, rgba : &[u8]) -> io::Result<()> {
+    let expected = width as u64 * height as u64 * 4;
+    if rgba.len() as u64 != expected {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+            format!("RGBA data for a {}×{} image should be {} bytes, not {}", width, height, expected, rgba.len())));
+    }
+    Ok(())
+}
+
+/// Swaps RGBA pixels to the BGRA order TGA and BMP use.
+fn rgba_to_bgra(rgba : &[u8]) -> Vec<u8> {
+    let mut bgra = Vec::with_capacity(rgba.len());
+    for px in rgba.chunks_exact(4) {
+        bgra.extend_from_slice(&[px[2], px[1], px[0], px[3]]);
+    }
+    bgra
+}
+
+/// Writes an 8-bit RGBA PNG.
+pub fn write_png(writer : impl Write, width : u32, height : u32, rgba : &[u8]) -> io::Result<()> {
+    check_rgba(width, height, rgba)?;
+    let mut png_encoder = png::Encoder::new(writer, width, height);
+    // Note: Newer versions of the 'png' library call this 'Rgba'.
+    png_encoder.set_color(png::ColorType::RGBA);
+    png_encoder.set_depth(png::BitDepth::Eight);
+
+    let mut px_writer = png_encoder.write_header()?;
+    px_writer.write_image_data(rgba)?;
+    Ok(())
+}
+
+/// Writes an uncompressed 32-bit Truevision TGA.
+pub fn write_tga(writer : &mut impl Write, width : u32, height : u32, rgba : &[u8]) -> io::Result<()> {
+    check_rgba(width, height, rgba)?;
+    if width > u16::MAX as u32 || height > u16::MAX as u32 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{}×{} is too large for a TGA image", width, height)));
+    }
+    // No image ID or colour map, and uncompressed true-colour data.
+    writer.write_all(&[0, 0, 2])?;
+    writer.write_all(&[0; 5])?;
+    // The origin.
+    writer.write_u16::<LittleEndian>(0)?;
+    writer.write_u16::<LittleEndian>(0)?;
+    writer.write_u16::<LittleEndian>(width as u16)?;
+    writer.write_u16::<LittleEndian>(height as u16)?;
+    // 32 bits per pixel, with 8 bits of alpha, stored top row first.
+    writer.write_all(&[32, 0x28])?;
+    writer.write_all(&rgba_to_bgra(rgba))
+}
+
+/// Writes an uncompressed 32-bit Windows bitmap, with a V4 header so that
+/// the alpha channel is kept.
+pub fn write_bmp(writer : &mut impl Write, width : u32, height : u32, rgba : &[u8]) -> io::Result<()> {
+    check_rgba(width, height, rgba)?;
+    const FILE_HEADER_SIZE : u32 = 14;
+    const INFO_HEADER_SIZE : u32 = 108;
+    let data_size = rgba.len() as u64;
+    if width > i32::MAX as u32 || height > i32::MAX as u32 || data_size + (FILE_HEADER_SIZE + INFO_HEADER_SIZE) as u64 > u32::MAX as u64 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{}×{} is too large for a BMP image", width, height)));
+    }
+
+    writer.write_all(b"BM")?;
+    writer.write_u32::<LittleEndian>(FILE_HEADER_SIZE + INFO_HEADER_SIZE + data_size as u32)?;
+    writer.write_u32::<LittleEndian>(0)?;
+    writer.write_u32::<LittleEndian>(FILE_HEADER_SIZE + INFO_HEADER_SIZE)?;
+
+    writer.write_u32::<LittleEndian>(INFO_HEADER_SIZE)?;
+    writer.write_i32::<LittleEndian>(width as i32)?;
+    // A negative height means the top row comes first.
+    writer.write_i32::<LittleEndian>(-(height as i32))?;
+    writer.write_u16::<LittleEndian>(1)?;
+    writer.write_u16::<LittleEndian>(32)?;
+    // BI_BITFIELDS, with the masks below.
+    writer.write_u32::<LittleEndian>(3)?;
+    writer.write_u32::<LittleEndian>(data_size as u32)?;
+    // 72 DPI, and no palette.
+    writer.write_i32::<LittleEndian>(2835)?;
+    writer.write_i32::<LittleEndian>(2835)?;
+    writer.write_u32::<LittleEndian>(0)?;
+    writer.write_u32::<LittleEndian>(0)?;
+    for mask in [0x00ff0000u32, 0x0000ff00, 0x000000ff, 0xff000000] {
+        writer.write_u32::<LittleEndian>(mask)?;
+    }
+    // 'sRGB', which makes the endpoints and gamma (left as zero) unused.
+    writer.write_u32::<LittleEndian>(0x73524742)?;
+    writer.write_all(&[0; 48])?;
+    writer.write_all(&rgba_to_bgra(rgba))
+}
+
+fn qoi_hash(px : [u8; 4]) -> usize {
+    (px[0] as usize * 3 + px[1] as usize * 5 + px[2] as usize * 7 + px[3] as usize * 11) % 64
+}
+
+/// Writes a "Quite OK Image", which is much faster to encode and decode than
+/// PNG, for intermediate files.
+pub fn write_qoi(writer : &mut impl Write, width : u32, height : u32, rgba : &[u8]) -> io::Result<()> {
+    check_rgba(width, height, rgba)?;
+    writer.write_all(b"qoif")?;
+    writer.write_u32::<BigEndian>(width)?;
+    writer.write_u32::<BigEndian>(height)?;
+    // RGBA, sRGB with linear alpha.
+    writer.write_all(&[4, 0])?;
+
+    let mut out = Vec::with_capacity(rgba.len() / 2);
+    let mut index = [[0u8; 4]; 64];
+    let mut prev = [0u8, 0, 0, 255];
+    let mut run = 0u8;
+    let num_pixels = rgba.len() / 4;
+    for (i, px) in rgba.chunks_exact(4).enumerate() {
+        let px = [px[0], px[1], px[2], px[3]];
+        if px == prev {
+            run += 1;
+            if run == 62 || i + 1 == num_pixels {
+                out.push(0xc0 | (run - 1));
+                run = 0;
+            }
+            continue;
+        }
+        if run > 0 {
+            out.push(0xc0 | (run - 1));
+            run = 0;
+        }
+
+        let hash = qoi_hash(px);
+        if index[hash] == px {
+            out.push(hash as u8);
+        } else {
+            index[hash] = px;
+            if px[3] == prev[3] {
+                let dr = px[0].wrapping_sub(prev[0]) as i8;
+                let dg = px[1].wrapping_sub(prev[1]) as i8;
+                let db = px[2].wrapping_sub(prev[2]) as i8;
+                let dr_dg = dr.wrapping_sub(dg);
+                let db_dg = db.wrapping_sub(dg);
+                if (-2..=1).contains(&dr) && (-2..=1).contains(&dg) && (-2..=1).contains(&db) {
+                    out.push(0x40 | (((dr + 2) as u8) << 4) | (((dg + 2) as u8) << 2) | (db + 2) as u8);
+                } else if (-32..=31).contains(&dg) && (-8..=7).contains(&dr_dg) && (-8..=7).contains(&db_dg) {
+                    out.push(0x80 | (dg + 32) as u8);
+                    out.push((((dr_dg + 8) as u8) << 4) | (db_dg + 8) as u8);
+                } else {
+                    out.extend_from_slice(&[0xfe, px[0], px[1], px[2]]);
+                }
+            } else {
+                out.extend_from_slice(&[0xff, px[0], px[1], px[2], px[3]]);
+            }
+        }
+        prev = px;
+    }
+    writer.write_all(&out)?;
+    writer.write_all(&[0, 0, 0, 0, 0, 0, 0, 1])
+}
@@ -0,0 +1,386 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Extracting entries to the file system.
+//!
+//! This is kept apart from the archive format itself, so that the rest of
+//! the library can be built for targets with no file system (such as
+//! WebAssembly).
+
+use decoder::{check_extension, Decoder, PROBE_LEN};
+use job::{extract_outcome, JobEvent};
+use paths::{converted_path, entry_output_path, plan_collisions, raw16_metadata_path, Collision};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use {ColorKey, Crc32Reader, EntryKind, ExtractOptions, ExtractRecord, ExtractReport, ExtractStatus, ForcedType, ImageFormat, Limits, Provenance, RimImage};
+use {sanitize_entry_name, ZfsEntry, ZfsFile};
+
+impl ZfsEntry
+{
+    /// Works out where this entry should be extracted to under `dir`.
+    ///
+    /// The name is sanitized so that it can't escape `dir`.
+    pub fn output_path(&self, dir : &Path) -> io::Result<PathBuf> {
+        match sanitize_entry_name(&self.name) {
+            Some(relative) => Ok(dir.join(relative)),
+            None => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Entry name \"{}\" can't be used as a file name", self.name))),
+        }
+    }
+
+    /// Writes the entry out unchanged, returning the number of bytes written.
+    /// It always goes into the current directory: to extract into
+    /// `options.output_dir`, use `ZfsFile::extract_where`.
+    pub fn extract_file(&self, reader : &mut (impl Read + Seek)) -> io::Result<u64> {
+        let out_path = self.output_path(Path::new("."))?;
+        self.extract_file_from(&mut self.reader(reader)?, &out_path)
+    }
+
+    /// Writes the entry out unchanged to `out_path`, from a reader over its data.
+    pub fn extract_file_from(&self, data : &mut impl Read, out_path : &Path) -> io::Result<u64> {
+        self.write_file(data, out_path)
+    }
+
+    fn extract_message(&self) -> String {
+        format!("Extracting file \"{}\"…", self.name)
+    }
+
+    fn write_file(&self, data : &mut impl Read, out_path : &Path) -> io::Result<u64> {
+        let out_file = File::create(out_path)?;
+        let mut writer = io::BufWriter::new(&out_file);
+        let written = io::copy(data, &mut writer)?;
+        writer.flush()?;
+        if written != self.size as u64 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                format!("Entry is {} bytes, but only {} could be read", self.size, written)));
+        }
+        #[cfg(feature = "set-timestamps")]
+        out_file.set_modified(self.timestamp)?;
+        Ok(written)
+    }
+    
+    /// Converts the entry from RIM to PNG, returning the size of the PNG. It
+    /// always goes into the current directory: to extract into
+    /// `options.output_dir`, use `ZfsFile::extract_where`.
+    pub fn extract_rim_image(&self, reader : &mut (impl Read + Seek)) -> io::Result<u64> {
+        let out_path = self.output_path(Path::new("."))?;
+        // Never read past the end of the entry, even if the RIM header claims otherwise.
+        self.extract_rim_image_as(&mut self.reader(reader)?, ImageFormat::Png, &out_path)
+    }
+
+    /// Converts the entry from RIM to the given format, from a reader over
+    /// its data, returning the number of bytes written.
+    ///
+    /// `out_path` is where the entry itself would be extracted to: converted
+    /// images have the format's extension added to it.
+    pub fn extract_rim_image_as(&self, data : &mut impl Read, format : ImageFormat, out_path : &Path) -> io::Result<u64> {
+        let (_, written) = self.write_rim_image(data, format, ColorKey::None, &Limits::default(), out_path)?;
+        Ok(written)
+    }
+
+    /// Does the work of `extract_rim_image_as()`, returning a description of
+    /// the image rather than printing it.
+    fn write_rim_image(&self, data : &mut impl Read, format : ImageFormat, color_key : ColorKey, limits : &Limits, out_path : &Path) -> io::Result<(String, u64)> {
+        let mut rim_data = Vec::new();
+        data.read_to_end(&mut rim_data)?;
+        let image = RimImage::from_stream_with_limits(&mut &rim_data[..], limits)?;
+        
+        let message = format!("Converting RIM v{} ({}) image \"{}\" ({}×{})…", image.version, image.format.name(), self.name, image.width, image.height);
+        
+        let out_file = File::create(converted_path(out_path, format))?;
+        let mut writer = io::BufWriter::new(&out_file);
+        image.write_as(&mut writer, format, color_key)?;
+        writer.flush()?;
+        #[cfg(feature = "set-timestamps")]
+        out_file.set_modified(self.timestamp)?;
+        let mut written = out_file.metadata()?.len();
+
+        if format == ImageFormat::Raw16 {
+            let metadata = image.raw16_metadata().to_pretty_string();
+            std::fs::write(raw16_metadata_path(out_path), &metadata)?;
+            written += metadata.len() as u64;
+        }
+        if format == ImageFormat::RimCopy {
+            let mut rim_file = File::create(out_path)?;
+            rim_file.write_all(&rim_data)?;
+            #[cfg(feature = "set-timestamps")]
+            rim_file.set_modified(self.timestamp)?;
+            written += rim_data.len() as u64;
+        }
+        Ok((message, written))
+    }
+
+    /// Converts the entry with `decoder`, writing it next to `out_path` with
+    /// the decoder's extension, and returns a description of what was done,
+    /// the number of bytes written, and where they were written.
+    fn write_decoded(&self, decoder : &dyn Decoder, data : &mut impl Read, out_path : &Path) -> io::Result<(String, u64, PathBuf)> {
+        let mut input = Vec::new();
+        data.read_to_end(&mut input)?;
+        if input.len() != self.size {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                format!("Entry is {} bytes, but only {} could be read", self.size, input.len())));
+        }
+        let decoded = decoder.decode(&self.name, &input)?;
+        check_extension(decoder, &decoded.extension)?;
+
+        let mut decoded_path = out_path.as_os_str().to_owned();
+        decoded_path.push(".");
+        decoded_path.push(&decoded.extension);
+        let decoded_path = PathBuf::from(decoded_path);
+        let out_file = File::create(&decoded_path)?;
+        (&out_file).write_all(&decoded.data)?;
+        #[cfg(feature = "set-timestamps")]
+        out_file.set_modified(self.timestamp)?;
+        let message = format!("Decoding \"{}\" with {}…", self.name, decoder.name());
+        Ok((message, decoded.data.len() as u64, decoded_path))
+    }
+
+    /// What the entry will be extracted as, or None if `options` say it
+    /// should be skipped.
+    pub(crate) fn planned_kind(&self, options : &ExtractOptions) -> Option<EntryKind> {
+        match options.config.forced_type(&self.name) {
+            Some(ForcedType::Skip) => None,
+            Some(ForcedType::Kind(kind)) => Some(kind),
+            None => Some(EntryKind::from_name(&self.name)),
+        }
+    }
+
+    /// Extracts the entry as `options` says it should be, given a way to get
+    /// at its data, and records what happened.
+    ///
+    /// Nothing is printed: the progress line is kept in the record, so that
+    /// callers can log entries in a consistent order.
+    ///
+    /// `collision` is what was planned for the entry if it would land on the
+    /// same file as an earlier one.
+    pub(crate) fn extract_with<R : Read>(&self, index : usize, options : &ExtractOptions, collision : Option<&Collision>,
+                                         open_data : impl FnOnce() -> io::Result<R>) -> ExtractRecord {
+        options.progress.send(JobEvent::StepStarted { step : index, name : self.name.clone(), bytes : self.size as u64 });
+        let record = self.extract_entry(index, options, collision, open_data);
+        options.progress.send(JobEvent::StepFinished {
+            step : index,
+            name : self.name.clone(),
+            bytes : self.size as u64,
+            outcome : extract_outcome(&record.status, &record.message),
+        });
+        record
+    }
+
+    fn extract_entry<R : Read>(&self, index : usize, options : &ExtractOptions, collision : Option<&Collision>,
+                               open_data : impl FnOnce() -> io::Result<R>) -> ExtractRecord {
+        let mut record = ExtractRecord {
+            index,
+            name : self.name.clone(),
+            status : ExtractStatus::Skipped,
+            bytes_read : 0,
+            bytes_written : 0,
+            crc32 : None,
+            message : None,
+        };
+        let kind = match self.planned_kind(options) {
+            Some(kind) => kind,
+            None => return record,
+        };
+        options.progress.debug(|| format!("Entry {} \"{}\": offset {}, size {}, timestamp {}, flags {}, unknown field 0x{:08x}, record at {}",
+                                           index, self.name, self.offset, self.size, self.zfs_timestamp(), self.flags, self.unknown, self.table_entry_offset));
+        if !self.flags.is_empty() {
+            options.progress.warn(format!("\"{}\" has flags {}, which unciv doesn't understand, so it's extracted as it's stored", self.name, self.flags));
+        }
+        let out_path = match collision {
+            Some(Collision::Skip) => return record,
+            Some(Collision::Rename(renamed)) => Ok(renamed.clone()),
+            None => entry_output_path(self, index, kind, options),
+        };
+        let result = out_path.and_then(|out_path| {
+            if let Some(parent) = out_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let mut data = Crc32Reader::new(open_data()?);
+            let mut header = Vec::new();
+            if !options.decoders.is_empty() {
+                (&mut data).take(PROBE_LEN as u64).read_to_end(&mut header)?;
+            }
+            let decoder = options.decoders.iter().find(|decoder| decoder.probe(&self.name, &header));
+            // The entry's own file isn't written when it's converted, except
+            // for the untouched copy of a RIM which RimCopy keeps.
+            let (message, written, converted_to) = {
+                let mut data = (&header[..]).chain(&mut data);
+                if let Some(decoder) = decoder {
+                    let (message, written, path) = self.write_decoded(&**decoder, &mut data, &out_path)?;
+                    (message, written, Some(path))
+                } else if kind == EntryKind::Rim {
+                    let (message, written) = self.write_rim_image(&mut data, options.image_format, options.color_key, &options.config.limits, &out_path)?;
+                    (message, written, Some(converted_path(&out_path, options.image_format)))
+                } else {
+                    (self.extract_message(), self.write_file(&mut data, &out_path)?, None)
+                }
+            };
+            let kept_original = converted_to.is_none() || (decoder.is_none() && options.image_format == ImageFormat::RimCopy);
+            record.crc32 = Some(data.crc32());
+            if let Some(&expected) = options.expected_crc32s.get(&index) {
+                if expected != data.crc32() {
+                    // Don't leave damaged data behind looking like a good
+                    // extract.
+                    let raw16_metadata = decoder.is_none() && kind == EntryKind::Rim && options.image_format == ImageFormat::Raw16;
+                    let written_paths = [
+                        Some(out_path.clone()).filter(|_| kept_original),
+                        converted_to.clone(),
+                        Some(raw16_metadata_path(&out_path)).filter(|_| raw16_metadata),
+                    ];
+                    for path in written_paths.iter().flatten() {
+                        std::fs::remove_file(path)?;
+                    }
+                    return Err(io::Error::new(io::ErrorKind::InvalidData,
+                        format!("Read data with CRC-32 {:08x}, but it should be {:08x}: the archive may be damaged, so nothing was written", data.crc32(), expected)));
+                }
+            }
+
+            if let Some(ref archive) = options.provenance {
+                let provenance = Provenance { archive : archive.clone(), index, offset : self.offset, crc32 : data.crc32() };
+                if kept_original {
+                    provenance.write_to(&out_path)?;
+                }
+                if let Some(ref converted_to) = converted_to {
+                    provenance.write_to(converted_to)?;
+                }
+            }
+            Ok((message, written, converted_to.is_some()))
+        });
+        record.bytes_read = self.size as u64;
+        match result {
+            Ok((message, bytes_written, converted)) => {
+                record.message = Some(message);
+                record.status = if converted { ExtractStatus::Converted } else { ExtractStatus::Extracted };
+                record.bytes_written = bytes_written;
+            },
+            Err(err) => record.status = ExtractStatus::Failed(err.to_string()),
+        }
+        record
+    }
+}
+
+impl ZfsFile
+{
+    /// Extracts every entry into the current directory, with the default
+    /// `ExtractOptions`.
+    ///
+    /// Entries which fail to extract don't stop the rest of the archive from
+    /// being extracted: they're recorded as failed in the returned report.
+    pub fn extract_all(&self, reader : &mut (impl Read + Seek)) -> ExtractReport {
+        self.extract_all_with(reader, &ExtractOptions::default())
+    }
+
+    /// Extracts every entry into `dir`, creating it if needed.
+    pub fn extract_to_dir(&self, reader : &mut (impl Read + Seek), dir : &Path) -> io::Result<ExtractReport> {
+        std::fs::create_dir_all(dir)?;
+        let options = ExtractOptions { output_dir : dir.to_path_buf(), ..ExtractOptions::default() };
+        Ok(self.extract_all_with(reader, &options))
+    }
+
+    /// Extracts every entry into `options.output_dir`, with the given options.
+    pub fn extract_all_with(&self, reader : &mut (impl Read + Seek), options : &ExtractOptions) -> ExtractReport {
+        self.extract_where(reader, options, |_| true)
+    }
+
+    /// Extracts the entries for which `selected` returns true (given their
+    /// index), and which match `options`' patterns.
+    pub fn extract_where(&self, reader : &mut (impl Read + Seek), options : &ExtractOptions, selected : impl Fn(usize) -> bool) -> ExtractReport {
+        let start = std::time::Instant::now();
+        let wanted : Vec<usize> = (0..self.files.len()).filter(|&index| selected(index) && options.selects(index, &self.files[index].name)).collect();
+        self.send_begin(&wanted, options);
+        let collisions = plan_collisions(&self.files, &wanted, options);
+        let mut report = ExtractReport::default();
+        for index in wanted {
+            let i = &self.files[index];
+            let record = i.extract_with(index, options, collisions.get(&index), || i.reader(reader));
+            record.log(&options.progress);
+            report.push(record);
+        }
+        report.stats.elapsed = start.elapsed();
+        report
+    }
+
+    /// Extracts every entry with the given options, using up to `jobs`
+    /// threads.
+    ///
+    /// Each thread reads the archive through its own reader from
+    /// `open_reader` (e.g. by opening the file again), so the threads never
+    /// have to share a file position. Progress lines and the report are
+    /// still in table order, whichever entries finish first.
+    pub fn extract_all_parallel<R, F>(&self, open_reader : F, options : &ExtractOptions, jobs : usize) -> io::Result<ExtractReport>
+    where R : Read + Seek + Send, F : Fn() -> io::Result<R>
+    {
+        let start = std::time::Instant::now();
+        let wanted : Vec<usize> = (0..self.files.len()).filter(|&index| options.selects(index, &self.files[index].name)).collect();
+        self.send_begin(&wanted, options);
+        let collisions = plan_collisions(&self.files, &wanted, options);
+        let jobs = jobs.clamp(1, wanted.len().max(1));
+        let mut readers = Vec::with_capacity(jobs);
+        for _ in 0..jobs {
+            readers.push(open_reader()?);
+        }
+
+        let next = AtomicUsize::new(0);
+        let mut report = ExtractReport::default();
+        std::thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel();
+            for mut reader in readers {
+                let sender = sender.clone();
+                let (next, wanted, collisions) = (&next, &wanted, &collisions);
+                scope.spawn(move || loop {
+                    let position = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(&index) = wanted.get(position) else { break };
+                    let entry = &self.files[index];
+                    let record = entry.extract_with(index, options, collisions.get(&index), || entry.reader(&mut reader));
+                    if sender.send((position, record)).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(sender);
+
+            // Hold back records which finish early, so that they're logged in
+            // the same order every time.
+            let mut finished = BTreeMap::new();
+            let mut next_to_log = 0;
+            for (position, record) in receiver {
+                finished.insert(position, record);
+                while let Some(record) = finished.remove(&next_to_log) {
+                    record.log(&options.progress);
+                    report.push(record);
+                    next_to_log += 1;
+                }
+            }
+        });
+        report.stats.elapsed = start.elapsed();
+        Ok(report)
+    }
+
+    /// Tells anyone following `options`' progress how much is about to be
+    /// extracted.
+    fn send_begin(&self, wanted : &[usize], options : &ExtractOptions) {
+        let bytes = wanted.iter().map(|&index| self.files[index].size as u64).sum();
+        options.progress.send(JobEvent::Begin { steps : Some(wanted.len()), bytes : Some(bytes) });
+        options.progress.debug(|| format!("ZFS3 version {}: {} entries, {}-byte names, {} entries per file table, {}-byte records",
+                                          self.version, self.files.len(), self.max_filename_len, self.files_per_table, self.record_size()));
+    }
+}
@@ -0,0 +1,353 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! A C API, for programs which aren't written in Rust (like the CTP2
+//! source code) to read archives and images with. `include/unciv.h` is
+//! generated from this file with cbindgen.
+//!
+//! Functions which can fail return `UNCIV_OK` or one of the negative error
+//! codes (or NULL, for `unciv_archive_open()`), and `unciv_last_error()`
+//! describes what went wrong. A panic inside unciv doesn't unwind into the
+//! caller: it's reported as `UNCIV_ERR_PANIC` instead.
+
+use rim::{ColorKey, RimImage};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+use OpenMode;
+use ZfsArchive;
+
+pub const UNCIV_OK : c_int = 0;
+/// Reading a file failed.
+pub const UNCIV_ERR_IO : c_int = -1;
+/// An archive or image is corrupt, or not what it should be.
+pub const UNCIV_ERR_INVALID_DATA : c_int = -2;
+/// An argument was NULL, out of range, or otherwise invalid.
+pub const UNCIV_ERR_INVALID_ARGUMENT : c_int = -3;
+/// The buffer given is too small for what was to be written to it.
+pub const UNCIV_ERR_BUFFER_TOO_SMALL : c_int = -4;
+/// There's no entry with the name given.
+pub const UNCIV_ERR_NOT_FOUND : c_int = -5;
+/// Something went wrong inside unciv itself (a bug, rather than a problem
+/// with the arguments or files given).
+pub const UNCIV_ERR_PANIC : c_int = -6;
+
+thread_local! {
+    static LAST_ERROR : RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_last_error(message : &str) {
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = message);
+}
+
+/// Records an error for `unciv_last_error()`, and returns its code.
+fn fail(code : c_int, message : &str) -> c_int {
+    set_last_error(message);
+    code
+}
+
+fn fail_io(err : io::Error) -> c_int {
+    let code = match err.kind() {
+        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => UNCIV_ERR_INVALID_DATA,
+        io::ErrorKind::InvalidInput => UNCIV_ERR_INVALID_ARGUMENT,
+        _ => UNCIV_ERR_IO,
+    };
+    fail(code, &err.to_string())
+}
+
+/// Runs the body of an exported function, so that a panic becomes an error
+/// instead of unwinding into C (which is undefined behaviour). A panic
+/// returns `on_panic`, with the panic's message as the last error.
+fn catch_panic<T>(on_panic : T, body : impl FnOnce() -> T) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = payload.downcast_ref::<&str>().copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("no details");
+            set_last_error(&format!("unciv panicked: {}", message));
+            on_panic
+        },
+    }
+}
+
+/// An open archive.
+pub struct UncivArchive
+{
+    archive : ZfsArchive<io::BufReader<File>>,
+    /// The entries' names, kept here so that the pointers handed out by
+    /// `unciv_archive_entry()` stay valid until the archive is closed.
+    names : Vec<CString>,
+}
+
+/// Details of an entry in an archive.
+#[repr(C)]
+pub struct UncivEntry
+{
+    /// The entry's name, which is valid until the archive is closed.
+    pub name : *const c_char,
+    pub offset : u32,
+    pub size : u32,
+    /// When the entry was modified, in seconds since 1970.
+    pub timestamp : u64,
+    /// The entry's flags, as they are in the file table.
+    pub flags : u32,
+}
+
+/// Describes the last error on this thread. The string is valid until the
+/// next call into unciv on this thread.
+#[no_mangle]
+pub extern "C" fn unciv_last_error() -> *const c_char {
+    catch_panic(ptr::null(), || {
+        LAST_ERROR.with(|last_error| last_error.borrow().as_ptr())
+    })
+}
+
+/// Opens the archive at `path` for reading, returning NULL if it can't be.
+///
+/// # Safety
+///
+/// `path` must be NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn unciv_archive_open(path : *const c_char) -> *mut UncivArchive {
+    catch_panic(ptr::null_mut(), || {
+        if path.is_null() {
+            fail(UNCIV_ERR_INVALID_ARGUMENT, "No path given");
+            return ptr::null_mut();
+        }
+        let path = match CStr::from_ptr(path).to_str() {
+            Ok(path) => path,
+            Err(_) => {
+                fail(UNCIV_ERR_INVALID_ARGUMENT, "The path isn't valid UTF-8");
+                return ptr::null_mut();
+            },
+        };
+        let archive = match OpenMode::ReadOnly.open(path).and_then(|file| ZfsArchive::new(io::BufReader::new(file))) {
+            Ok(archive) => archive,
+            Err(err) => {
+                fail_io(err);
+                return ptr::null_mut();
+            },
+        };
+        let names = archive.entries().iter()
+            .map(|entry| CString::new(entry.name.replace('\0', "")).unwrap_or_default())
+            .collect();
+        Box::into_raw(Box::new(UncivArchive { archive, names }))
+    })
+}
+
+/// Closes an archive. Closing NULL does nothing.
+///
+/// # Safety
+///
+/// `archive` must be NULL or have come from `unciv_archive_open()`, and not
+/// have been closed already.
+#[no_mangle]
+pub unsafe extern "C" fn unciv_archive_close(archive : *mut UncivArchive) {
+    catch_panic((), || {
+        if !archive.is_null() {
+            drop(Box::from_raw(archive));
+        }
+    })
+}
+
+/// The number of entries in an archive.
+///
+/// # Safety
+///
+/// `archive` must be an open archive.
+#[no_mangle]
+pub unsafe extern "C" fn unciv_archive_len(archive : *const UncivArchive) -> usize {
+    catch_panic(0, || {
+        match archive.as_ref() {
+            Some(archive) => archive.archive.len(),
+            None => 0,
+        }
+    })
+}
+
+/// Fills in `entry` with the details of the entry at `index`.
+///
+/// # Safety
+///
+/// `archive` must be an open archive, and `entry` must point to an
+/// `UncivEntry`.
+#[no_mangle]
+pub unsafe extern "C" fn unciv_archive_entry(archive : *const UncivArchive, index : usize, entry : *mut UncivEntry) -> c_int {
+    catch_panic(UNCIV_ERR_PANIC, || {
+        let (Some(archive), Some(entry)) = (archive.as_ref(), entry.as_mut()) else {
+            return fail(UNCIV_ERR_INVALID_ARGUMENT, "No archive or entry given");
+        };
+        let Some(details) = archive.archive.by_index(index) else {
+            return fail(UNCIV_ERR_INVALID_ARGUMENT, &format!("There's no entry {} (the archive has {})", index, archive.archive.len()));
+        };
+        *entry = UncivEntry {
+            name : archive.names[index].as_ptr(),
+            offset : details.offset as u32,
+            size : details.size as u32,
+            timestamp : details.zfs_timestamp() as u64,
+            flags : details.flags.raw(),
+        };
+        UNCIV_OK
+    })
+}
+
+/// Finds the entry called exactly `name`, and stores its index in
+/// `index`.
+///
+/// # Safety
+///
+/// `archive` must be an open archive, `name` a NUL-terminated string, and
+/// `index` must point to a `size_t`.
+#[no_mangle]
+pub unsafe extern "C" fn unciv_archive_find(archive : *const UncivArchive, name : *const c_char, index : *mut usize) -> c_int {
+    catch_panic(UNCIV_ERR_PANIC, || {
+        let (Some(archive), false, Some(index)) = (archive.as_ref(), name.is_null(), index.as_mut()) else {
+            return fail(UNCIV_ERR_INVALID_ARGUMENT, "No archive, name, or index given");
+        };
+        let name = CStr::from_ptr(name).to_string_lossy();
+        match archive.archive.index_of(&name) {
+            Some(found) => {
+                *index = found;
+                UNCIV_OK
+            },
+            None => fail(UNCIV_ERR_NOT_FOUND, &format!("There's no entry called \"{}\"", name)),
+        }
+    })
+}
+
+/// Reads the data of the entry at `index` into `buffer`, which must be at
+/// least as big as the entry (see `unciv_archive_entry()`).
+///
+/// # Safety
+///
+/// `archive` must be an open archive, and `buffer` must point to at least
+/// `buffer_len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn unciv_archive_read(archive : *mut UncivArchive, index : usize, buffer : *mut u8, buffer_len : usize) -> c_int {
+    catch_panic(UNCIV_ERR_PANIC, || {
+        let (Some(archive), false) = (archive.as_mut(), buffer.is_null()) else {
+            return fail(UNCIV_ERR_INVALID_ARGUMENT, "No archive or buffer given");
+        };
+        let Some(size) = archive.archive.by_index(index).map(|entry| entry.size) else {
+            return fail(UNCIV_ERR_INVALID_ARGUMENT, &format!("There's no entry {} (the archive has {})", index, archive.archive.len()));
+        };
+        if buffer_len < size {
+            return fail(UNCIV_ERR_BUFFER_TOO_SMALL, &format!("The entry is {} bytes, but the buffer is only {}", size, buffer_len));
+        }
+        match archive.archive.read_data(index) {
+            Ok(data) => {
+                ptr::copy_nonoverlapping(data.as_ptr(), buffer, data.len());
+                UNCIV_OK
+            },
+            Err(err) => fail_io(err),
+        }
+    })
+}
+
+/// Reads a RIM image from `data`, which is `len` bytes long.
+unsafe fn read_rim(data : *const u8, len : usize) -> Result<RimImage, c_int> {
+    if data.is_null() {
+        return Err(fail(UNCIV_ERR_INVALID_ARGUMENT, "No image data given"));
+    }
+    RimImage::from_stream(&mut slice::from_raw_parts(data, len)).map_err(fail_io)
+}
+
+/// Reads the size of the RIM image in `data` (e.g. an entry's data from
+/// `unciv_archive_read()`).
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes, and `width` and `height` to
+/// `uint16_t`s.
+#[no_mangle]
+pub unsafe extern "C" fn unciv_rim_info(data : *const u8, len : usize, width : *mut u16, height : *mut u16) -> c_int {
+    catch_panic(UNCIV_ERR_PANIC, || {
+        let (false, false) = (width.is_null(), height.is_null()) else {
+            return fail(UNCIV_ERR_INVALID_ARGUMENT, "No width or height given");
+        };
+        match read_rim(data, len) {
+            Ok(image) => {
+                *width = image.width;
+                *height = image.height;
+                UNCIV_OK
+            },
+            Err(code) => code,
+        }
+    })
+}
+
+/// Decodes the RIM image in `data` into 8-bit RGBA pixels, which need
+/// width × height × 4 bytes of `rgba`.
+///
+/// `color_key` says which pixels become transparent: "none" (or NULL),
+/// "magenta", "zero", or a raw pixel value like "0x7c1f".
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes, `color_key` must be NULL or a
+/// NUL-terminated string, and `rgba` must point to `rgba_len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn unciv_rim_decode_rgba(data : *const u8, len : usize, color_key : *const c_char, rgba : *mut u8, rgba_len : usize) -> c_int {
+    catch_panic(UNCIV_ERR_PANIC, || {
+        if rgba.is_null() {
+            return fail(UNCIV_ERR_INVALID_ARGUMENT, "No pixel buffer given");
+        }
+        let color_key = if color_key.is_null() {
+            ColorKey::None
+        } else {
+            match CStr::from_ptr(color_key).to_string_lossy().parse() {
+                Ok(color_key) => color_key,
+                Err(err) => return fail_io(err),
+            }
+        };
+        let image = match read_rim(data, len) {
+            Ok(image) => image,
+            Err(code) => return code,
+        };
+        let pixels = match image.to_rgba_bytes_with(color_key) {
+            Ok(pixels) => pixels,
+            Err(err) => return fail_io(err),
+        };
+        if rgba_len < pixels.len() {
+            return fail(UNCIV_ERR_BUFFER_TOO_SMALL, &format!("The image needs {} bytes, but the buffer is only {}", pixels.len(), rgba_len));
+        }
+        ptr::copy_nonoverlapping(pixels.as_ptr(), rgba, pixels.len());
+        UNCIV_OK
+    })
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn panics_become_errors() {
+        let code = catch_panic(UNCIV_ERR_PANIC, || -> c_int { panic!("oops") });
+        assert_eq!(code, UNCIV_ERR_PANIC);
+        let message = unsafe { CStr::from_ptr(unciv_last_error()) };
+        assert_eq!(message.to_str().unwrap(), "unciv panicked: oops");
+    }
+}
@@ -0,0 +1,163 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Index caches, which save an archive's parsed file table next to it
+//! (`gl.zfs.idx` for `gl.zfs`), so that it can be opened again without
+//! walking the chain of file tables.
+//!
+//! An index starts with `UNCIVIDX`, its format version, and the size and
+//! modification time the archive had when it was written. It's only used
+//! while the archive still has that size and time. Then come the archive's
+//! layout, the header's entry count, unknown field, and first file table
+//! offset, the number of entries, and each entry's name (as a length and
+//! bytes), offset, size, timestamp, flags, unknown field, and record offset,
+//! all little endian.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use timestamp;
+use OpenMode;
+use RawHeader;
+use ZfsEntry;
+use ZfsEntryFlags;
+use ZfsFile;
+
+const INDEX_MAGIC : &[u8; 8] = b"UNCIVIDX";
+const INDEX_VERSION : u32 = 2;
+
+/// Where the index for an archive is kept.
+pub fn index_path(archive_path : &Path) -> PathBuf {
+    let mut path = archive_path.as_os_str().to_owned();
+    path.push(".idx");
+    PathBuf::from(path)
+}
+
+/// What an index is only valid for: the archive's size, and its
+/// modification time as seconds and nanoseconds since 1970.
+fn archive_key(archive_path : &Path) -> io::Result<(u64, u64, u32)> {
+    let metadata = std::fs::metadata(archive_path)?;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+    Ok((metadata.len(), modified.as_secs(), modified.subsec_nanos()))
+}
+
+fn read_key(reader : &mut impl Read) -> io::Result<(u64, u64, u32)> {
+    Ok((reader.read_u64::<LittleEndian>()?, reader.read_u64::<LittleEndian>()?, reader.read_u32::<LittleEndian>()?))
+}
+
+impl ZfsFile
+{
+    /// Saves the file table of the archive at `archive_path` (which this
+    /// must have been read from) to its index.
+    pub fn write_index(&self, archive_path : impl AsRef<Path>) -> io::Result<()> {
+        let archive_path = archive_path.as_ref();
+        let (size, secs, nanos) = archive_key(archive_path)?;
+        let mut writer = io::BufWriter::new(File::create(index_path(archive_path))?);
+        writer.write_all(INDEX_MAGIC)?;
+        writer.write_u32::<LittleEndian>(INDEX_VERSION)?;
+        writer.write_u64::<LittleEndian>(size)?;
+        writer.write_u64::<LittleEndian>(secs)?;
+        writer.write_u32::<LittleEndian>(nanos)?;
+        let raw_header = self.raw_header;
+        for field in [self.version, self.max_filename_len, self.files_per_table, self.record_fields_size,
+                      raw_header.num_files, raw_header.unknown, raw_header.filetable_offset] {
+            writer.write_u32::<LittleEndian>(field)?;
+        }
+        writer.write_u32::<LittleEndian>(self.files.len() as u32)?;
+        for entry in &self.files {
+            writer.write_u16::<LittleEndian>(entry.name.len() as u16)?;
+            writer.write_all(entry.name.as_bytes())?;
+            writer.write_u64::<LittleEndian>(entry.offset as u64)?;
+            writer.write_u64::<LittleEndian>(entry.size as u64)?;
+            writer.write_u32::<LittleEndian>(entry.zfs_timestamp())?;
+            writer.write_u32::<LittleEndian>(entry.flags.raw())?;
+            writer.write_u32::<LittleEndian>(entry.unknown)?;
+            writer.write_u64::<LittleEndian>(entry.table_entry_offset)?;
+        }
+        writer.flush()
+    }
+
+    /// Loads the file table of the archive at `archive_path` from its index,
+    /// or returns None if it has no index, or the archive has changed since
+    /// the index was written.
+    pub fn from_index(archive_path : impl AsRef<Path>) -> io::Result<Option<ZfsFile>> {
+        let archive_path = archive_path.as_ref();
+        let mut reader = match File::open(index_path(archive_path)) {
+            Ok(file) => io::BufReader::new(file),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != INDEX_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} isn't an unciv index", index_path(archive_path).display())));
+        }
+        // Indexes are only a cache, so one from another version of unciv is
+        // as good as a stale one.
+        if reader.read_u32::<LittleEndian>()? != INDEX_VERSION || read_key(&mut reader)? != archive_key(archive_path)? {
+            return Ok(None);
+        }
+        let version = reader.read_u32::<LittleEndian>()?;
+        let max_filename_len = reader.read_u32::<LittleEndian>()?;
+        let files_per_table = reader.read_u32::<LittleEndian>()?;
+        let record_fields_size = reader.read_u32::<LittleEndian>()?;
+        let raw_header = RawHeader {
+            version,
+            max_filename_len,
+            files_per_table,
+            num_files : reader.read_u32::<LittleEndian>()?,
+            unknown : reader.read_u32::<LittleEndian>()?,
+            filetable_offset : reader.read_u32::<LittleEndian>()?,
+        };
+        let num_files = reader.read_u32::<LittleEndian>()?;
+        let mut files = Vec::new();
+        for _ in 0..num_files {
+            let mut name = vec![0; reader.read_u16::<LittleEndian>()? as usize];
+            reader.read_exact(&mut name)?;
+            let name = String::from_utf8(name).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            files.push(ZfsEntry {
+                name,
+                offset : reader.read_u64::<LittleEndian>()? as usize,
+                size : reader.read_u64::<LittleEndian>()? as usize,
+                timestamp : timestamp::from_zfs(reader.read_u32::<LittleEndian>()?),
+                flags : ZfsEntryFlags::from_raw(reader.read_u32::<LittleEndian>()?),
+                unknown : reader.read_u32::<LittleEndian>()?,
+                table_entry_offset : reader.read_u64::<LittleEndian>()?,
+            });
+        }
+        Ok(Some(ZfsFile { version, max_filename_len, files_per_table, record_fields_size, raw_header, files }))
+    }
+
+    /// Loads the file table of the archive at `archive_path`, from its index
+    /// if it has an up-to-date one, or from the archive itself otherwise.
+    /// Indexes aren't written or updated, so this is the same as
+    /// `from_stream()` unless `write_index()` has been used.
+    ///
+    /// An index which can't be read is ignored, as the archive itself can
+    /// still be.
+    pub fn open_indexed(archive_path : impl AsRef<Path>) -> io::Result<ZfsFile> {
+        let archive_path = archive_path.as_ref();
+        if let Ok(Some(zfs_file)) = ZfsFile::from_index(archive_path) {
+            return Ok(zfs_file);
+        }
+        ZfsFile::from_stream(&mut io::BufReader::new(OpenMode::ReadOnly.open(archive_path)?))
+    }
+}
@@ -0,0 +1,341 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Long-running operations (extracting, packing, verifying, and converting)
+//! as jobs, which report their progress the same way whatever is running
+//! them: the command line, or a GUI embedding the crate.
+//!
+//! A job is set up as a plain struct, and then `Job::run()` does the work,
+//! sending `JobEvent`s to a `Progress` as it goes and returning the job's
+//! results at the end. Jobs don't print anything themselves: what they have
+//! to say is sent as `Log` events, for whatever's running them to show, save,
+//! or drop.
+
+use builder::ZfsBuilder;
+use manifest::ManifestEntry;
+use report::{ExtractReport, ExtractStatus};
+use limits::Limits;
+use rim::{ColorKey, RimImage};
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use timestamp;
+use validate::{validate_archive, Problem};
+use ExtractOptions;
+use OpenMode;
+use ZfsEntry;
+use ZfsEntryFlags;
+use ZfsFile;
+
+/// How a step of a job turned out.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StepOutcome
+{
+    /// The step was done, with a description of what was done, if there's
+    /// more to say than its name (e.g. "Converting RIM v1 (RGB555) image…").
+    Done(Option<String>),
+    Skipped,
+    Failed(String),
+}
+
+/// How much detail a `JobEvent::Log` message goes into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel
+{
+    /// What's being done, such as each entry being extracted.
+    Info,
+    /// Details for working out what went wrong, or for looking into the
+    /// format, such as each entry's record.
+    Debug,
+}
+
+/// Something which happened while running a job.
+///
+/// Every job sends `Begin`, then `StepStarted` and `StepFinished` for each
+/// step (e.g. each entry extracted, or each file packed), with `Warning`s
+/// along the way. There's no event for the end of the job: that's when
+/// `Job::run()` returns. When steps run in parallel, their events are
+/// interleaved.
+#[derive(Clone, Debug)]
+pub enum JobEvent
+{
+    /// How many steps there are, and how many bytes they'll read, if that's
+    /// known up front (it isn't when reading a `ZfsStream`).
+    Begin { steps : Option<usize>, bytes : Option<u64> },
+    /// `step` identifies the step within the job: for extraction, it's the
+    /// entry's index, and otherwise it counts up from zero.
+    StepStarted { step : usize, name : String, bytes : u64 },
+    StepFinished { step : usize, name : String, bytes : u64, outcome : StepOutcome },
+    /// Something which didn't stop the job, but which someone should know.
+    Warning(String),
+    /// A line of commentary on what the job is doing.
+    Log(LogLevel, String),
+}
+
+/// Where a job sends its events, if anywhere.
+#[derive(Clone, Debug, Default)]
+pub struct Progress
+{
+    sender : Option<Sender<JobEvent>>,
+}
+
+impl Progress
+{
+    /// Sends events to `sender`. The job carries on if the receiver goes
+    /// away.
+    pub fn new(sender : Sender<JobEvent>) -> Progress {
+        Progress { sender : Some(sender) }
+    }
+
+    /// Drops every event.
+    pub fn none() -> Progress {
+        Progress::default()
+    }
+
+    pub fn send(&self, event : JobEvent) {
+        if let Some(ref sender) = self.sender {
+            let _ = sender.send(event);
+        }
+    }
+
+    pub fn warn(&self, message : impl Into<String>) {
+        self.send(JobEvent::Warning(message.into()));
+    }
+
+    pub fn info(&self, message : impl Into<String>) {
+        self.send(JobEvent::Log(LogLevel::Info, message.into()));
+    }
+
+    /// Sends a `Debug` message. As these are only sometimes wanted, the
+    /// message is only made if someone's listening.
+    pub fn debug(&self, message : impl FnOnce() -> String) {
+        if self.sender.is_some() {
+            self.send(JobEvent::Log(LogLevel::Debug, message()));
+        }
+    }
+
+    /// Runs a step, sending events for its start and end. A step which fails
+    /// doesn't fail the job: the error is returned for the job to record.
+    fn step<T>(&self, step : usize, name : &str, bytes : u64, run : impl FnOnce() -> io::Result<(T, Option<String>)>) -> io::Result<T> {
+        self.send(JobEvent::StepStarted { step, name : name.to_string(), bytes });
+        let result = run();
+        let outcome = match result {
+            Ok((_, ref message)) => StepOutcome::Done(message.clone()),
+            Err(ref err) => StepOutcome::Failed(err.to_string()),
+        };
+        self.send(JobEvent::StepFinished { step, name : name.to_string(), bytes, outcome });
+        result.map(|(value, _)| value)
+    }
+}
+
+/// A long-running operation.
+pub trait Job
+{
+    type Output;
+
+    fn run(self, progress : &Progress) -> io::Result<Self::Output>;
+}
+
+/// Extracts entries from an archive file, in parallel. See
+/// `ZfsFile::extract_all_parallel()`.
+#[derive(Clone, Debug)]
+pub struct ExtractJob
+{
+    pub path : PathBuf,
+    pub options : ExtractOptions,
+    /// How many threads to extract with.
+    pub jobs : usize,
+}
+
+impl Job for ExtractJob
+{
+    type Output = ExtractReport;
+
+    fn run(mut self, progress : &Progress) -> io::Result<ExtractReport> {
+        let zfs_file = ZfsFile::from_stream_with_limits(&mut OpenMode::ReadOnly.open(&self.path)?, &self.options.config.limits)?;
+        self.options.progress = progress.clone();
+        let path = &self.path;
+        // Each thread gets its own handle on the archive.
+        zfs_file.extract_all_parallel(|| OpenMode::ReadOnly.open(path).map(io::BufReader::new), &self.options, self.jobs)
+    }
+}
+
+/// What `PackJob` did.
+#[derive(Clone, Debug)]
+pub struct PackSummary
+{
+    pub files : usize,
+    /// How many files share their data with an identical earlier one.
+    pub duplicates : usize,
+}
+
+/// Packs files into a new archive, as the entries of a manifest (see
+/// `ManifestEntry::for_file()` for packing files under their own names).
+#[derive(Clone, Debug)]
+pub struct PackJob
+{
+    pub entries : Vec<ManifestEntry>,
+    pub output : PathBuf,
+    /// Whether identical files share their data. See `ZfsBuilder::set_dedup()`.
+    pub dedup : bool,
+}
+
+impl Job for PackJob
+{
+    type Output = PackSummary;
+
+    /// Unlike other jobs, packing stops at the first file which can't be
+    /// read, as the archive would be missing it.
+    fn run(self, progress : &Progress) -> io::Result<PackSummary> {
+        let sizes = self.entries.iter().map(|entry| entry.file.metadata().map(|metadata| metadata.len())).collect::<io::Result<Vec<u64>>>()?;
+        progress.send(JobEvent::Begin { steps : Some(self.entries.len()), bytes : Some(sizes.iter().sum()) });
+
+        let mut builder = ZfsBuilder::new();
+        builder.set_dedup(self.dedup);
+        for (step, entry) in self.entries.iter().enumerate() {
+            progress.step(step, &entry.name, sizes[step], || {
+                builder.add_with_flags(&entry.name, std::fs::read(&entry.file)?, timestamp::from_zfs(entry.timestamp), entry.flags)?;
+                Ok(((), None))
+            })?;
+        }
+
+        let mut writer = io::BufWriter::new(File::create(&self.output)?);
+        builder.write(&mut writer)?;
+        writer.flush()?;
+        Ok(PackSummary { files : builder.len(), duplicates : builder.duplicates() })
+    }
+}
+
+/// The result of checking one archive with `VerifyJob`.
+#[derive(Debug)]
+pub enum VerifyOutcome
+{
+    Checked { entries : usize, problems : Vec<Problem> },
+    /// The archive couldn't be read at all.
+    Unreadable(io::Error),
+}
+
+/// Checks archives for structural problems. See `ZfsFile::validate()`.
+#[derive(Clone, Debug)]
+pub struct VerifyJob
+{
+    pub paths : Vec<PathBuf>,
+}
+
+impl Job for VerifyJob
+{
+    /// The outcome for each archive, in order.
+    type Output = Vec<(PathBuf, VerifyOutcome)>;
+
+    fn run(self, progress : &Progress) -> io::Result<Vec<(PathBuf, VerifyOutcome)>> {
+        let sizes : Vec<u64> = self.paths.iter().map(|path| path.metadata().map_or(0, |metadata| metadata.len())).collect();
+        progress.send(JobEvent::Begin { steps : Some(self.paths.len()), bytes : Some(sizes.iter().sum()) });
+
+        let mut outcomes = Vec::with_capacity(self.paths.len());
+        for (step, path) in self.paths.into_iter().enumerate() {
+            let name = path.display().to_string();
+            let checked = progress.step(step, &name, sizes[step], || {
+                // Walk the tables leniently, as broken ones are what we're
+                // looking for, and would stop the archive being opened.
+                let (entries, problems) = validate_archive(&mut io::BufReader::new(OpenMode::ReadOnly.open(&path)?))?;
+                let message = match problems.len() {
+                    0 => None,
+                    count => Some(format!("{} problems found", count)),
+                };
+                Ok(((entries, problems), message))
+            });
+            let outcome = match checked {
+                Ok((entries, problems)) => VerifyOutcome::Checked { entries, problems },
+                Err(err) => VerifyOutcome::Unreadable(err),
+            };
+            outcomes.push((path, outcome));
+        }
+        Ok(outcomes)
+    }
+}
+
+/// Converts loose RIM files, exactly as extracting them from an archive
+/// would.
+#[derive(Clone, Debug)]
+pub struct ConvertJob
+{
+    /// The folder the files are in.
+    pub in_dir : PathBuf,
+    /// The RIM files, relative to `in_dir`. Each is treated as an entry with
+    /// that name, so it's written to the same place under
+    /// `options.output_dir` (e.g. `sub/up01.rim.png` for `sub/up01.rim`).
+    pub files : Vec<PathBuf>,
+    /// How the files are converted (the image format, colour key, type
+    /// overrides, limits, and so on). Its progress is replaced by the job's.
+    pub options : ExtractOptions,
+}
+
+impl Job for ConvertJob
+{
+    /// The files which couldn't be converted, and why.
+    type Output = Vec<(PathBuf, io::Error)>;
+
+    fn run(self, progress : &Progress) -> io::Result<Vec<(PathBuf, io::Error)>> {
+        let metadata : Vec<Option<std::fs::Metadata>> = self.files.iter().map(|path| self.in_dir.join(path).metadata().ok()).collect();
+        let total_bytes = metadata.iter().flatten().map(|metadata| metadata.len()).sum();
+        progress.send(JobEvent::Begin { steps : Some(self.files.len()), bytes : Some(total_bytes) });
+
+        let options = ExtractOptions { progress : progress.clone(), ..self.options };
+        let mut failures = Vec::new();
+        for (step, (path, metadata)) in self.files.iter().zip(metadata).enumerate() {
+            let in_path = self.in_dir.join(path);
+            let entry = ZfsEntry {
+                name : path.to_string_lossy().into_owned(),
+                offset : 0,
+                size : metadata.as_ref().map_or(0, |metadata| metadata.len() as usize),
+                timestamp : metadata.and_then(|metadata| metadata.modified().ok()).unwrap_or(std::time::UNIX_EPOCH),
+                flags : ZfsEntryFlags::default(),
+                unknown : 0,
+                table_entry_offset : 0,
+            };
+            let record = entry.extract_with(step, &options, None, || File::open(&in_path).map(io::BufReader::new));
+            if let ExtractStatus::Failed(reason) = record.status {
+                failures.push((in_path, io::Error::other(reason)));
+            }
+        }
+        Ok(failures)
+    }
+}
+
+/// Converts a loose RIM file to a PNG at `out_path`, creating its folder if
+/// needed, and returns a description of the image. Images bigger than
+/// `limits` allow aren't converted.
+pub fn convert_rim_file(in_path : &Path, out_path : &Path, color_key : ColorKey, limits : &Limits) -> io::Result<String> {
+    let image = RimImage::from_stream_with_limits(&mut io::BufReader::new(File::open(in_path)?), limits)?;
+    if let Some(parent) = out_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    image.write_png_with(io::BufWriter::new(File::create(out_path)?), color_key)?;
+    Ok(format!("Converting RIM v{} ({}) image \"{}\" ({}×{})…", image.version, image.format.name(), in_path.display(), image.width, image.height))
+}
+
+/// The outcome of an extracted entry, for its `StepFinished` event.
+pub(crate) fn extract_outcome(status : &ExtractStatus, message : &Option<String>) -> StepOutcome {
+    match *status {
+        ExtractStatus::Extracted | ExtractStatus::Converted => StepOutcome::Done(message.clone()),
+        ExtractStatus::Skipped => StepOutcome::Skipped,
+        ExtractStatus::Failed(ref reason) => StepOutcome::Failed(reason.clone()),
+    }
+}
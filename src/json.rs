@@ -0,0 +1,432 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! A minimal JSON writer (and reader), for reports and metadata.
+//!
+//! This is deliberately tiny, to avoid pulling in yet more dependencies just
+//! to write out a few machine-readable files, and read back the ones unciv
+//! wrote itself.
+
+use std::fmt;
+use std::io;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonValue
+{
+    Null,
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue
+{
+    /// Creates an object from a list of key/value pairs, keeping their order.
+    pub fn object<K : Into<String>>(fields : Vec<(K, JsonValue)>) -> JsonValue {
+        JsonValue::Object(fields.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+
+    /// Formats the value with two-space indentation, for files people might read.
+    pub fn to_pretty_string(&self) -> String {
+        let mut out = String::new();
+        self.write_pretty(&mut out, 0);
+        out.push('\n');
+        out
+    }
+
+    /// Parses a JSON document. Numbers become `UInt`s or `Int`s if they're
+    /// whole, and `Float`s otherwise.
+    pub fn parse(text : &str) -> io::Result<JsonValue> {
+        let mut parser = Parser { text : text.as_bytes(), pos : 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos < parser.text.len() {
+            return Err(parser.error("unexpected text after the end"));
+        }
+        Ok(value)
+    }
+
+    /// The value of an object's field, or None if it has no such field (or
+    /// isn't an object).
+    pub fn get(&self, key : &str) -> Option<&JsonValue> {
+        match *self {
+            JsonValue::Object(ref fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            JsonValue::UInt(u) => Some(u),
+            JsonValue::Int(i) if i >= 0 => Some(i as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            JsonValue::UInt(u) => Some(u as f64),
+            JsonValue::Int(i) => Some(i as f64),
+            JsonValue::Float(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            JsonValue::String(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[JsonValue]> {
+        match *self {
+            JsonValue::Array(ref items) => Some(items),
+            _ => None,
+        }
+    }
+
+    fn write_pretty(&self, out : &mut String, indent : usize) {
+        match *self {
+            JsonValue::Array(ref items) if !items.is_empty() => {
+                out.push_str("[\n");
+                for (i, item) in items.iter().enumerate() {
+                    push_indent(out, indent + 1);
+                    item.write_pretty(out, indent + 1);
+                    out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+                }
+                push_indent(out, indent);
+                out.push(']');
+            },
+            JsonValue::Object(ref fields) if !fields.is_empty() => {
+                out.push_str("{\n");
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    push_indent(out, indent + 1);
+                    write_string(out, key);
+                    out.push_str(": ");
+                    value.write_pretty(out, indent + 1);
+                    out.push_str(if i + 1 < fields.len() { ",\n" } else { "\n" });
+                }
+                push_indent(out, indent);
+                out.push('}');
+            },
+            _ => out.push_str(&self.to_string()),
+        }
+    }
+}
+
+fn push_indent(out : &mut String, indent : usize) {
+    for _ in 0..indent {
+        out.push_str("  ");
+    }
+}
+
+fn write_string(out : &mut String, s : &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+struct Parser<'a>
+{
+    text : &'a [u8],
+    pos : usize,
+}
+
+impl<'a> Parser<'a>
+{
+    fn error(&self, message : &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, format!("Invalid JSON at byte {}: {}", self.pos, message))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.text.len() && self.text[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    /// Skips whitespace, then returns the next byte without consuming it.
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.text.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, byte : u8) -> io::Result<()> {
+        if self.peek() != Some(byte) {
+            return Err(self.error(&format!("expected '{}'", byte as char)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, word : &str, value : JsonValue) -> io::Result<JsonValue> {
+        if !self.text[self.pos..].starts_with(word.as_bytes()) {
+            return Err(self.error("expected a value"));
+        }
+        self.pos += word.len();
+        Ok(value)
+    }
+
+    fn value(&mut self) -> io::Result<JsonValue> {
+        match self.peek() {
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(JsonValue::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    fields.push((key, self.value()?));
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return Ok(JsonValue::Object(fields));
+                        },
+                        _ => return Err(self.error("expected ',' or '}'")),
+                    }
+                }
+            },
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(JsonValue::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(JsonValue::Array(items));
+                        },
+                        _ => return Err(self.error("expected ',' or ']'")),
+                    }
+                }
+            },
+            Some(b'"') => Ok(JsonValue::String(self.string()?)),
+            Some(b't') => self.literal("true", JsonValue::Bool(true)),
+            Some(b'f') => self.literal("false", JsonValue::Bool(false)),
+            Some(b'n') => self.literal("null", JsonValue::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn number(&mut self) -> io::Result<JsonValue> {
+        let start = self.pos;
+        while self.pos < self.text.len() && matches!(self.text[self.pos], b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') {
+            self.pos += 1;
+        }
+        let number = std::str::from_utf8(&self.text[start..self.pos]).unwrap_or_default();
+        if let Ok(u) = number.parse() {
+            Ok(JsonValue::UInt(u))
+        } else if let Ok(i) = number.parse() {
+            Ok(JsonValue::Int(i))
+        } else {
+            number.parse().map(JsonValue::Float).map_err(|_| self.error("invalid number"))
+        }
+    }
+
+    fn hex4(&mut self) -> io::Result<u32> {
+        let digits = self.text.get(self.pos..self.pos + 4).and_then(|digits| std::str::from_utf8(digits).ok());
+        let code = digits.and_then(|digits| u32::from_str_radix(digits, 16).ok()).ok_or_else(|| self.error("invalid \\u escape"))?;
+        self.pos += 4;
+        Ok(code)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        if self.text.get(self.pos) != Some(&b'"') {
+            return Err(self.error("expected a string"));
+        }
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            let Some(&byte) = self.text.get(self.pos) else { return Err(self.error("unterminated string")) };
+            self.pos += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let Some(&escape) = self.text.get(self.pos) else { return Err(self.error("unterminated string")) };
+                    self.pos += 1;
+                    let c = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            // Characters outside the BMP are written as surrogate pairs.
+                            if (0xd800..0xdc00).contains(&code) && self.text[self.pos..].starts_with(b"\\u") {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                        },
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                },
+                _ => bytes.push(byte),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8"))
+    }
+}
+
+/// Formats the value compactly, on a single line.
+impl fmt::Display for JsonValue
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            JsonValue::Null => write!(f, "null"),
+            JsonValue::Bool(b) => write!(f, "{}", b),
+            JsonValue::Int(i) => write!(f, "{}", i),
+            JsonValue::UInt(u) => write!(f, "{}", u),
+            // JSON has no representation for NaN or infinity.
+            JsonValue::Float(x) if !x.is_finite() => write!(f, "null"),
+            JsonValue::Float(x) => write!(f, "{}", x),
+            JsonValue::String(ref s) => {
+                let mut out = String::new();
+                write_string(&mut out, s);
+                f.write_str(&out)
+            },
+            JsonValue::Array(ref items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            JsonValue::Object(ref fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    let mut out = String::new();
+                    write_string(&mut out, key);
+                    write!(f, "{}:{}", out, value)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+impl From<bool> for JsonValue
+{
+    fn from(b : bool) -> JsonValue {
+        JsonValue::Bool(b)
+    }
+}
+
+impl From<i64> for JsonValue
+{
+    fn from(i : i64) -> JsonValue {
+        JsonValue::Int(i)
+    }
+}
+
+impl From<u64> for JsonValue
+{
+    fn from(u : u64) -> JsonValue {
+        JsonValue::UInt(u)
+    }
+}
+
+impl From<usize> for JsonValue
+{
+    fn from(u : usize) -> JsonValue {
+        JsonValue::UInt(u as u64)
+    }
+}
+
+impl From<u32> for JsonValue
+{
+    fn from(u : u32) -> JsonValue {
+        JsonValue::UInt(u as u64)
+    }
+}
+
+impl From<f64> for JsonValue
+{
+    fn from(x : f64) -> JsonValue {
+        JsonValue::Float(x)
+    }
+}
+
+impl<'a> From<&'a str> for JsonValue
+{
+    fn from(s : &'a str) -> JsonValue {
+        JsonValue::String(s.to_string())
+    }
+}
+
+impl From<String> for JsonValue
+{
+    fn from(s : String) -> JsonValue {
+        JsonValue::String(s)
+    }
+}
+
+impl<T : Into<JsonValue>> From<Option<T>> for JsonValue
+{
+    fn from(o : Option<T>) -> JsonValue {
+        match o {
+            Some(v) => v.into(),
+            None => JsonValue::Null,
+        }
+    }
+}
+
+impl<T : Into<JsonValue>> From<Vec<T>> for JsonValue
+{
+    fn from(v : Vec<T>) -> JsonValue {
+        JsonValue::Array(v.into_iter().map(Into::into).collect())
+    }
+}
@@ -0,0 +1,91 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+/// The kinds of file found in the game's archives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EntryKind
+{
+    Rim,
+    Text,
+    Sound,
+    Other,
+}
+
+impl EntryKind
+{
+    /// Guesses the kind of an entry from its name alone.
+    pub fn from_name(name : &str) -> EntryKind {
+        let extension = match name.rfind('.') {
+            Some(dot) => name[dot + 1..].to_ascii_lowercase(),
+            None => return EntryKind::Other,
+        };
+        match extension.as_str() {
+            "rim" => EntryKind::Rim,
+            "txt" | "ldl" | "slc" => EntryKind::Text,
+            "wav" => EntryKind::Sound,
+            _ => EntryKind::Other,
+        }
+    }
+
+    /// Works out the kind of an entry from its name and the first few bytes
+    /// of its data, which take priority over the name.
+    pub fn detect(name : &str, header : &[u8]) -> EntryKind {
+        if header.starts_with(b"RIMF") {
+            EntryKind::Rim
+        } else if header.starts_with(b"RIFF") {
+            EntryKind::Sound
+        } else {
+            EntryKind::from_name(name)
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EntryKind::Rim => "rim",
+            EntryKind::Text => "text",
+            EntryKind::Sound => "sound",
+            EntryKind::Other => "other",
+        }
+    }
+}
+
+impl FromStr for EntryKind
+{
+    type Err = io::Error;
+
+    fn from_str(name : &str) -> io::Result<EntryKind> {
+        match name.to_ascii_lowercase().as_str() {
+            "rim" => Ok(EntryKind::Rim),
+            "text" | "txt" => Ok(EntryKind::Text),
+            "sound" | "wav" => Ok(EntryKind::Sound),
+            "other" | "raw" | "binary" => Ok(EntryKind::Other),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown entry type \"{}\"", name))),
+        }
+    }
+}
+
+impl fmt::Display for EntryKind
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
@@ -0,0 +1,106 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! A minimal KTX2 writer, for exporting same-sized images as a single
+//! texture array which a GPU renderer can upload in one go.
+//!
+//! Only uncompressed 8-bit sRGB RGBA with a single mip level is written.
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use std::io;
+use std::io::Write;
+
+const KTX2_IDENTIFIER : [u8; 12] = [0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a];
+const VK_FORMAT_R8G8B8A8_SRGB : u32 = 43;
+
+/// The size of the identifier, header, index, and the one level index entry.
+const HEADER_SIZE : u32 = 12 + 9 * 4 + 4 * 4 + 2 * 8 + 3 * 8;
+/// The size of a basic data format descriptor with four samples.
+const DFD_SIZE : u32 = 4 + 6 * 4 + 4 * 16;
+
+/// Writes the data format descriptor for R8G8B8A8_SRGB.
+fn write_dfd(writer : &mut impl Write) -> io::Result<()> {
+    writer.write_u32::<LittleEndian>(DFD_SIZE)?;
+    // Khronos vendor, basic descriptor type.
+    writer.write_u32::<LittleEndian>(0)?;
+    // Version 2, and the size of the block.
+    writer.write_u32::<LittleEndian>(2 | ((DFD_SIZE - 4) << 16))?;
+    // RGBSDA colour model, BT.709 primaries, sRGB transfer, straight alpha.
+    writer.write_u32::<LittleEndian>(1 | (1 << 8) | (2 << 16))?;
+    // A 1×1×1×1 texel block.
+    writer.write_u32::<LittleEndian>(0)?;
+    // 4 bytes in plane 0.
+    writer.write_u32::<LittleEndian>(4)?;
+    writer.write_u32::<LittleEndian>(0)?;
+    // Red, green, and blue, then alpha, which is never sRGB-encoded.
+    for (channel, offset) in [(0u32, 0u32), (1, 8), (2, 16), (15 | 0x10, 24)] {
+        writer.write_u32::<LittleEndian>(offset | (7 << 16) | (channel << 24))?;
+        writer.write_u32::<LittleEndian>(0)?;
+        writer.write_u32::<LittleEndian>(0)?;
+        writer.write_u32::<LittleEndian>(255)?;
+    }
+    Ok(())
+}
+
+/// Writes `layers` as a KTX2 2D texture array. Each layer must be
+/// `width * height` 8-bit RGBA pixels.
+pub fn write_texture_array(writer : &mut impl Write, width : u32, height : u32, layers : &[Vec<u8>]) -> io::Result<()> {
+    let layer_size = width as u64 * height as u64 * 4;
+    if layers.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "A texture array needs at least one layer"));
+    }
+    if let Some(layer) = layers.iter().find(|layer| layer.len() as u64 != layer_size) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+            format!("Texture array layers should be {} bytes, not {}", layer_size, layer.len())));
+    }
+    let level_size = layer_size * layers.len() as u64;
+
+    writer.write_all(&KTX2_IDENTIFIER)?;
+    writer.write_u32::<LittleEndian>(VK_FORMAT_R8G8B8A8_SRGB)?;
+    // typeSize
+    writer.write_u32::<LittleEndian>(1)?;
+    writer.write_u32::<LittleEndian>(width)?;
+    writer.write_u32::<LittleEndian>(height)?;
+    // pixelDepth: this is a 2D texture.
+    writer.write_u32::<LittleEndian>(0)?;
+    writer.write_u32::<LittleEndian>(layers.len() as u32)?;
+    // faceCount, levelCount, and supercompressionScheme.
+    writer.write_u32::<LittleEndian>(1)?;
+    writer.write_u32::<LittleEndian>(1)?;
+    writer.write_u32::<LittleEndian>(0)?;
+
+    // The data format descriptor follows the level index directly. There's
+    // no key/value data or supercompression global data.
+    writer.write_u32::<LittleEndian>(HEADER_SIZE)?;
+    writer.write_u32::<LittleEndian>(DFD_SIZE)?;
+    writer.write_u32::<LittleEndian>(0)?;
+    writer.write_u32::<LittleEndian>(0)?;
+    writer.write_u64::<LittleEndian>(0)?;
+    writer.write_u64::<LittleEndian>(0)?;
+
+    // The one level's data comes after the DFD, which keeps it 4-byte aligned.
+    writer.write_u64::<LittleEndian>((HEADER_SIZE + DFD_SIZE) as u64)?;
+    writer.write_u64::<LittleEndian>(level_size)?;
+    writer.write_u64::<LittleEndian>(level_size)?;
+
+    write_dfd(writer)?;
+    for layer in layers {
+        writer.write_all(layer)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,295 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! A parser for .ldl files, which describe the game's UI layout.
+//!
+//! An .ldl file is a list of nested blocks, each of which can inherit from
+//! templates, and contains typed attributes and child blocks:
+//!
+//! ```text
+//! #include "ldl_data/base.ldl"
+//! ControlPanel:WINDOW_BASE {
+//!     int xpix 10
+//!     string image "upba0219.rim"
+//!     Button {
+//!         bool enabled true
+//!     }
+//! }
+//! ```
+//!
+//! Attributes without a type (`name value`) are accepted too. Comments run
+//! from `//` or a `#` (other than `#include`) to the end of the line.
+
+use json::JsonValue;
+use std::io;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum LdlValue
+{
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    String(String),
+    /// Anything else, like an unquoted name.
+    Word(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LdlAttribute
+{
+    /// The declared type (e.g. "int"), if there was one.
+    pub kind : Option<String>,
+    pub name : String,
+    pub value : LdlValue,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LdlBlock
+{
+    pub name : String,
+    /// The blocks this one inherits from, in the order they're listed.
+    pub templates : Vec<String>,
+    pub attributes : Vec<LdlAttribute>,
+    pub children : Vec<LdlBlock>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LdlFile
+{
+    /// The files named by `#include` lines.
+    pub includes : Vec<String>,
+    pub blocks : Vec<LdlBlock>,
+}
+
+const ATTRIBUTE_TYPES : &[&str] = &["int", "bool", "string", "float", "double"];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token
+{
+    Word(String),
+    String(String),
+    Include,
+    Colon,
+    Open,
+    Close,
+}
+
+fn syntax_error(line : usize, message : String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", line, message))
+}
+
+/// Splits a file into tokens, each with the line it's on.
+fn tokenize(text : &str) -> io::Result<Vec<(Token, usize)>> {
+    let mut tokens = Vec::new();
+    for (line_num, line) in text.lines().enumerate() {
+        let line_num = line_num + 1;
+        let mut chars = line.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            match c {
+                _ if c.is_whitespace() => {},
+                '{' => tokens.push((Token::Open, line_num)),
+                '}' => tokens.push((Token::Close, line_num)),
+                ':' => tokens.push((Token::Colon, line_num)),
+                '/' if line[start..].starts_with("//") => break,
+                '#' if line[start..].starts_with("#include") => {
+                    tokens.push((Token::Include, line_num));
+                    chars.nth("include".len() - 1);
+                },
+                '#' => break,
+                '"' => {
+                    let mut value = String::new();
+                    loop {
+                        match chars.next() {
+                            Some((_, '"')) => break,
+                            Some((_, '\\')) => match chars.next() {
+                                Some((_, 'n')) => value.push('\n'),
+                                Some((_, escaped)) => value.push(escaped),
+                                None => return Err(syntax_error(line_num, "unterminated string".to_string())),
+                            },
+                            Some((_, c)) => value.push(c),
+                            None => return Err(syntax_error(line_num, "unterminated string".to_string())),
+                        }
+                    }
+                    tokens.push((Token::String(value), line_num));
+                },
+                _ => {
+                    let mut end = start + c.len_utf8();
+                    while let Some(&(i, c)) = chars.peek() {
+                        if c.is_whitespace() || "{}:\"".contains(c) {
+                            break;
+                        }
+                        end = i + c.len_utf8();
+                        chars.next();
+                    }
+                    tokens.push((Token::Word(line[start..end].to_string()), line_num));
+                },
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_value(kind : Option<&str>, token : Token, line : usize) -> io::Result<LdlValue> {
+    let text = match token {
+        Token::String(text) => return Ok(LdlValue::String(text)),
+        Token::Word(text) => text,
+        other => return Err(syntax_error(line, format!("expected a value, not {:?}", other))),
+    };
+    let invalid = || syntax_error(line, format!("\"{}\" isn't a valid {}", text, kind.unwrap_or("value")));
+    match kind {
+        Some("int") => text.parse().map(LdlValue::Int).map_err(|_| invalid()),
+        Some("float") | Some("double") => text.parse().map(LdlValue::Float).map_err(|_| invalid()),
+        Some("bool") => match text.to_ascii_lowercase().as_str() {
+            "true" | "1" => Ok(LdlValue::Bool(true)),
+            "false" | "0" => Ok(LdlValue::Bool(false)),
+            _ => Err(invalid()),
+        },
+        // Strings don't have to be quoted.
+        Some(_) => Ok(LdlValue::String(text)),
+        None => {
+            if let Ok(value) = text.parse() {
+                Ok(LdlValue::Int(value))
+            } else if let Ok(value) = text.parse() {
+                Ok(LdlValue::Float(value))
+            } else if text.eq_ignore_ascii_case("true") || text.eq_ignore_ascii_case("false") {
+                Ok(LdlValue::Bool(text.eq_ignore_ascii_case("true")))
+            } else {
+                Ok(LdlValue::Word(text))
+            }
+        },
+    }
+}
+
+struct Parser
+{
+    tokens : std::iter::Peekable<std::vec::IntoIter<(Token, usize)>>,
+    last_line : usize,
+}
+
+impl Parser
+{
+    fn next(&mut self) -> io::Result<(Token, usize)> {
+        match self.tokens.next() {
+            Some((token, line)) => {
+                self.last_line = line;
+                Ok((token, line))
+            },
+            None => Err(syntax_error(self.last_line, "unexpected end of file".to_string())),
+        }
+    }
+
+    fn word(&mut self, what : &str) -> io::Result<String> {
+        match self.next()? {
+            (Token::Word(word), _) => Ok(word),
+            (other, line) => Err(syntax_error(line, format!("expected {}, not {:?}", what, other))),
+        }
+    }
+
+    /// Parses a block, after its name.
+    fn block(&mut self, name : String) -> io::Result<LdlBlock> {
+        let mut block = LdlBlock { name, ..LdlBlock::default() };
+        loop {
+            match self.next()? {
+                (Token::Colon, _) => block.templates.push(self.word("a template name")?),
+                (Token::Open, _) => break,
+                (other, line) => return Err(syntax_error(line, format!("expected \"{{\" after \"{}\", not {:?}", block.name, other))),
+            }
+        }
+        loop {
+            let name = match self.next()? {
+                (Token::Close, _) => return Ok(block),
+                (Token::Word(name), _) => name,
+                (other, line) => return Err(syntax_error(line, format!("expected an attribute or block, not {:?}", other))),
+            };
+            match self.tokens.peek() {
+                Some(&(Token::Colon, _)) | Some(&(Token::Open, _)) => block.children.push(self.block(name)?),
+                _ if ATTRIBUTE_TYPES.contains(&name.as_str()) => {
+                    let attr_name = self.word("an attribute name")?;
+                    let (token, line) = self.next()?;
+                    block.attributes.push(LdlAttribute { value : parse_value(Some(&name), token, line)?, kind : Some(name), name : attr_name });
+                },
+                _ => {
+                    let (token, line) = self.next()?;
+                    block.attributes.push(LdlAttribute { value : parse_value(None, token, line)?, kind : None, name });
+                },
+            }
+        }
+    }
+}
+
+impl LdlFile
+{
+    pub fn parse(text : &str) -> io::Result<LdlFile> {
+        let mut parser = Parser { tokens : tokenize(text)?.into_iter().peekable(), last_line : 1 };
+        let mut file = LdlFile::default();
+        while parser.tokens.peek().is_some() {
+            match parser.next()? {
+                (Token::Include, line) => match parser.next()? {
+                    (Token::String(path), _) | (Token::Word(path), _) => file.includes.push(path),
+                    _ => return Err(syntax_error(line, "expected a file name after #include".to_string())),
+                },
+                (Token::Word(name), _) => file.blocks.push(parser.block(name)?),
+                (other, line) => return Err(syntax_error(line, format!("expected a block, not {:?}", other))),
+            }
+        }
+        Ok(file)
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        JsonValue::object(vec![
+            ("includes", self.includes.clone().into()),
+            ("blocks", self.blocks.iter().map(LdlBlock::to_json).collect::<Vec<_>>().into()),
+        ])
+    }
+}
+
+impl LdlBlock
+{
+    pub fn to_json(&self) -> JsonValue {
+        JsonValue::object(vec![
+            ("name", self.name.as_str().into()),
+            ("templates", self.templates.clone().into()),
+            ("attributes", self.attributes.iter().map(LdlAttribute::to_json).collect::<Vec<_>>().into()),
+            ("children", self.children.iter().map(LdlBlock::to_json).collect::<Vec<_>>().into()),
+        ])
+    }
+}
+
+impl LdlAttribute
+{
+    pub fn to_json(&self) -> JsonValue {
+        let value = match self.value {
+            LdlValue::Int(value) => value.into(),
+            LdlValue::Float(value) => value.into(),
+            LdlValue::Bool(value) => value.into(),
+            LdlValue::String(ref value) | LdlValue::Word(ref value) => value.as_str().into(),
+        };
+        JsonValue::object(vec![
+            ("type", self.kind.clone().into()),
+            ("name", self.name.as_str().into()),
+            ("value", value),
+        ])
+    }
+}
@@ -0,0 +1,543 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+extern crate byteorder;
+#[cfg(feature = "image")]
+extern crate image;
+#[cfg(feature = "plugins")]
+extern crate libloading;
+extern crate png;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::collections::HashSet;
+use std::io::Seek;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+
+mod archive;
+pub mod atlas;
+mod builder;
+pub mod catalog;
+pub mod config;
+pub mod contrast;
+pub mod decoder;
+pub mod diff;
+pub mod encode;
+#[cfg(feature = "image")]
+mod dynamic_image;
+mod extract;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod glob;
+pub mod index;
+pub mod job;
+pub mod json;
+pub mod ktx2;
+pub mod ldl;
+mod kind;
+pub mod limits;
+pub mod manifest;
+mod open_mode;
+mod options;
+mod paths;
+mod preview;
+pub mod phash;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+mod provenance;
+mod raw;
+pub mod recolor;
+pub mod repair;
+mod report;
+mod rim;
+mod stream;
+mod template;
+pub mod timestamp;
+pub mod trace;
+pub mod undo;
+pub mod validate;
+mod vfs;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use archive::ZfsArchive;
+pub use builder::ZfsBuilder;
+pub use config::{Config, ForcedType};
+pub use kind::EntryKind;
+pub use limits::Limits;
+pub use open_mode::OpenMode;
+pub use options::{DuplicatePolicy, ExtractOptions, ImageFormat};
+pub use paths::{plan_output_path, plan_output_paths, sanitize_entry_name};
+pub use preview::{preview, preview_with, shrink};
+pub use provenance::{Crc32Reader, Provenance};
+pub use raw::{RawHeader, RawRecord, ZfsEntryFlags};
+pub use report::{ExtractRecord, ExtractReport, ExtractStats, ExtractStatus};
+pub use rim::{ColorKey, RimFormat, RimImage};
+pub use stream::{ZfsStream, ZfsStreamEntry};
+pub use template::NameTemplate;
+pub use vfs::{AssetSource, AssetVfs};
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ZfsEntry
+{
+    pub name : String,
+    pub offset : usize,
+    pub size : usize,
+    pub timestamp : std::time::SystemTime,
+    /// The record's flags, which are kept as-is. What they mean isn't known
+    /// (see `read_data()`).
+    pub flags : ZfsEntryFlags,
+    /// The record's field between the data's offset and size, whose meaning
+    /// isn't known.
+    pub unknown : u32,
+    /// Where this entry's record lives in the archive's file table.
+    pub table_entry_offset : u64,
+}
+
+impl ZfsEntry
+{
+    /// Returns a reader over just this entry's data, for streaming it rather
+    /// than reading it all into memory.
+    pub fn reader<'a, R : Read + Seek>(&self, reader : &'a mut R) -> io::Result<io::Take<&'a mut R>> {
+        reader.seek(io::SeekFrom::Start(self.offset as u64))?;
+        Ok(reader.take(self.size as u64))
+    }
+
+    /// Reads the entry's data.
+    ///
+    /// This is the same as `read_raw_data()`, as no compression (or any other
+    /// encoding) is known to be used by ZFS3 archives: nothing the entry's
+    /// flags might mean is undone. Entries with flags set are rare enough that
+    /// none has been seen to work out what they mean, so callers which care
+    /// should check `flags` themselves.
+    pub fn read_data(&self, reader : &mut (impl Read + Seek)) -> io::Result<Vec<u8>> {
+        self.read_raw_data(reader)
+    }
+
+    /// Reads the entry's data exactly as it's stored in the archive.
+    pub fn read_raw_data(&self, reader : &mut (impl Read + Seek)) -> io::Result<Vec<u8>> {
+        // Only allocate as much as is really there, whatever the record says.
+        let mut buffer = Vec::new();
+        self.reader(reader)?.read_to_end(&mut buffer)?;
+        if buffer.len() != self.size {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                format!("Entry is {} bytes, but only {} could be read", self.size, buffer.len())));
+        }
+        Ok(buffer)
+    }
+
+    /// The entry's modification time, as the seconds since 1970 stored in
+    /// its record.
+    pub fn zfs_timestamp(&self) -> u32 {
+        timestamp::to_zfs(self.timestamp)
+    }
+
+    /// The entry's record (other than its name), as it's stored.
+    pub fn raw(&self) -> RawRecord {
+        RawRecord {
+            offset : self.offset as u32,
+            unknown : self.unknown,
+            size : self.size as u32,
+            timestamp : self.zfs_timestamp(),
+            flags : self.flags,
+        }
+    }
+    
+    /// Reads an entry's record from the file table, or None if the record is
+    /// empty (which marks the end of the table).
+    ///
+    /// Records shorter than version 1's have no timestamp or flags, and
+    /// fields beyond version 1's are skipped.
+    pub(crate) fn read_record(reader : &mut impl Read, header : &ZfsHeader, table_entry_offset : u64) -> io::Result<Option<ZfsEntry>> {
+        let mut raw_name = vec![0; header.max_filename_len as usize];
+        reader.read_exact(&mut raw_name)?;
+
+        if raw_name[0] == 0 {
+            return Ok(None);
+        }
+
+        let file_name = String::from_utf8_lossy(&raw_name);
+        let file_name = file_name.trim_matches('\0');
+        let fields = header.record_fields_size;
+        let data_offset = reader.read_u32::<LittleEndian>()?;
+        let unknown = reader.read_u32::<LittleEndian>()?;
+        let data_size = reader.read_u32::<LittleEndian>()?;
+        let timestamp = if fields >= 16 { reader.read_u32::<LittleEndian>()? } else { 0 };
+        let flags = if fields >= 20 { reader.read_u32::<LittleEndian>()? } else { 0 };
+        if fields > RECORD_FIELDS_SIZE {
+            io::copy(&mut reader.take((fields - RECORD_FIELDS_SIZE) as u64), &mut io::sink())?;
+        }
+
+        Ok(Some(ZfsEntry{
+            name : file_name.to_string(),
+            offset : data_offset as usize,
+            size : data_size as usize,
+            timestamp : timestamp::from_zfs(timestamp),
+            flags : ZfsEntryFlags::from_raw(flags),
+            unknown,
+            table_entry_offset,
+        }))
+    }
+}
+
+/// The size of each file table record in version 1 archives (as used by
+/// CTP2), other than the name: the data's offset, an unknown field, the
+/// data's size, the timestamp, and the flags.
+pub(crate) const RECORD_FIELDS_SIZE : u32 = 20;
+
+/// The longest name field any archive can have. The game's are 16 bytes, and
+/// nothing bigger than this could be sensible.
+const MAX_NAME_FIELD_LEN : u32 = 1024;
+
+/// The archive versions whose records are known to be laid out as above.
+const KNOWN_VERSIONS : &[u32] = &[1];
+
+/// The fixed-size header at the start of every ZFS archive.
+pub(crate) struct ZfsHeader
+{
+    pub version : u32,
+    pub max_filename_len : u32,
+    /// How many entries each table in the chain of file tables holds.
+    pub files_per_table : u32,
+    pub num_files : u32,
+    pub unknown : u32,
+    pub filetable_offset : u32,
+    /// The size of each file table record, other than the name. This isn't
+    /// stored in the header, but depends on the version.
+    pub record_fields_size : u32,
+}
+
+impl ZfsHeader
+{
+    /// Reads the header, assuming the records are laid out as in version 1.
+    /// For other versions, `detect_layout()` should be used to check.
+    pub fn read(reader : &mut impl Read) -> io::Result<ZfsHeader> {
+        let mut sig = [0; 4];
+        reader.read_exact(&mut sig)?;
+        if sig != *b"ZFS3" {
+            if sig.starts_with(b"ZFS") && sig[3].is_ascii_alphanumeric() {
+                return Err(io::Error::new(io::ErrorKind::Unsupported,
+                    format!("This is a ZFS{} archive, which isn't supported (unciv reads ZFS3 archives)", sig[3] as char)));
+            }
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid ZFS signature"));
+        }
+        let header = ZfsHeader {
+            version : reader.read_u32::<LittleEndian>()?,
+            max_filename_len : reader.read_u32::<LittleEndian>()?,
+            files_per_table : reader.read_u32::<LittleEndian>()?,
+            num_files : reader.read_u32::<LittleEndian>()?,
+            unknown : reader.read_u32::<LittleEndian>()?,
+            filetable_offset : reader.read_u32::<LittleEndian>()?,
+            record_fields_size : RECORD_FIELDS_SIZE,
+        };
+        if header.max_filename_len == 0 || header.max_filename_len > MAX_NAME_FIELD_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("ZFS header says entry names are {} bytes, which can't be right", header.max_filename_len)));
+        }
+        Ok(header)
+    }
+
+    pub fn raw(&self) -> RawHeader {
+        RawHeader {
+            version : self.version,
+            max_filename_len : self.max_filename_len,
+            files_per_table : self.files_per_table,
+            num_files : self.num_files,
+            unknown : self.unknown,
+            filetable_offset : self.filetable_offset,
+        }
+    }
+
+    pub fn is_known_version(&self) -> bool {
+        KNOWN_VERSIONS.contains(&self.version)
+    }
+
+    /// Works out how the records of an archive with an unknown version are
+    /// laid out, from its first file table.
+    ///
+    /// The data of the first table's first entry normally comes straight
+    /// after the table, which gives away the size of the records. If it
+    /// doesn't, the version 1 layout is kept, as long as the first record
+    /// makes sense that way.
+    pub fn detect_layout(&mut self, reader : &mut (impl Read + Seek)) -> io::Result<()> {
+        if self.is_known_version() || self.files_per_table == 0 {
+            return Ok(());
+        }
+        let archive_size = reader.seek(io::SeekFrom::End(0))?;
+        let table_start = self.filetable_offset as u64 + 4;
+        reader.seek(io::SeekFrom::Start(table_start + self.max_filename_len as u64))?;
+        let first_offset = reader.read_u32::<LittleEndian>()? as u64;
+        let _unk3 = reader.read_u32::<LittleEndian>()?;
+        let first_size = reader.read_u32::<LittleEndian>()? as u64;
+
+        let tables_size = first_offset.saturating_sub(table_start);
+        let per_table = self.files_per_table as u64;
+        if tables_size > 0 && tables_size % per_table == 0 {
+            let fields = (tables_size / per_table).saturating_sub(self.max_filename_len as u64);
+            // Every layout needs at least the offset, unknown field, and size.
+            if (12..=64).contains(&fields) && fields % 4 == 0 {
+                self.record_fields_size = fields as u32;
+                return Ok(());
+            }
+        }
+        if first_offset >= table_start && first_offset + first_size <= archive_size {
+            return Ok(());
+        }
+        Err(io::Error::new(io::ErrorKind::Unsupported,
+            format!("ZFS3 version {} isn't supported: unciv reads version 1, and couldn't work out the layout of this one's file table", self.version)))
+    }
+
+    pub fn write(&self, writer : &mut impl Write) -> io::Result<()> {
+        writer.write_u32::<LittleEndian>(0x3353465a)?;
+        writer.write_u32::<LittleEndian>(self.version)?;
+        writer.write_u32::<LittleEndian>(self.max_filename_len)?;
+        writer.write_u32::<LittleEndian>(self.files_per_table)?;
+        writer.write_u32::<LittleEndian>(self.num_files)?;
+        writer.write_u32::<LittleEndian>(self.unknown)?;
+        writer.write_u32::<LittleEndian>(self.filetable_offset)
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ZfsFile
+{
+    version : u32,
+    max_filename_len : u32,
+    files_per_table : u32,
+    record_fields_size : u32,
+    raw_header : RawHeader,
+    pub files : Vec::<ZfsEntry>,
+}
+
+impl ZfsFile
+{
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// The size of the (zero-padded) name field of each entry's record.
+    pub fn max_filename_len(&self) -> u32 {
+        self.max_filename_len
+    }
+
+    /// How many entries each table in the archive's chain of file tables holds.
+    pub fn files_per_table(&self) -> u32 {
+        self.files_per_table
+    }
+
+    /// The size of each entry's record in the file tables, including its
+    /// name, which depends on the version.
+    pub fn record_size(&self) -> u32 {
+        self.max_filename_len + self.record_fields_size
+    }
+
+    /// The archive's header, as it's stored.
+    pub fn raw_header(&self) -> RawHeader {
+        self.raw_header
+    }
+
+    /// Reads an archive's file table, within the default `Limits`.
+    pub fn from_stream(reader : &mut (impl Read + Seek)) -> io::Result<ZfsFile> {
+        ZfsFile::from_stream_with_limits(reader, &Limits::default())
+    }
+
+    /// Reads an archive's file table, failing if it breaks `limits`, or if
+    /// its file tables don't fit in the archive or loop back on themselves.
+    pub fn from_stream_with_limits(reader : &mut (impl Read + Seek), limits : &Limits) -> io::Result<ZfsFile> {
+        let mut header = ZfsHeader::read(reader)?;
+        header.detect_layout(reader)?;
+        let version = header.version;
+        let max_filename_len = header.max_filename_len;
+        let files_per_table = header.files_per_table;
+        let num_files = header.num_files;
+        let filetable_offset = header.filetable_offset;
+
+        if files_per_table == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "ZFS header says each file table holds no entries"));
+        }
+        let archive_size = reader.seek(io::SeekFrom::End(0))?;
+        let check_table = |offset : u32| {
+            if offset as u64 + 4 > archive_size {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    format!("File table at offset {} is past the end of the archive ({} bytes)", offset, archive_size)));
+            }
+            Ok(())
+        };
+        check_table(filetable_offset)?;
+
+        let mut files = Vec::<ZfsEntry>::new();
+        let mut tables_seen = HashSet::new();
+        tables_seen.insert(filetable_offset);
+
+        reader.seek(io::SeekFrom::Start(filetable_offset as u64))?;
+
+        let mut next_table_offset = reader.read_u32::<LittleEndian>()?;
+        for i in 0..num_files {
+            let table_entry_offset = reader.stream_position()?;
+            match ZfsEntry::read_record(reader, &header, table_entry_offset)? {
+                Some(entry) => {
+                    if files.len() >= limits.max_entries {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                            format!("Archive has more than {} entries, the most allowed", limits.max_entries)));
+                    }
+                    if entry.size as u64 > limits.max_entry_size {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                            format!("Entry \"{}\" is {} bytes, more than the {} allowed", entry.name, entry.size, limits.max_entry_size)));
+                    }
+                    files.push(entry);
+                },
+                None => break,
+            }
+
+            if (i % files_per_table) == (files_per_table - 1) {
+                // The last table has no next one, however many entries the
+                // header claims there are.
+                if next_table_offset == 0 {
+                    break;
+                }
+                check_table(next_table_offset)?;
+                if !tables_seen.insert(next_table_offset) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData,
+                        format!("The file tables loop back to the one at offset {}", next_table_offset)));
+                }
+                reader.seek(io::SeekFrom::Start(next_table_offset as u64))?;
+                next_table_offset = reader.read_u32::<LittleEndian>()?;
+            }
+        }
+
+        Ok(ZfsFile {
+            version,
+            max_filename_len,
+            files_per_table,
+            record_fields_size : header.record_fields_size,
+            raw_header : header.raw(),
+            files
+        })
+    }
+    
+    /// Replaces the contents of the entry called `name` in an existing archive.
+    ///
+    /// If the new data fits in the entry's current slot, and no other entry
+    /// shares it, it is written over the old data in place. Otherwise, it is
+    /// appended to the end of the archive. Either way, only the entry's
+    /// record in the file table is updated: the rest of the archive is left
+    /// untouched.
+    pub fn replace_entry(&mut self, name : &str, data : &[u8], writer : &mut (impl Write + Seek)) -> io::Result<()> {
+        let name_len = self.max_filename_len as u64;
+        let index = match self.files.iter().position(|e| e.name == name) {
+            Some(index) => index,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, format!("No entry named \"{}\" in archive", name))),
+        };
+        if data.len() > u32::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Entry data is too large for a ZFS archive"));
+        }
+        // Data shared with other entries (as deduplicated archives have)
+        // mustn't be overwritten.
+        let (start, end) = (self.files[index].offset, self.files[index].offset + self.files[index].size);
+        let shared = self.files.iter().enumerate().any(|(i, other)| i != index && other.size > 0 && other.offset < end && start < other.offset + other.size);
+        let entry = &mut self.files[index];
+
+        let data_offset = if data.len() <= entry.size && !shared {
+            entry.offset as u64
+        } else {
+            writer.seek(io::SeekFrom::End(0))?
+        };
+        if data_offset + data.len() as u64 > u32::MAX as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "ZFS archive would grow too large"));
+        }
+
+        writer.seek(io::SeekFrom::Start(data_offset))?;
+        writer.write_all(data)?;
+
+        // Each table record is the name, followed by the offset, an unknown
+        // field, and the size.
+        writer.seek(io::SeekFrom::Start(entry.table_entry_offset + name_len))?;
+        writer.write_u32::<LittleEndian>(data_offset as u32)?;
+        writer.seek(io::SeekFrom::Current(4))?;
+        writer.write_u32::<LittleEndian>(data.len() as u32)?;
+        writer.flush()?;
+
+        entry.offset = data_offset as usize;
+        entry.size = data.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    /// Builds a deduplicated archive in which "a" and "b" share their data.
+    fn shared_archive() -> io::Cursor<Vec<u8>> {
+        let mut builder = ZfsBuilder::new();
+        builder.add("a", b"shared data".to_vec(), UNIX_EPOCH).unwrap();
+        builder.add("b", b"shared data".to_vec(), UNIX_EPOCH).unwrap();
+        builder.add("c", b"data of its own".to_vec(), UNIX_EPOCH).unwrap();
+        let mut data = Vec::new();
+        builder.write(&mut data).unwrap();
+        io::Cursor::new(data)
+    }
+
+    /// Reads every entry's data back from a fresh parse of the archive.
+    fn contents(archive : &mut io::Cursor<Vec<u8>>) -> Vec<Vec<u8>> {
+        archive.set_position(0);
+        let zfs = ZfsFile::from_stream(archive).unwrap();
+        zfs.files.iter().map(|entry| entry.read_data(archive).unwrap()).collect()
+    }
+
+    #[test]
+    fn replacing_shared_data_leaves_the_other_entry_alone() {
+        let mut archive = shared_archive();
+        let mut zfs = ZfsFile::from_stream(&mut archive).unwrap();
+        assert_eq!(zfs.files[0].offset, zfs.files[1].offset);
+
+        // Shorter than the slot, which would be written in place if it
+        // weren't shared.
+        zfs.replace_entry("a", b"new", &mut archive).unwrap();
+        assert_eq!(contents(&mut archive), [&b"new"[..], b"shared data", b"data of its own"]);
+        zfs.replace_entry("b", b"newer data!", &mut archive).unwrap();
+        assert_eq!(contents(&mut archive), [&b"new"[..], b"newer data!", b"data of its own"]);
+    }
+
+    #[test]
+    fn unshared_data_is_replaced_in_place() {
+        let mut archive = shared_archive();
+        let len = archive.get_ref().len();
+        let mut zfs = ZfsFile::from_stream(&mut archive).unwrap();
+        let offset = zfs.files[2].offset;
+
+        zfs.replace_entry("c", b"smaller", &mut archive).unwrap();
+        assert_eq!(zfs.files[2].offset, offset);
+        assert_eq!(archive.get_ref().len(), len);
+        assert_eq!(contents(&mut archive), [&b"shared data"[..], b"shared data", b"smaller"]);
+
+        // Too big for its slot, so it's appended.
+        zfs.replace_entry("c", b"much larger than it was before", &mut archive).unwrap();
+        assert_eq!(zfs.files[2].offset, len);
+        assert_eq!(contents(&mut archive), [&b"shared data"[..], b"shared data", b"much larger than it was before"]);
+    }
+}
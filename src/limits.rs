@@ -0,0 +1,64 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Limits on what a (possibly corrupt or malicious) archive can make unciv
+//! do, so that it fails with an error rather than running out of memory.
+//!
+//! Everything unciv reads from an archive is bounded by the archive itself
+//! (so a record claiming a huge entry can't make it allocate more than is
+//! really there), but within that, an archive can still ask for a lot of
+//! work. These are checked as archives and images are parsed.
+
+/// How much an archive is allowed to ask for. The defaults are far beyond
+/// anything in the game's own archives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits
+{
+    /// The most entries an archive can have.
+    pub max_entries : usize,
+    /// The largest an entry can be, in bytes.
+    pub max_entry_size : u64,
+    /// The widest and tallest a RIM image can be, in pixels.
+    pub max_image_width : u32,
+    pub max_image_height : u32,
+}
+
+impl Default for Limits
+{
+    fn default() -> Limits {
+        Limits {
+            max_entries : 100_000,
+            max_entry_size : 256 << 20,
+            max_image_width : 8192,
+            max_image_height : 8192,
+        }
+    }
+}
+
+impl Limits
+{
+    /// No limits beyond what the formats themselves allow.
+    pub fn none() -> Limits {
+        Limits {
+            max_entries : usize::MAX,
+            max_entry_size : u64::MAX,
+            max_image_width : u32::MAX,
+            max_image_height : u32::MAX,
+        }
+    }
+}
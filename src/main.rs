@@ -15,204 +15,377 @@
  * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
  * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
  */
+extern crate unciv;
+#[cfg(feature = "clipboard")]
+extern crate arboard;
 
-#![cfg_attr(feature = "set-timestamps", feature(file_set_times))]
+mod cli;
 
-extern crate byteorder;
-extern crate png;
-use byteorder::{LittleEndian, ReadBytesExt};
-use std::fs::File;
-use std::io::Seek;
 use std::io;
-use std::io::Read;
-use std::io::Write;
+use std::path::Path;
 
-fn convert_555_888<W: std::io::Write>(reader : &mut impl Read, writer: &mut png::StreamWriter<W>) {
-    let px555 = reader.read_u16::<LittleEndian>().unwrap();
-    let red = (px555 >> 10) & 31;
-    let green = (px555 >> 5) & 31;
-    let blue = (px555 >> 0) & 31;
-    let rgb = [(red << 3) as u8, (green << 3) as u8, (blue << 3) as u8, 255u8];
-    writer.write(&rgb).unwrap();
-}
-
-fn convert_565_888<W: std::io::Write>(reader : &mut impl Read, writer: &mut png::StreamWriter<W>) {
-    let px555 = reader.read_u16::<LittleEndian>().unwrap();
-    let red = (px555 >> 11) & 31;
-    let green = (px555 >> 5) & 63;
-    let blue = (px555 >> 0) & 31;
-    let rgb = [(red << 3) as u8, (green << 2) as u8, (blue << 3) as u8, 255 as u8];
-    writer.write(&rgb).unwrap();
-}
-
-struct ZfsEntry
+/// One of unciv's subcommands.
+struct Command
 {
-    name : String,
-    offset : usize,
-    size : usize,
-    timestamp : std::time::SystemTime,
+    name : &'static str,
+    usage : &'static str,
+    summary : &'static str,
+    run : fn(&[String]) -> io::Result<()>,
+    /// Recipes for `unciv examples`, as (what it does, arguments) pairs.
+    examples : &'static [(&'static str, &'static str)],
 }
 
-impl ZfsEntry
-{
-    fn get_data(&self, reader : &mut (impl Read + Seek)) -> io::Result<Vec<u8>> {
-        let mut buffer = vec![0; self.size];
-        reader.seek(io::SeekFrom::Start(self.offset as u64))?;
-        reader.read(&mut buffer)?;
-        Ok(buffer)
-    }
-    
-    fn extract_file(&self, reader : &mut (impl Read + Seek)) -> io::Result<()> {
-        let data = self.get_data(reader)?;
-        println!("Extracting file \"{}\"…", self.name);
-        let mut out_file = File::create(&self.name)?;
-        out_file.write_all(&data)?;
-        #[cfg(feature = "set-timestamps")]
-        out_file.set_modified(self.timestamp)?;
-        Ok(())
-    }
-    
-    fn extract_rim_image(&self, reader : &mut (impl Read + Seek)) -> io::Result<()> {
-        reader.seek(io::SeekFrom::Start(self.offset as u64))?;
-        
-        let rim_sig = reader.read_u32::<LittleEndian>()?;
-        
-        // 'RIMF'
-        if rim_sig != 0x464d4952 {
-            return Err(io::Error::new(io::ErrorKind::Other, "Invalid RIM signature"));
-        }
-        
-        let rim_ver = reader.read_u32::<LittleEndian>()?;
-        let rim_width = reader.read_u16::<LittleEndian>()?;
-        let rim_height = reader.read_u16::<LittleEndian>()?;
-        let rim_pitch = reader.read_u16::<LittleEndian>()?;
-        let rim_fmt = reader.read_u16::<LittleEndian>()?;
-        
-        println!("Converting RIM v{} ({}) image \"{}\" ({}×{})…", rim_ver, if rim_fmt == 0 { "RGB555" } else { "RGB565" }, self.name, rim_width, rim_height);
-        
-        // Note: Apparently this doesn't need to be mutable? Can't think of why, though: it has state (e.g. offset) we're mutating.
-        let mut out_file = File::create(format!("{}.png", &self.name))?;
-        {
-            let mut png_encoder = png::Encoder::new(&out_file, rim_width as u32, rim_height as u32);
-            // Note: Newer versions of the 'png' library call this 'Rgba'.
-            png_encoder.set_color(png::ColorType::RGBA);
-            png_encoder.set_depth(png::BitDepth::Eight);
-            
-            let mut px_writer = png_encoder.write_header()?;
-            // Note: Newer versions of the 'png' library have this return a result, so needs a '?'.
-            let mut stream_writer = px_writer.stream_writer();
-            for _line_num in 0..rim_height {
-                for _px in 0..rim_width {
-                    if rim_fmt == 0 {
-                        convert_555_888(reader, &mut stream_writer);
-                    } else if rim_fmt == 1 {
-                        convert_565_888(reader, &mut stream_writer);
-                    }
+const COMMANDS : &[Command] = &[
+    Command {
+        name : "list",
+        usage : "[--json | --csv | --dump-raw] <zfs-file> [pattern...]",
+        summary : "List the entries in an archive",
+        run : cli::list::list,
+        examples : &[
+            ("See what's in an archive", "gl.zfs"),
+            ("List just the images whose names start with sp", "gl.zfs 'sp*.rim'"),
+            ("Write a CSV manifest of an archive, for a spreadsheet or database", "--csv gl.zfs > gl.csv"),
+            ("Show the header and records' fields as they're stored, including unknown ones", "--dump-raw gl.zfs"),
+        ],
+    },
+    Command {
+        name : "extract",
+        usage : "[-o <output-dir>] [-j <jobs>] [--progress] [-q | -v] [--xattrs] [--report <report.json>] [--config <unciv.conf>] [--format png|tga|bmp|qoi|raw16|rim-copy] [--color-key none|magenta|zero|0xNNNN] [--name-template <template>] [--on-duplicate rename|skip|overwrite] [--index <range>] [--verify-read <catalog.tsv>] [--plugin <library>] <zfs-file | -> [pattern...]",
+        summary : "Extract entries (all of them, by default) into the current or given directory",
+        run : cli::extract::run,
+        examples : &[
+            ("Extract everything into the current directory", "gl.zfs"),
+            ("Extract into a folder of its own", "-o gl gl.zfs"),
+            ("Show a progress bar while extracting a large archive", "--progress -o gl gl.zfs"),
+            ("Extract sprites with transparent backgrounds", "--color-key magenta gl.zfs 'sp*.rim'"),
+            ("Keep the original RIMs alongside the PNGs, for modding", "--format rim-copy -o gl gl.zfs"),
+            ("Write a JSON report of what was extracted", "--report report.json gl.zfs"),
+            ("Check entries against a catalog made with --checksums as they're read", "--verify-read ctp.tsv ~/ctp/ctp_data/default/graphics/pictures/gl.zfs"),
+            ("Sort images and text into folders by type", "--name-template '{kind}/{stem}' gl.zfs"),
+            ("Extract entries by position, when their names are corrupt", "--index 100..200 --name-template '{index}.{ext}' gl.zfs"),
+        ],
+    },
+    Command {
+        name : "diff",
+        usage : "<old-zfs> <new-zfs> [--images <dir>] [--color-key <key>]",
+        summary : "List the entries added, removed, or modified between two archives",
+        run : cli::diff::run,
+        examples : &[
+            ("See what a mod changes, with before-and-after pictures", "gl.zfs mod/gl.zfs --images gl-changes"),
+        ],
+    },
+    Command {
+        name : "extract-changes",
+        usage : "<base-install> <patch-dir> -o <output-dir> [--config <unciv.conf>] [--format <format>]",
+        summary : "Extract only the entries a patch added or changed",
+        run : cli::changes::run,
+        examples : &[
+            ("See what a patch changed", "~/ctp ~/ctp-patch -o patch-changes"),
+        ],
+    },
+    Command {
+        name : "info",
+        usage : "<zfs-file>",
+        summary : "Show details of an archive's header and contents",
+        run : cli::list::info,
+        examples : &[
+            ("Show an archive's header details and totals by type", "gl.zfs"),
+        ],
+    },
+    Command {
+        name : "explain",
+        usage : "<zfs-file> <entry> [--install <install-dir>] [--config <unciv.conf>]",
+        summary : "Show everything known about one entry, and which other archives have it",
+        run : cli::explain::run,
+        examples : &[
+            ("Work out why an image won't extract, or which copy the game uses", "gl.zfs up01.rim --install ~/ctp"),
+        ],
+    },
+    Command {
+        name : "cat",
+        usage : "<zfs-file> <pattern...>",
+        summary : "Write the raw contents of entries to standard output",
+        run : cli::list::cat,
+        examples : &[
+            ("Read a text entry without extracting anything", "gl.zfs readme.txt"),
+        ],
+    },
+    Command {
+        name : "copy",
+        usage : "<zfs-file> <entry.rim> [--color-key <key>] [--wait]",
+        summary : "Copy an image to the clipboard (if unciv was built with the clipboard feature)",
+        run : cli::copy::run,
+        examples : &[
+            ("Copy a sprite, with a transparent background, to paste into a forum post", "gl.zfs sp001.rim --color-key magenta"),
+        ],
+    },
+    Command {
+        name : "ldl-json",
+        usage : "<file.ldl> | <zfs-file> <entry.ldl>",
+        summary : "Parse a UI layout (.ldl) file, and print it as JSON",
+        run : cli::ldl::run,
+        examples : &[
+            ("Dump the layout of a window from an archive", "gl.zfs notes.ldl"),
+        ],
+    },
+    Command {
+        name : "contrast",
+        usage : "<install-dir | zfs-file> [--text-color <#rrggbb>] [--min-ratio <ratio>] [--color-key <key>] [--trace <trace.json>]",
+        summary : "Report UI text whose contrast with the image behind it is too low",
+        run : cli::contrast::run,
+        examples : &[
+            ("Find hard-to-read UI text, assuming it's white unless the layout says otherwise", "~/ctp --text-color '#ffffff'"),
+        ],
+    },
+    Command {
+        name : "replay",
+        usage : "<trace.json> [--order recorded|offset] [--timed] [--repeat <n>]",
+        summary : "Replay a trace of asset reads (from contrast --trace), as a benchmark",
+        run : cli::replay::run,
+        examples : &[
+            ("See how much faster loading would be if the data were in the order it's read", "trace.json --order offset --repeat 3"),
+        ],
+    },
+    Command {
+        name : "export-ktx2",
+        usage : "<zfs-file> [-o <output-dir>] [--color-key <key>] [pattern...]",
+        summary : "Export same-sized RIM images as KTX2 texture arrays",
+        run : cli::ktx2::run,
+        examples : &[
+            ("Export images as texture arrays for a GPU renderer", "gl.zfs -o textures"),
+        ],
+    },
+    Command {
+        name : "atlas",
+        usage : "<zfs-file> [-o <output-dir>] [--color-key <key>] [--padding <pixels>] [--min-frames <n>] [pattern...]",
+        summary : "Pack numbered sequences of images (animation frames) into sprite sheets",
+        run : cli::atlas::run,
+        examples : &[
+            ("Make sprite sheets of every animation, with magenta made transparent", "gl.zfs -o atlases --color-key magenta"),
+        ],
+    },
+    Command {
+        name : "find",
+        usage : "<install-dir> <pattern...>",
+        summary : "Search every archive in an install for matching entries",
+        run : cli::list::find,
+        examples : &[
+            ("Find which archive an image is in", "~/ctp 'up01.rim'"),
+        ],
+    },
+    Command {
+        name : "catalog",
+        usage : "<install-dir> [-o <catalog.tsv>] [--hashes] [--checksums]",
+        summary : "Write a catalog of every entry in an install, optionally with image hashes and CRC-32s",
+        run : cli::catalog::run,
+        examples : &[
+            ("Catalog an install, so similar can search it without decoding everything", "~/ctp --hashes -o ctp.tsv"),
+            ("Record the CRC-32 of every entry, for extract --verify-read", "~/ctp --checksums -o ctp.tsv"),
+        ],
+    },
+    Command {
+        name : "similar",
+        usage : "<image.png> <install-dir | --catalog <catalog.tsv>> [--top <count>]",
+        summary : "Find the images in an install which look most like a picture",
+        run : cli::similar::run,
+        examples : &[
+            ("Work out which image a screenshotted button came from", "button.png ~/ctp --top 5"),
+            ("Search a prebuilt catalog instead, which is much faster", "button.png --catalog ctp.tsv"),
+        ],
+    },
+    Command {
+        name : "replace",
+        usage : "<zfs-file> <entry-name> <file>",
+        summary : "Replace the contents of an entry in an archive, in place",
+        run : cli::replace::run,
+        examples : &[
+            ("Put an edited image back into an archive", "gl.zfs up01.rim up01.rim"),
+        ],
+    },
+    Command {
+        name : "undo",
+        usage : "<zfs-file>",
+        summary : "Undo the last in-place change to an archive (e.g. by replace)",
+        run : cli::replace::undo,
+        examples : &[
+            ("Put back the image replace just overwrote", "gl.zfs"),
+        ],
+    },
+    Command {
+        name : "index",
+        usage : "<zfs-file...> [--remove]",
+        summary : "Save archives' file tables next to them, so they open faster",
+        run : cli::index::run,
+        examples : &[
+            ("Index every archive in an install", "ctp2_data/default/graphics/pictures/*.zfs"),
+        ],
+    },
+    Command {
+        name : "pack",
+        usage : "(<dir> | --manifest <manifest.tsv>) -o <output-zfs> [--no-dedup] [--progress] [-q | -v]",
+        summary : "Build a new archive from the files in a folder",
+        run : cli::pack::run,
+        examples : &[
+            ("Pack a folder of edited files into an archive for the game", "gl -o gl.zfs"),
+            ("Pack the files listed in a manifest, with their names and timestamps", "--manifest imported/gl/manifest.tsv -o gl.zfs"),
+        ],
+    },
+    Command {
+        name : "watch",
+        usage : "<dir> <output-zfs> [--no-dedup] [--interval <seconds>]",
+        summary : "Watch a folder, rebuilding an archive from it whenever it changes",
+        run : cli::watch::run,
+        examples : &[
+            ("Keep the game's archive up to date while editing a mod", "mymod/gl ctp2_data/default/graphics/pictures/gl.zfs"),
+        ],
+    },
+    Command {
+        name : "import-tree",
+        usage : "<old-dir> --convention <unciv|ctp2unpacker|raw> -o <output-dir> [--originals <archive-dir>] [--rim-format 555|565]",
+        summary : "Turn files extracted by older tools into folders with manifests, for packing",
+        run : cli::import_tree::run,
+        examples : &[
+            ("Import a tree extracted with ctp2unpacker", "./old_extract --convention ctp2unpacker -o imported"),
+            ("Import a tree extracted by an older unciv, keeping each image's RIM format", "./old_extract --convention unciv --originals ctp2_data/default/graphics/pictures -o imported"),
+        ],
+    },
+    Command {
+        name : "recolor",
+        usage : "--map <colors.toml> [--filter <pattern>...] <zfs-file> <output-zfs>",
+        summary : "Recolour the images in an archive, writing a new archive",
+        run : cli::recolor::run,
+        examples : &[
+            ("Make autumn versions of the terrain tiles", "--map autumn.toml --filter 'gt*.rim' gl.zfs gl-autumn.zfs"),
+        ],
+    },
+    Command {
+        name : "convert-dir",
+        usage : "<dir> [-o <output-dir>] [--recursive] [--config <unciv.conf>] [--format png|tga|bmp|qoi|raw16|rim-copy] [--color-key <key>] [--progress] [-q | -v]",
+        summary : "Convert loose .rim files to PNG (or another format), as extraction would",
+        run : cli::convert::convert_dir,
+        examples : &[
+            ("Convert a folder of loose RIMs to PNG", "-r pictures -o pictures-png"),
+        ],
+    },
+    Command {
+        name : "convert-daemon",
+        usage : "<dir> [--to png|rim] [-o <output-dir>] [--rim-format 555|565] [--interval <seconds>] [--metrics <file.prom>] [--config <unciv.conf>]",
+        summary : "Watch a folder, converting images dropped into it",
+        run : cli::convert::convert_daemon,
+        examples : &[
+            ("Convert edited PNGs back to RIM as they're saved", "work --to rim"),
+        ],
+    },
+    Command {
+        name : "examples",
+        usage : "[command]",
+        summary : "Show example commands for common jobs",
+        run : print_examples,
+        examples : &[
+            ("Show the examples for extracting", "extract"),
+        ],
+    },
+    Command {
+        name : "verify",
+        usage : "[--progress] [-q | -v] <zfs-file...>",
+        summary : "Check archives for overlapping or out-of-bounds entries, broken tables, and the like",
+        run : cli::verify::run,
+        examples : &[
+            ("Check that a downloaded archive isn't corrupt", "gl.zfs"),
+        ],
+    },
+    Command {
+        name : "repair",
+        usage : "<zfs-file> -o <output-zfs> [--no-carve]",
+        summary : "Rebuild a damaged archive from whatever entries can be recovered",
+        run : cli::repair::run,
+        examples : &[
+            ("Salvage a partial download", "gl.zfs -o gl-fixed.zfs"),
+        ],
+    },
+    Command {
+        name : "self-check",
+        usage : "<install-dir> [--exhaustive]",
+        summary : "Run every parser over every archive in an install, reporting failures",
+        run : cli::check::run,
+        examples : &[
+            ("Check that every archive and image in an install can be read", "~/ctp --exhaustive"),
+        ],
+    },
+];
 
-                }
-                if rim_width * 2 < rim_pitch {
-                    reader.seek(io::SeekFrom::Current((rim_pitch - rim_width * 2) as i64))?;
-                }
-            }
-        }
-        #[cfg(feature = "set-timestamps")]
-        out_file.set_modified(self.timestamp)?;
-        Ok(())
+fn print_usage() {
+    println!("unciv: An Uncivilized File Extractor for Civilization: Call to Power");
+    println!("By David Gow <david@davidgow.net>");
+    println!();
+    println!("Usage: unciv <command> [arguments]");
+    println!("       unciv <zfs-file>  (the same as \"unciv extract <zfs-file>\", for an existing file or a .zfs name)");
+    println!();
+    println!("Commands:");
+    for command in COMMANDS {
+        println!("  {:<16} {}", command.name, command.summary);
     }
+    println!();
+    for command in COMMANDS {
+        println!("unciv {} {}", command.name, command.usage);
+    }
+    println!();
+    println!("Patterns may use the wildcards * and ?, and ignore case.");
+    println!("Run \"unciv examples\" for some common recipes.");
 }
 
-struct ZfsFile
-{
-    _version : u32,
-    _max_filename_len : u32,
-    files : Vec::<ZfsEntry>,
-}
-
-impl ZfsFile
-{
-    fn from_stream(reader : &mut (impl Read + Seek)) -> io::Result<ZfsFile> {
-        let sig = reader.read_u32::<LittleEndian>()?;
-        // 'ZFS3'
-        if sig != 0x3353465a {
-            return Err(io::Error::new(io::ErrorKind::Other, "Invalid ZFS signature"));
+fn print_examples(args : &[String]) -> io::Result<()> {
+    let topic = match args {
+        [] => None,
+        [topic] => Some(topic.as_str()),
+        [_, arg, ..] => return Err(cli::unexpected_argument(arg)),
+    };
+    if let Some(topic) = topic {
+        if !COMMANDS.iter().any(|command| command.name == topic) {
+            return Err(cli::usage_error(format!("Unknown command \"{}\"", topic)));
         }
-        let version = reader.read_u32::<LittleEndian>()?;
-        let max_filename_len = reader.read_u32::<LittleEndian>()?;
-        let _unk1 = reader.read_u32::<LittleEndian>()?;
-        let num_files = reader.read_u32::<LittleEndian>()?;
-        let _unk2 = reader.read_u32::<LittleEndian>()?;
-        let filetable_offset = reader.read_u32::<LittleEndian>()?;
-
-
-        let mut files = Vec::<ZfsEntry>::new();
-
-        reader.seek(io::SeekFrom::Start(filetable_offset as u64))?;
-
-        let mut next_table_offset = reader.read_u32::<LittleEndian>()?;
-        for i in 0..num_files {
-            let mut raw_name = vec![0; max_filename_len as usize];
-            reader.read_exact(&mut raw_name)?;
-
-            if raw_name[0] == 0 {
-                break;
-            }
-
-            let file_name = String::from_utf8_lossy(&raw_name);
-            let file_name = file_name.trim_matches('\0');
-            let data_offset = reader.read_u32::<LittleEndian>()?;
-            let _unk3 = reader.read_u32::<LittleEndian>()?;
-            let data_size = reader.read_u32::<LittleEndian>()?;
-            let timestamp = reader.read_u32::<LittleEndian>()?;
-            let _flags = reader.read_u32::<LittleEndian>()?;
-
-            files.push(ZfsEntry{
-                name : file_name.to_string(),
-                offset : data_offset as usize,
-                size : data_size as usize,
-                timestamp: std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(timestamp as u64),
-            });
+    }
 
-            if (i % _unk1) == (_unk1 - 1) {
-                reader.seek(io::SeekFrom::Start(next_table_offset as u64))?;
-                next_table_offset = reader.read_u32::<LittleEndian>()?;
-            }
+    for command in COMMANDS {
+        if topic.is_some_and(|topic| topic != command.name) || command.examples.is_empty() {
+            continue;
         }
-
-        Ok(ZfsFile {
-            _version : version,
-            _max_filename_len : max_filename_len,
-            files
-        })
-    }
-    
-    fn extract_all(self, reader : &mut (impl Read + Seek)) -> io::Result<()> {
-        for i in self.files {
-            if i.name.ends_with(".rim") {
-                i.extract_rim_image(reader)?;
-            } else {
-                i.extract_file(reader)?;
-            }
+        println!("{}:", command.summary);
+        for &(description, example_args) in command.examples {
+            println!("  # {}", description);
+            println!("  unciv {} {}", command.name, example_args);
         }
-        Ok(())
+        println!();
     }
+    println!("Run \"unciv help\" for every command's full usage.");
+    Ok(())
 }
 
 fn main() {
     let args : Vec<std::string::String> = std::env::args().collect();
 
-    if args.len() != 2 {
-        println!("unciv: An Uncivilized File Extractor for Civilization: Call to Power");
-        println!("By David Gow <david@davidgow.net>");
-        println!("");
-        println!("Usage: unciv <zfs-file>");
-        return;
-    }
-
-    println!("File: {}", &args[1]);
+    let (command, command_args) = match args.get(1).map(|arg| arg.as_str()) {
+        None | Some("help") | Some("-h") | Some("--help") => {
+            print_usage();
+            return;
+        },
+        Some(name) => match COMMANDS.iter().find(|command| command.name == name) {
+            Some(command) => (command, &args[2..]),
+            // For compatibility, "unciv <zfs-file>" extracts everything.
+            None if Path::new(name).exists() || name.to_ascii_lowercase().ends_with(".zfs") => (&COMMANDS[1], &args[1..]),
+            None => {
+                eprintln!("unciv: Unknown command \"{}\"", name);
+                print_usage();
+                std::process::exit(1);
+            },
+        },
+    };
 
-    let mut file = File::open(&args[1]).unwrap();
-
-    let zfs_file = ZfsFile::from_stream(&mut file).unwrap();
-    
-    zfs_file.extract_all(&mut file).unwrap();
+    if let Err(err) = (command.run)(command_args) {
+        eprintln!("unciv: {}: {}", command.name, err);
+        if err.kind() == io::ErrorKind::InvalidInput {
+            eprintln!("Usage: unciv {} {}", command.name, command.usage);
+        }
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,106 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! A manifest of the files to pack into an archive, so that an archive can
+//! be rebuilt with its entries in their original order, and with their
+//! original names, timestamps, and flags.
+//!
+//! The manifest is a tab-separated text file. After a "# unciv manifest 1"
+//! line and a line of column names, there is one line per entry, giving its
+//! name, the file its data comes from (relative to the manifest's folder),
+//! its timestamp in seconds since 1970, and its flags in hex. Names and
+//! paths are escaped as in catalogs.
+
+use catalog::{escape, unescape};
+use std::io;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use timestamp;
+
+const MANIFEST_HEADER : &str = "# unciv manifest 1";
+const MANIFEST_COLUMNS : &str = "name\tfile\ttimestamp\tflags";
+
+/// The conventional name for a manifest, in the folder of files it lists.
+pub const MANIFEST_FILE_NAME : &str = "manifest.tsv";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ManifestEntry
+{
+    pub name : String,
+    /// Where the entry's data comes from. `read_manifest()` resolves it
+    /// against the manifest's folder.
+    pub file : PathBuf,
+    /// Seconds since 1970, as stored in the entry's record.
+    pub timestamp : u32,
+    pub flags : u32,
+}
+
+impl ManifestEntry
+{
+    /// An entry named after `file`, with its modification time and no flags.
+    pub fn for_file(file : &Path) -> io::Result<ManifestEntry> {
+        let name = file.file_name().and_then(|name| name.to_str())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} has no usable file name", file.display())))?;
+        Ok(ManifestEntry {
+            name : name.to_string(),
+            file : file.to_path_buf(),
+            timestamp : timestamp::to_zfs(file.metadata()?.modified()?),
+            flags : 0,
+        })
+    }
+}
+
+/// Writes a manifest. Paths under `dir` (the folder the manifest is going
+/// in) are written relative to it.
+pub fn write_manifest(writer : &mut impl Write, entries : &[ManifestEntry], dir : &Path) -> io::Result<()> {
+    writeln!(writer, "{}", MANIFEST_HEADER)?;
+    writeln!(writer, "{}", MANIFEST_COLUMNS)?;
+    for entry in entries {
+        let file = entry.file.strip_prefix(dir).unwrap_or(&entry.file);
+        let file = file.to_str().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} isn't valid UTF-8", file.display())))?;
+        writeln!(writer, "{}\t{}\t{}\t{:08x}", escape(&entry.name), escape(file), entry.timestamp, entry.flags)?;
+    }
+    Ok(())
+}
+
+/// Reads a manifest, resolving relative paths against `dir` (the folder the
+/// manifest is in).
+pub fn read_manifest(reader : impl BufRead, dir : &Path) -> io::Result<Vec<ManifestEntry>> {
+    let mut entries = Vec::new();
+    for (line_num, line) in reader.lines().enumerate() {
+        let line = line?;
+        let invalid = |message : &str| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", line_num + 1, message));
+        if line_num == 0 && line != MANIFEST_HEADER {
+            return Err(invalid("not an unciv manifest (or from a newer version)"));
+        }
+        if line_num < 2 {
+            continue;
+        }
+        let fields : Vec<&str> = line.split('\t').collect();
+        if fields.len() != 4 {
+            return Err(invalid("expected 4 columns"));
+        }
+        entries.push(ManifestEntry {
+            name : unescape(fields[0]),
+            file : dir.join(unescape(fields[1])),
+            timestamp : fields[2].parse().map_err(|_| invalid("bad timestamp"))?,
+            flags : u32::from_str_radix(fields[3], 16).map_err(|_| invalid("bad flags"))?,
+        });
+    }
+    Ok(entries)
+}
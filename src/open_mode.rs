@@ -0,0 +1,56 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+/// How an archive should be opened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpenMode
+{
+    /// Opens the archive for reading only. On Windows, other programs (like
+    /// the game itself) can still open, and even write to, the archive while
+    /// unciv has it open.
+    ReadOnly,
+    /// Opens the archive for editing. On Windows, nothing else can open the
+    /// archive until unciv is done with it, so the game can't read an archive
+    /// which is half-written. Elsewhere, this is up to the user.
+    ReadWrite,
+}
+
+impl OpenMode
+{
+    pub fn open(self, path : impl AsRef<Path>) -> io::Result<File> {
+        let mut options = OpenOptions::new();
+        options.read(true);
+        if self == OpenMode::ReadWrite {
+            options.write(true);
+        }
+        #[cfg(windows)]
+        {
+            use std::os::windows::fs::OpenOptionsExt;
+            // FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE
+            options.share_mode(match self {
+                OpenMode::ReadOnly => 0x7,
+                OpenMode::ReadWrite => 0,
+            });
+        }
+        options.open(path)
+    }
+}
@@ -0,0 +1,219 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use config::Config;
+use decoder::Decoder;
+use ColorKey;
+use glob::glob_match;
+use job::Progress;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use template::NameTemplate;
+
+/// What RIM images are written out as when extracting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat
+{
+    Png,
+    /// A PNG, plus an untouched copy of the original RIM, so that images
+    /// which weren't edited can be repacked without any generation loss.
+    RimCopy,
+    Tga,
+    Bmp,
+    Qoi,
+    /// The untouched 16-bit pixels, with no row padding, plus a JSON file
+    /// describing them.
+    Raw16,
+}
+
+impl ImageFormat
+{
+    pub fn name(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::RimCopy => "rim-copy",
+            ImageFormat::Tga => "tga",
+            ImageFormat::Bmp => "bmp",
+            ImageFormat::Qoi => "qoi",
+            ImageFormat::Raw16 => "raw16",
+        }
+    }
+
+    /// The extension added to the names of converted images.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png | ImageFormat::RimCopy => "png",
+            ImageFormat::Tga => "tga",
+            ImageFormat::Bmp => "bmp",
+            ImageFormat::Qoi => "qoi",
+            ImageFormat::Raw16 => "raw16",
+        }
+    }
+}
+
+impl FromStr for ImageFormat
+{
+    type Err = io::Error;
+
+    fn from_str(name : &str) -> io::Result<ImageFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "png" => Ok(ImageFormat::Png),
+            "rim-copy" => Ok(ImageFormat::RimCopy),
+            "tga" => Ok(ImageFormat::Tga),
+            "bmp" => Ok(ImageFormat::Bmp),
+            "qoi" => Ok(ImageFormat::Qoi),
+            "raw16" => Ok(ImageFormat::Raw16),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown image format \"{}\"", name))),
+        }
+    }
+}
+
+impl fmt::Display for ImageFormat
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+/// What happens when an entry would be extracted to the same file as one
+/// before it: because their names are the same, differ only in case (which
+/// is the same file on Windows and macOS), or differ only in which path
+/// separator they use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicatePolicy
+{
+    /// The later entry is written over the earlier one.
+    Overwrite,
+    /// The later entry gets a number added to its name, e.g. `up01~2.rim`.
+    Rename,
+    /// The later entry isn't extracted.
+    Skip,
+}
+
+impl DuplicatePolicy
+{
+    pub fn name(self) -> &'static str {
+        match self {
+            DuplicatePolicy::Overwrite => "overwrite",
+            DuplicatePolicy::Rename => "rename",
+            DuplicatePolicy::Skip => "skip",
+        }
+    }
+}
+
+impl FromStr for DuplicatePolicy
+{
+    type Err = io::Error;
+
+    fn from_str(name : &str) -> io::Result<DuplicatePolicy> {
+        match name.to_ascii_lowercase().as_str() {
+            "overwrite" => Ok(DuplicatePolicy::Overwrite),
+            "rename" => Ok(DuplicatePolicy::Rename),
+            "skip" => Ok(DuplicatePolicy::Skip),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown duplicate policy \"{}\"", name))),
+        }
+    }
+}
+
+impl fmt::Display for DuplicatePolicy
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+/// Settings which control how entries are extracted.
+#[derive(Clone, Debug)]
+pub struct ExtractOptions
+{
+    /// Where entries are extracted to. Entry names are sanitized so that
+    /// they can't escape this directory.
+    pub output_dir : PathBuf,
+    /// Type overrides and the like, from the config file.
+    pub config : Config,
+    pub image_format : ImageFormat,
+    /// Which pixels of RIM images become transparent when they're converted.
+    pub color_key : ColorKey,
+    /// Only entries matching one of these wildcard patterns are extracted,
+    /// or all of them if there are none.
+    pub patterns : Vec<String>,
+    /// Only entries whose index is in one of these ranges are extracted, or
+    /// all of them if there are none. This is useful when names are corrupt
+    /// or duplicated. (When reading a `ZfsStream`, indices count entries in
+    /// the order they're found in the stream.)
+    pub indices : Vec<Range<usize>>,
+    /// If set, the name of the archive, which is recorded along with each
+    /// entry's index, offset, and CRC-32 in the extended attributes of the
+    /// files extracted from it.
+    pub provenance : Option<String>,
+    /// The CRC-32s entries' data should have, by index. Entries whose data
+    /// doesn't match (because the archive has been damaged) are reported as
+    /// failed, although what was read is still written out.
+    pub expected_crc32s : BTreeMap<usize, u32>,
+    /// If set, how extracted files are named, instead of after their entries.
+    pub name_template : Option<NameTemplate>,
+    /// What to do with entries which would be extracted to the same file as
+    /// an earlier one. Either way, each collision is warned about.
+    pub on_duplicate : DuplicatePolicy,
+    /// Decoders offered each entry before unciv's own handling, in order.
+    /// Entries they convert keep their name, with the decoder's extension
+    /// added.
+    pub decoders : Vec<Arc<dyn Decoder>>,
+    /// Where progress events are sent as entries are extracted, including a
+    /// line logged for each entry (nothing is printed).
+    pub progress : Progress,
+}
+
+impl ExtractOptions
+{
+    pub fn matches(&self, name : &str) -> bool {
+        self.patterns.is_empty() || self.patterns.iter().any(|pattern| glob_match(pattern, name))
+    }
+
+    /// Returns true if the entry at `index`, called `name`, should be
+    /// extracted: that is, it's in one of the index ranges and matches one
+    /// of the patterns.
+    pub fn selects(&self, index : usize, name : &str) -> bool {
+        (self.indices.is_empty() || self.indices.iter().any(|range| range.contains(&index))) && self.matches(name)
+    }
+}
+
+impl Default for ExtractOptions
+{
+    fn default() -> ExtractOptions {
+        ExtractOptions {
+            output_dir : PathBuf::from("."),
+            config : Config::default(),
+            image_format : ImageFormat::Png,
+            color_key : ColorKey::None,
+            patterns : Vec::new(),
+            indices : Vec::new(),
+            provenance : None,
+            expected_crc32s : BTreeMap::new(),
+            name_template : None,
+            on_duplicate : DuplicatePolicy::Rename,
+            decoders : Vec::new(),
+            progress : Progress::none(),
+        }
+    }
+}
@@ -0,0 +1,338 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+use DuplicatePolicy;
+use EntryKind;
+use ExtractOptions;
+use ImageFormat;
+use ZfsEntry;
+
+/// Turns an entry name from an archive into a relative path which is safe
+/// to create under an output directory.
+///
+/// Both `/` and `\\` are treated as directory separators, as the game was
+/// written for Windows. Empty, `.`, and `..` components are dropped, as are
+/// drive letters, so a hostile or corrupt name like `..\\..\\foo` or
+/// `C:\\foo` can never escape the output directory. Characters which aren't
+/// allowed in file names on Windows are replaced with `_`, and names Windows
+/// keeps for devices get a `_` after them (`CON_`, `nul_.txt`). Returns None
+/// if nothing is left of the name.
+pub fn sanitize_entry_name(name : &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in name.split(['/', '\\']) {
+        // Strip drive letters and alternate data stream names.
+        let component = match component.rfind(':') {
+            Some(colon) => &component[colon + 1..],
+            None => component,
+        };
+        let component = component.trim_end_matches(['.', ' ']);
+        if component.is_empty() {
+            continue;
+        }
+        let mut cleaned : String = component.chars().map(|c| match c {
+            '<' | '>' | '"' | '|' | '?' | '*' => '_',
+            c if (c as u32) < 0x20 => '_',
+            c => c,
+        }).collect();
+        let stem_len = cleaned.find('.').unwrap_or(cleaned.len());
+        if is_device_name(&cleaned[..stem_len]) {
+            cleaned.insert(stem_len, '_');
+        }
+        path.push(cleaned);
+    }
+    if path.as_os_str().is_empty() {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+/// Whether Windows keeps `stem` (a file name up to its first `.`) for a
+/// device, so that no file can be given that name, whatever its extension.
+fn is_device_name(stem : &str) -> bool {
+    let stem = stem.trim_end_matches(' ').to_ascii_uppercase();
+    match stem.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" => true,
+        _ => stem.len() == 4 && (stem.starts_with("COM") || stem.starts_with("LPT")) && matches!(stem.as_bytes()[3], b'1'..=b'9'),
+    }
+}
+
+/// Where an entry is extracted to (before any image extension is added),
+/// using `options`' name template if there is one.
+pub(crate) fn entry_output_path(entry : &ZfsEntry, index : usize, kind : EntryKind, options : &ExtractOptions) -> io::Result<PathBuf> {
+    let name = match options.name_template {
+        Some(ref template) => template.expand(&entry.name, index, kind),
+        None => entry.name.clone(),
+    };
+    match sanitize_entry_name(&name) {
+        Some(relative) => Ok(options.output_dir.join(relative)),
+        None => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Entry name \"{}\" can't be used as a file name", name))),
+    }
+}
+
+/// Where a converted image is written, given where the entry itself would
+/// be extracted to.
+pub(crate) fn converted_path(out_path : &Path, format : ImageFormat) -> PathBuf {
+    let mut converted_path = out_path.as_os_str().to_owned();
+    converted_path.push(".");
+    converted_path.push(format.extension());
+    PathBuf::from(converted_path)
+}
+
+/// Where the JSON describing a `Raw16` image is written, given where the
+/// entry itself would be extracted to.
+pub(crate) fn raw16_metadata_path(out_path : &Path) -> PathBuf {
+    let mut json_path = out_path.as_os_str().to_owned();
+    json_path.push(".json");
+    PathBuf::from(json_path)
+}
+
+/// Works out where extracting `entry` with `options` will write it, without
+/// touching the filesystem.
+///
+/// For converted images, this is the converted file: `RimCopy` also writes
+/// the original RIM without the extension, and `Raw16` a `.json` file next
+/// to the entry's path. Returns None if the entry would be skipped, or its
+/// name can't be used as a file name. Patterns in `options` aren't checked.
+/// `index` is the entry's position in its archive, for name templates.
+///
+/// This looks at the entry on its own, so it doesn't account for entries
+/// which land on the same file as an earlier one, and are renamed or skipped
+/// (see `ExtractOptions::on_duplicate`). `plan_output_paths()` does.
+pub fn plan_output_path(entry : &ZfsEntry, index : usize, options : &ExtractOptions) -> Option<PathBuf> {
+    let kind = entry.planned_kind(options)?;
+    let out_path = entry_output_path(entry, index, kind, options).ok()?;
+    Some(final_path(out_path, kind, options))
+}
+
+/// Works out where extracting an archive's `files` with `options` will
+/// write each entry, as `plan_output_path()` does, but for every entry
+/// `options` selects at once, so that entries which collide are renamed or
+/// skipped just as extraction would. Entries which wouldn't be written at
+/// all are left out. Collisions are warned about through `options.progress`.
+pub fn plan_output_paths(files : &[ZfsEntry], options : &ExtractOptions) -> BTreeMap<usize, PathBuf> {
+    let mut claims = OutputClaims::default();
+    let mut paths = BTreeMap::new();
+    for (index, entry) in files.iter().enumerate() {
+        if !options.selects(index, &entry.name) {
+            continue;
+        }
+        let Some(kind) = entry.planned_kind(options) else { continue };
+        let out_path = match claims.claim(entry, index, options) {
+            Some(Collision::Skip) => continue,
+            Some(Collision::Rename(renamed)) => renamed,
+            None => match entry_output_path(entry, index, kind, options) {
+                Ok(out_path) => out_path,
+                Err(_) => continue,
+            },
+        };
+        paths.insert(index, final_path(out_path, kind, options));
+    }
+    paths
+}
+
+/// The file which ends up holding an entry of the given kind, given where
+/// the entry itself would be extracted to.
+fn final_path(out_path : PathBuf, kind : EntryKind, options : &ExtractOptions) -> PathBuf {
+    if kind == EntryKind::Rim {
+        converted_path(&out_path, options.image_format)
+    } else {
+        out_path
+    }
+}
+
+/// Every file extracting an entry of the given kind writes, given where the
+/// entry itself would be extracted to, lowercased for `OutputClaims`: the
+/// one `final_path()` gives, and the files `Raw16` and `RimCopy` write
+/// alongside it.
+fn claim_keys(out_path : &Path, kind : EntryKind, options : &ExtractOptions) -> Vec<String> {
+    let mut paths = vec![final_path(out_path.to_path_buf(), kind, options)];
+    if kind == EntryKind::Rim {
+        match options.image_format {
+            ImageFormat::Raw16 => paths.push(raw16_metadata_path(out_path)),
+            ImageFormat::RimCopy => paths.push(out_path.to_path_buf()),
+            _ => {},
+        }
+    }
+    paths.iter().map(|path| path.to_string_lossy().to_lowercase()).collect()
+}
+
+/// Adds `~<number>` to the end of a path's file stem, e.g. `up01~2.rim`.
+fn numbered_path(out_path : &Path, number : usize) -> PathBuf {
+    let stem = out_path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match out_path.extension() {
+        Some(ext) => format!("{}~{}.{}", stem, number, ext.to_string_lossy()),
+        None => format!("{}~{}", stem, number),
+    };
+    out_path.with_file_name(name)
+}
+
+/// What happens to an entry which would be extracted to the same file as an
+/// earlier one, instead of writing over it.
+pub(crate) enum Collision
+{
+    /// The entry is extracted here instead (before any image extension is
+    /// added).
+    Rename(PathBuf),
+    Skip,
+}
+
+/// Keeps track of the files an extraction will write, so that entries which
+/// would land on the same file can be dealt with before anything is written.
+///
+/// Paths are compared ignoring case, as names which differ only in case are
+/// the same file on Windows and macOS, and the archives were made there.
+#[derive(Default)]
+pub(crate) struct OutputClaims
+{
+    /// The name of the entry which claimed each path, by lowercased path.
+    claimed : HashMap<String, String>,
+}
+
+impl OutputClaims
+{
+    /// Claims the file `entry` will be extracted to, returning what should
+    /// happen instead if an earlier entry has already claimed it (according
+    /// to `options.on_duplicate`). Each collision is warned about.
+    pub fn claim(&mut self, entry : &ZfsEntry, index : usize, options : &ExtractOptions) -> Option<Collision> {
+        let kind = entry.planned_kind(options)?;
+        let out_path = entry_output_path(entry, index, kind, options).ok()?;
+        let keys = claim_keys(&out_path, kind, options);
+        let earlier = match keys.iter().find_map(|key| self.claimed.get(key)) {
+            Some(earlier) => earlier.clone(),
+            None => {
+                self.claim_all(keys, entry);
+                return None;
+            },
+        };
+        match options.on_duplicate {
+            DuplicatePolicy::Overwrite => {
+                options.progress.warn(format!("\"{}\" (entry {}) is extracted to the same file as \"{}\", and overwrites it", entry.name, index, earlier));
+                None
+            },
+            DuplicatePolicy::Skip => {
+                options.progress.warn(format!("\"{}\" (entry {}) would be extracted to the same file as \"{}\", so it's skipped", entry.name, index, earlier));
+                Some(Collision::Skip)
+            },
+            DuplicatePolicy::Rename => {
+                let (renamed, keys) = (2..).map(|number| {
+                    let renamed = numbered_path(&out_path, number);
+                    let keys = claim_keys(&renamed, kind, options);
+                    (renamed, keys)
+                }).find(|(_, keys)| !keys.iter().any(|key| self.claimed.contains_key(key))).unwrap();
+                self.claim_all(keys, entry);
+                let shown = renamed.strip_prefix(&options.output_dir).unwrap_or(&renamed);
+                options.progress.warn(format!("\"{}\" (entry {}) would be extracted to the same file as \"{}\", so it's extracted as \"{}\" instead",
+                                              entry.name, index, earlier, shown.display()));
+                Some(Collision::Rename(renamed))
+            },
+        }
+    }
+
+    fn claim_all(&mut self, keys : Vec<String>, entry : &ZfsEntry) {
+        for key in keys {
+            self.claimed.insert(key, entry.name.clone());
+        }
+    }
+}
+
+/// Works out which of the `wanted` entries of `files` (by index) collide with
+/// an earlier one, and what happens to them, before any are extracted.
+///
+/// Doing this up front means the same entries are renamed or skipped however
+/// many threads extract them, in whatever order.
+pub(crate) fn plan_collisions(files : &[ZfsEntry], wanted : &[usize], options : &ExtractOptions) -> HashMap<usize, Collision> {
+    let mut claims = OutputClaims::default();
+    wanted.iter().filter_map(|&index| claims.claim(&files[index], index, options).map(|collision| (index, collision))).collect()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn sanitized(name : &str) -> Option<String> {
+        sanitize_entry_name(name).map(|path| path.to_string_lossy().replace('\\', "/"))
+    }
+
+    #[test]
+    fn parent_components_are_dropped() {
+        assert_eq!(sanitized("..\\..\\foo.rim").as_deref(), Some("foo.rim"));
+        assert_eq!(sanitized("a/../b/./c.txt").as_deref(), Some("a/b/c.txt"));
+        assert_eq!(sanitized(".."), None);
+    }
+
+    #[test]
+    fn absolute_paths_stay_relative() {
+        assert_eq!(sanitized("/etc/passwd").as_deref(), Some("etc/passwd"));
+        assert_eq!(sanitized("\\\\server\\share\\x.wav").as_deref(), Some("server/share/x.wav"));
+    }
+
+    #[test]
+    fn drive_prefixes_are_dropped() {
+        assert_eq!(sanitized("C:\\foo\\bar.rim").as_deref(), Some("foo/bar.rim"));
+        assert_eq!(sanitized("c:foo.txt").as_deref(), Some("foo.txt"));
+        assert_eq!(sanitized("C:"), None);
+    }
+
+    #[test]
+    fn device_names_are_renamed() {
+        assert_eq!(sanitized("CON").as_deref(), Some("CON_"));
+        assert_eq!(sanitized("gfx/nul.txt").as_deref(), Some("gfx/nul_.txt"));
+        assert_eq!(sanitized("Com1.rim").as_deref(), Some("Com1_.rim"));
+        assert_eq!(sanitized("lpt9").as_deref(), Some("lpt9_"));
+        assert_eq!(sanitized("CONSOLE.txt").as_deref(), Some("CONSOLE.txt"));
+        assert_eq!(sanitized("com0").as_deref(), Some("com0"));
+    }
+
+    fn entry(name : &str) -> ZfsEntry {
+        ZfsEntry {
+            name : name.to_string(),
+            offset : 0,
+            size : 0,
+            timestamp : std::time::UNIX_EPOCH,
+            flags : Default::default(),
+            unknown : 0,
+            table_entry_offset : 0,
+        }
+    }
+
+    #[test]
+    fn files_written_alongside_images_are_claimed() {
+        let files = [entry("up01.rim"), entry("up01.rim.json"), entry("up02.rim"), entry("UP02.RIM.raw16")];
+        let options = ExtractOptions { output_dir : PathBuf::from("out"), image_format : ImageFormat::Raw16, ..ExtractOptions::default() };
+        let paths = plan_output_paths(&files, &options);
+        assert_eq!(paths[&0], Path::new("out/up01.rim.raw16"));
+        assert_eq!(paths[&1], Path::new("out/up01.rim~2.json"));
+        assert_eq!(paths[&2], Path::new("out/up02.rim.raw16"));
+        assert_eq!(paths[&3], Path::new("out/UP02.RIM~2.raw16"));
+
+        let options = ExtractOptions { on_duplicate : DuplicatePolicy::Skip, ..options };
+        let paths = plan_output_paths(&files[..2], &options);
+        assert_eq!(paths.keys().collect::<Vec<_>>(), [&0]);
+    }
+
+    #[test]
+    fn forbidden_characters_are_replaced() {
+        assert_eq!(sanitized("what?<*>.txt").as_deref(), Some("what____.txt"));
+        assert_eq!(sanitized("trailing. . ").as_deref(), Some("trailing"));
+    }
+}
@@ -0,0 +1,73 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Perceptual hashes, for finding images which look alike even if they've
+//! been rescaled or recompressed (e.g. a screenshot of a button).
+
+use std::io;
+
+/// Computes a 64-bit difference hash ("dHash") of an 8-bit RGBA image.
+///
+/// The image is shrunk to 9×8 greyscale by averaging, and each bit says
+/// whether a cell is darker than the one to its right. Similar images have
+/// hashes which differ in only a few bits: see `hash_distance()`.
+pub fn dhash(width : u32, height : u32, rgba : &[u8]) -> io::Result<u64> {
+    let (width, height) = (width as usize, height as usize);
+    if width == 0 || height == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Can't hash an empty image"));
+    }
+    if rgba.len() != width * height * 4 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+            format!("RGBA data for a {}×{} image should be {} bytes, not {}", width, height, width * height * 4, rgba.len())));
+    }
+
+    const COLUMNS : usize = 9;
+    const ROWS : usize = 8;
+    let mut cells = [[0u32; COLUMNS]; ROWS];
+    for (row, cell_row) in cells.iter_mut().enumerate() {
+        // Every cell covers at least one pixel, even in tiny images.
+        let y0 = row * height / ROWS;
+        let y1 = ((row + 1) * height / ROWS).max(y0 + 1).min(height);
+        for (column, cell) in cell_row.iter_mut().enumerate() {
+            let x0 = column * width / COLUMNS;
+            let x1 = ((column + 1) * width / COLUMNS).max(x0 + 1).min(width);
+            let mut total = 0u64;
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let px = &rgba[(y * width + x) * 4..];
+                    total += px[0] as u64 * 299 + px[1] as u64 * 587 + px[2] as u64 * 114;
+                }
+            }
+            *cell = (total / ((y1 - y0) * (x1 - x0)) as u64) as u32;
+        }
+    }
+
+    let mut hash = 0u64;
+    for cell_row in &cells {
+        for pair in cell_row.windows(2) {
+            hash = (hash << 1) | (pair[0] < pair[1]) as u64;
+        }
+    }
+    Ok(hash)
+}
+
+/// The number of bits which differ between two hashes: 0 for images which
+/// look the same, up to 64.
+pub fn hash_distance(a : u64, b : u64) -> u32 {
+    (a ^ b).count_ones()
+}
@@ -0,0 +1,133 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Decoders loaded from shared libraries, so that decoders for rare formats
+//! can be shipped without rebuilding unciv.
+//!
+//! A plugin exports the functions declared in `include/unciv_plugin.h`.
+//! `UNCIV_PLUGIN_ABI_VERSION` changes whenever they do, and plugins built
+//! for a different version are refused rather than called.
+
+use decoder::{Decoded, Decoder};
+use libloading::Library;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::raw::{c_char, c_int};
+use std::path::Path;
+use std::ptr;
+use std::slice;
+
+/// The version of the plugin ABI this build of unciv uses.
+pub const UNCIV_PLUGIN_ABI_VERSION : u32 = 1;
+
+/// What a plugin's decode function fills in. Whatever it points to belongs
+/// to the plugin, and is given back to its free function once unciv is done
+/// with it.
+#[repr(C)]
+struct UncivPluginOutput
+{
+    data : *mut u8,
+    len : usize,
+    /// The extension for the converted data, without the dot.
+    extension : *const c_char,
+    /// Why decoding failed, or NULL.
+    error : *const c_char,
+}
+
+type AbiVersionFn = unsafe extern "C" fn() -> u32;
+type NameFn = unsafe extern "C" fn() -> *const c_char;
+type ProbeFn = unsafe extern "C" fn(name : *const c_char, header : *const u8, header_len : usize) -> c_int;
+type DecodeFn = unsafe extern "C" fn(name : *const c_char, data : *const u8, len : usize, output : *mut UncivPluginOutput) -> c_int;
+type FreeFn = unsafe extern "C" fn(output : *mut UncivPluginOutput);
+
+/// A decoder from a shared library.
+pub struct Plugin
+{
+    name : String,
+    probe : ProbeFn,
+    decode : DecodeFn,
+    free : FreeFn,
+    // The functions above point into the library, so it must outlive them.
+    _library : Library,
+}
+
+/// Copies a string from a plugin, which may be NULL.
+unsafe fn plugin_string(string : *const c_char) -> Option<String> {
+    if string.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(string).to_string_lossy().into_owned())
+    }
+}
+
+impl Plugin
+{
+    /// Loads the plugin at `path`.
+    ///
+    /// Loading a library runs its initialisation code, so only plugins from
+    /// people you trust should be loaded.
+    pub fn load(path : &Path) -> io::Result<Plugin> {
+        let error = |message : String| io::Error::new(io::ErrorKind::InvalidData, format!("Can't load the plugin {}: {}", path.display(), message));
+        unsafe {
+            let library = Library::new(path).map_err(|err| error(err.to_string()))?;
+            let abi_version = *library.get::<AbiVersionFn>(b"unciv_plugin_abi_version\0").map_err(|err| error(err.to_string()))?;
+            let version = abi_version();
+            if version != UNCIV_PLUGIN_ABI_VERSION {
+                return Err(error(format!("it was built for version {} of the plugin ABI, but unciv uses version {}", version, UNCIV_PLUGIN_ABI_VERSION)));
+            }
+            let name_fn = *library.get::<NameFn>(b"unciv_plugin_name\0").map_err(|err| error(err.to_string()))?;
+            let probe = *library.get::<ProbeFn>(b"unciv_plugin_probe\0").map_err(|err| error(err.to_string()))?;
+            let decode = *library.get::<DecodeFn>(b"unciv_plugin_decode\0").map_err(|err| error(err.to_string()))?;
+            let free = *library.get::<FreeFn>(b"unciv_plugin_free\0").map_err(|err| error(err.to_string()))?;
+            let name = plugin_string(name_fn()).unwrap_or_else(|| path.display().to_string());
+            Ok(Plugin { name, probe, decode, free, _library : library })
+        }
+    }
+}
+
+impl Decoder for Plugin
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn probe(&self, name : &str, header : &[u8]) -> bool {
+        // Names with NULs in them can't be given to C.
+        let Ok(name) = CString::new(name) else { return false };
+        unsafe { (self.probe)(name.as_ptr(), header.as_ptr(), header.len()) != 0 }
+    }
+
+    fn decode(&self, name : &str, data : &[u8]) -> io::Result<Decoded> {
+        let name = CString::new(name).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Entry names with NULs in them can't be given to plugins"))?;
+        let mut output = UncivPluginOutput { data : ptr::null_mut(), len : 0, extension : ptr::null(), error : ptr::null() };
+        unsafe {
+            let code = (self.decode)(name.as_ptr(), data.as_ptr(), data.len(), &mut output);
+            let result = if code != 0 {
+                let reason = plugin_string(output.error).unwrap_or_else(|| format!("error {}", code));
+                Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} couldn't decode it: {}", self.name, reason)))
+            } else if output.data.is_null() && output.len > 0 {
+                Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} gave no data", self.name)))
+            } else {
+                let converted = if output.len == 0 { Vec::new() } else { slice::from_raw_parts(output.data, output.len).to_vec() };
+                Ok(Decoded { data : converted, extension : plugin_string(output.extension).unwrap_or_default() })
+            };
+            (self.free)(&mut output);
+            result
+        }
+    }
+}
@@ -0,0 +1,103 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Small PNG previews of images in archives, for galleries, web services,
+//! and the like.
+
+use encode::write_png;
+use rim::{ColorKey, RimImage};
+use std::io;
+use std::io::{Read, Seek};
+use EntryKind;
+use ZfsEntry;
+
+/// Decodes an image entry, shrinks it to fit within `max_dim` pixels each
+/// way, and returns it encoded as a PNG.
+///
+/// Images are never enlarged, and keep their aspect ratio. Fails with
+/// `InvalidData` if the entry isn't an image.
+pub fn preview(entry : &ZfsEntry, reader : &mut (impl Read + Seek), max_dim : u32) -> io::Result<Vec<u8>> {
+    preview_with(entry, reader, max_dim, ColorKey::None)
+}
+
+/// Like `preview()`, but with pixels matching `color_key` made transparent
+/// (before shrinking, so they don't bleed into their neighbours).
+pub fn preview_with(entry : &ZfsEntry, reader : &mut (impl Read + Seek), max_dim : u32, color_key : ColorKey) -> io::Result<Vec<u8>> {
+    let data = entry.read_data(reader)?;
+    if EntryKind::detect(&entry.name, &data) != EntryKind::Rim {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} isn't an image", entry.name)));
+    }
+    let image = RimImage::from_stream(&mut &data[..])?;
+    let rgba = image.to_rgba_bytes_with(color_key)?;
+    let (width, height, rgba) = shrink(image.width as u32, image.height as u32, &rgba, max_dim);
+
+    let mut png = Vec::new();
+    write_png(&mut png, width, height, &rgba)?;
+    Ok(png)
+}
+
+/// Shrinks an 8-bit RGBA image to fit within `max_dim` pixels each way, by
+/// averaging. Returns the new width, height, and pixels.
+///
+/// Colours are weighted by alpha, so that transparent pixels (whose colour
+/// is usually meaningless) don't tint the edges of what's left.
+pub fn shrink(width : u32, height : u32, rgba : &[u8], max_dim : u32) -> (u32, u32, Vec<u8>) {
+    let max_dim = max_dim.max(1);
+    if width <= max_dim && height <= max_dim {
+        return (width, height, rgba.to_vec());
+    }
+    let (new_width, new_height) = if width >= height {
+        (max_dim, ((height as u64 * max_dim as u64 + width as u64 / 2) / width as u64).max(1) as u32)
+    } else {
+        (((width as u64 * max_dim as u64 + height as u64 / 2) / height as u64).max(1) as u32, max_dim)
+    };
+
+    let (width, height) = (width as usize, height as usize);
+    let (new_width, new_height) = (new_width as usize, new_height as usize);
+    let mut shrunk = Vec::with_capacity(new_width * new_height * 4);
+    for row in 0..new_height {
+        let y0 = row * height / new_height;
+        let y1 = ((row + 1) * height / new_height).max(y0 + 1);
+        for column in 0..new_width {
+            let x0 = column * width / new_width;
+            let x1 = ((column + 1) * width / new_width).max(x0 + 1);
+            let mut totals = [0u64; 4];
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let px = &rgba[(y * width + x) * 4..];
+                    let alpha = px[3] as u64;
+                    for channel in 0..3 {
+                        totals[channel] += px[channel] as u64 * alpha;
+                    }
+                    totals[3] += alpha;
+                }
+            }
+            let count = ((y1 - y0) * (x1 - x0)) as u64;
+            match totals[3] {
+                0 => shrunk.extend_from_slice(&[0, 0, 0, 0]),
+                alpha => {
+                    for total in &totals[..3] {
+                        shrunk.push(((total + alpha / 2) / alpha) as u8);
+                    }
+                    shrunk.push(((alpha + count / 2) / count) as u8);
+                },
+            }
+        }
+    }
+    (new_width as u32, new_height as u32, shrunk)
+}
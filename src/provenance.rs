@@ -0,0 +1,155 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Records where extracted files came from, in extended attributes.
+//!
+//! On Linux and macOS these are xattrs (`user.unciv.archive` and so on, or
+//! `unciv.archive` on macOS, which has no namespaces). On Windows they are
+//! alternate data streams (`file.png:unciv.archive`). Other platforms aren't
+//! supported.
+
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+const fn make_crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC32_TABLE : [u32; 256] = make_crc32_table();
+
+/// A reader which computes the CRC-32 (as used by zip and PNG) of everything
+/// read through it.
+pub struct Crc32Reader<R>
+{
+    inner : R,
+    crc : u32,
+}
+
+impl<R : Read> Crc32Reader<R>
+{
+    pub fn new(inner : R) -> Crc32Reader<R> {
+        Crc32Reader { inner, crc : !0 }
+    }
+
+    /// The CRC-32 of the data read so far.
+    pub fn crc32(&self) -> u32 {
+        !self.crc
+    }
+}
+
+impl<R : Read> Read for Crc32Reader<R>
+{
+    fn read(&mut self, buf : &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        for &byte in &buf[..len] {
+            self.crc = CRC32_TABLE[((self.crc ^ byte as u32) & 0xff) as usize] ^ (self.crc >> 8);
+        }
+        Ok(len)
+    }
+}
+
+/// Where an extracted file came from.
+#[derive(Clone, Debug)]
+pub struct Provenance
+{
+    /// The name of the archive, as given by the user.
+    pub archive : String,
+    pub index : usize,
+    /// The offset of the entry's data within the archive.
+    pub offset : usize,
+    /// The CRC-32 of the entry's data, before any conversion.
+    pub crc32 : u32,
+}
+
+impl Provenance
+{
+    /// The attributes to record, as (name, value) pairs.
+    pub fn attributes(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("archive", self.archive.clone()),
+            ("index", self.index.to_string()),
+            ("offset", self.offset.to_string()),
+            ("crc32", format!("{:08x}", self.crc32)),
+        ]
+    }
+
+    /// Records the provenance in `path`'s extended attributes.
+    pub fn write_to(&self, path : &Path) -> io::Result<()> {
+        for (name, value) in self.attributes() {
+            set_attribute(path, name, value.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn set_attribute(path : &Path, name : &str, value : &[u8]) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::raw::{c_char, c_int, c_void};
+    use std::os::unix::ffi::OsStrExt;
+
+    extern "C" {
+        #[cfg(target_os = "linux")]
+        fn setxattr(path : *const c_char, name : *const c_char, value : *const c_void, size : usize, flags : c_int) -> c_int;
+        #[cfg(target_os = "macos")]
+        fn setxattr(path : *const c_char, name : *const c_char, value : *const c_void, size : usize, position : u32, options : c_int) -> c_int;
+    }
+
+    let invalid = |_| io::Error::new(io::ErrorKind::InvalidInput, "Path contains a NUL byte");
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(invalid)?;
+    #[cfg(target_os = "linux")]
+    let c_name = CString::new(format!("user.unciv.{}", name)).map_err(invalid)?;
+    #[cfg(target_os = "macos")]
+    let c_name = CString::new(format!("unciv.{}", name)).map_err(invalid)?;
+
+    // SAFETY: Both strings are NUL-terminated, and value points to value.len() bytes.
+    #[cfg(target_os = "linux")]
+    let result = unsafe { setxattr(c_path.as_ptr(), c_name.as_ptr(), value.as_ptr() as *const c_void, value.len(), 0) };
+    #[cfg(target_os = "macos")]
+    let result = unsafe { setxattr(c_path.as_ptr(), c_name.as_ptr(), value.as_ptr() as *const c_void, value.len(), 0, 0) };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn set_attribute(path : &Path, name : &str, value : &[u8]) -> io::Result<()> {
+    // NTFS alternate data streams are opened as "file:stream".
+    let mut stream_path = path.as_os_str().to_owned();
+    stream_path.push(format!(":unciv.{}", name));
+    std::fs::write(stream_path, value)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn set_attribute(_path : &Path, _name : &str, _value : &[u8]) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Extended attributes aren't supported on this platform"))
+}
@@ -0,0 +1,139 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! The fields of an archive's header and file table records as they're
+//! stored, including the ones whose meaning isn't known, for working out
+//! the rest of the format.
+//!
+//! Nothing here is interpreted: unciv reads and writes these values, but
+//! never acts on them.
+
+use std::fmt;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// The flags in an entry's file table record.
+///
+/// They're zero in every archive unciv has been tested with, and the game
+/// doesn't seem to care about them, so no bit's meaning is known yet. This
+/// keeps the bits as they're stored, and says which are set: as meanings
+/// are found, they'll get constants here and be added to `KNOWN`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct ZfsEntryFlags(u32);
+
+impl ZfsEntryFlags
+{
+    /// The bits whose meaning is known.
+    pub const KNOWN : u32 = 0;
+
+    pub fn from_raw(raw : u32) -> ZfsEntryFlags {
+        ZfsEntryFlags(raw)
+    }
+
+    /// The flags as they're stored.
+    pub fn raw(self) -> u32 {
+        self.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// The bits which are set, but whose meaning isn't known.
+    pub fn unknown(self) -> u32 {
+        self.0 & !ZfsEntryFlags::KNOWN
+    }
+
+    /// The numbers of the bits which are set, from 0 (the lowest) up.
+    pub fn set_bits(self) -> impl Iterator<Item = u32> {
+        (0..32).filter(move |bit| self.0 & (1 << bit) != 0)
+    }
+}
+
+impl From<u32> for ZfsEntryFlags
+{
+    fn from(raw : u32) -> ZfsEntryFlags {
+        ZfsEntryFlags(raw)
+    }
+}
+
+impl From<ZfsEntryFlags> for u32
+{
+    fn from(flags : ZfsEntryFlags) -> u32 {
+        flags.0
+    }
+}
+
+impl fmt::LowerHex for ZfsEntryFlags
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+/// Shows the flags in hex, followed by which bits are set, e.g.
+/// `0x00000005 (bits 0, 2)`.
+impl fmt::Display for ZfsEntryFlags
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:08x}", self.0)?;
+        if !self.is_empty() {
+            let bits : Vec<String> = self.set_bits().map(|bit| bit.to_string()).collect();
+            write!(f, " ({} {})", if bits.len() == 1 { "bit" } else { "bits" }, bits.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// An archive's header, after the `ZFS3` signature, as it's stored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct RawHeader
+{
+    pub version : u32,
+    /// The size of each record's (zero-padded) name field.
+    pub max_filename_len : u32,
+    pub files_per_table : u32,
+    /// How many entries the header claims there are, which may not be how
+    /// many the file tables hold.
+    pub num_files : u32,
+    /// The field between the entry count and the first file table's offset,
+    /// whose meaning isn't known.
+    pub unknown : u32,
+    pub filetable_offset : u32,
+}
+
+/// An entry's file table record, other than its name, as it's stored.
+///
+/// Records in versions other than 1 may have more fields after these, which
+/// aren't kept.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct RawRecord
+{
+    pub offset : u32,
+    /// The field between the data's offset and size, whose meaning isn't
+    /// known.
+    pub unknown : u32,
+    pub size : u32,
+    /// Seconds since 1970.
+    pub timestamp : u32,
+    pub flags : ZfsEntryFlags,
+}
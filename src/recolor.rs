@@ -0,0 +1,253 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Recolouring RIM images, for seasonal terrain, colourblind-friendly
+//! palettes, and the like.
+//!
+//! Recolourings are described by an INI-style (or TOML) file, for example:
+//!
+//! ```text
+//! # Adjustments to every pixel, other than those swapped below.
+//! hue = 30          # degrees to rotate the hue by
+//! saturation = 0.8  # multiplies the saturation
+//! brightness = 1.1  # multiplies the brightness
+//! # Pixels matching this colour key are left alone.
+//! keep = "magenta"
+//!
+//! # Exact colour swaps.
+//! [palette]
+//! "#408020" = "#806020"
+//! ```
+
+use config::{parse_ini, unquote};
+use rim::{ColorKey, RimImage};
+use std::io;
+use std::path::Path;
+
+/// A recolouring to apply to images.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recolor
+{
+    /// Colours to replace, and what to replace them with. Colours are
+    /// matched after being converted to the image's pixel format.
+    pub palette : Vec<([u8; 3], [u8; 3])>,
+    /// Degrees to rotate the hue of the other pixels by.
+    pub hue : f32,
+    pub saturation : f32,
+    pub brightness : f32,
+    /// Pixels matching this aren't changed at all, so sprites stay
+    /// transparent.
+    pub keep : ColorKey,
+}
+
+impl Default for Recolor
+{
+    fn default() -> Recolor {
+        Recolor {
+            palette : Vec::new(),
+            hue : 0.0,
+            saturation : 1.0,
+            brightness : 1.0,
+            keep : ColorKey::None,
+        }
+    }
+}
+
+/// Parses a colour like "#408020".
+pub fn parse_color(text : &str) -> io::Result<[u8; 3]> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Invalid colour \"{}\" (expected \"#rrggbb\")", text));
+    let hex = text.strip_prefix('#').ok_or_else(invalid)?;
+    if hex.len() != 6 {
+        return Err(invalid());
+    }
+    let rgb = u32::from_str_radix(hex, 16).map_err(|_| invalid())?;
+    Ok([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8])
+}
+
+fn parse_number(key : &str, value : &str) -> io::Result<f32> {
+    match value.parse::<f32>() {
+        Ok(number) if number.is_finite() => Ok(number),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("\"{}\" should be a number, not \"{}\"", key, value))),
+    }
+}
+
+fn rgb_to_hsv(rgb : [u8; 3]) -> (f32, f32, f32) {
+    let [r, g, b] = rgb.map(|c| c as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+    (hue, saturation, max)
+}
+
+fn hsv_to_rgb(hue : f32, saturation : f32, value : f32) -> [u8; 3] {
+    let chroma = value * saturation;
+    let sector = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (sector.rem_euclid(2.0) - 1.0).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = value - chroma;
+    [r, g, b].map(|c| ((c + m) * 255.0).round().clamp(0.0, 255.0) as u8)
+}
+
+/// Strips a comment after a value, as TOML allows: a `#` which isn't in
+/// quotes, and comes after a space. (A value starting with `#` is a colour.)
+fn strip_comment(value : &str) -> &str {
+    let mut quote = None;
+    let mut after_space = false;
+    for (i, c) in value.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(open), c) if c == open => quote = None,
+            (None, '#') if after_space => return value[..i].trim(),
+            _ => {},
+        }
+        after_space = c.is_whitespace();
+    }
+    value.trim()
+}
+
+impl Recolor
+{
+    pub fn parse(text : &str) -> io::Result<Recolor> {
+        let mut recolor = Recolor::default();
+        for section in parse_ini(text)? {
+            match section.name.as_str() {
+                "" => for (key, value) in section.entries {
+                    let value = unquote(strip_comment(&value));
+                    match key.as_str() {
+                        "hue" => recolor.hue = parse_number(&key, value)?,
+                        "saturation" => recolor.saturation = parse_number(&key, value)?,
+                        "brightness" => recolor.brightness = parse_number(&key, value)?,
+                        "keep" => recolor.keep = value.parse()?,
+                        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown setting \"{}\"", key))),
+                    }
+                },
+                "palette" => for (from, to) in section.entries {
+                    recolor.palette.push((parse_color(&from)?, parse_color(unquote(strip_comment(&to)))?));
+                },
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown section \"{}\"", section.name))),
+            }
+        }
+        Ok(recolor)
+    }
+
+    pub fn load(path : &Path) -> io::Result<Recolor> {
+        let text = std::fs::read_to_string(path)?;
+        Recolor::parse(&text).map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))
+    }
+
+    /// Returns true if the hue, saturation, or brightness are changed, not
+    /// just the palette.
+    fn adjusts(&self) -> bool {
+        self.hue.rem_euclid(360.0) != 0.0 || self.saturation != 1.0 || self.brightness != 1.0
+    }
+
+    /// Recolours an image in place, returning the number of pixels changed.
+    pub fn apply(&self, image : &mut RimImage) -> usize {
+        let format = image.format;
+        // Match palette colours in the image's own format, so that they
+        // survive being rounded to 5 or 6 bits per channel.
+        let palette : Vec<(u16, u16)> = self.palette.iter()
+            .map(|(from, to)| (format.encode_pixel(from), format.encode_pixel(to)))
+            .collect();
+
+        let mut changed = 0;
+        for px in image.data.iter_mut() {
+            if self.keep.matches(*px, format) {
+                continue;
+            }
+            // The top bit of RGB555 pixels is unused.
+            let colour = format.encode_pixel(&format.decode_pixel(*px));
+            let new_px = match palette.iter().find(|&&(from, _)| from == colour) {
+                Some(&(_, to)) => to,
+                None if self.adjusts() => {
+                    let [r, g, b, _] = format.decode_pixel(*px);
+                    let (hue, saturation, value) = rgb_to_hsv([r, g, b]);
+                    let rgb = hsv_to_rgb(hue + self.hue, (saturation * self.saturation).clamp(0.0, 1.0), (value * self.brightness).clamp(0.0, 1.0));
+                    format.encode_pixel(&rgb)
+                },
+                None => continue,
+            };
+            if new_px != colour {
+                *px = new_px;
+                changed += 1;
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn the_example_parses() {
+        // The example from the module docs, with a comment after a palette
+        // entry and some unquoted colours.
+        let example = r##"
+# Adjustments to every pixel, other than those swapped below.
+hue = 30          # degrees to rotate the hue by
+saturation = 0.8  # multiplies the saturation
+brightness = 1.1  # multiplies the brightness
+# Pixels matching this colour key are left alone.
+keep = "magenta"
+
+# Exact colour swaps.
+[palette]
+"#408020" = "#806020"
+"#204010" = "#403010"  # darker grass
+'#102030' = #302010
+"##;
+        assert_eq!(Recolor::parse(example).unwrap(), Recolor {
+            palette : vec![
+                ([0x40, 0x80, 0x20], [0x80, 0x60, 0x20]),
+                ([0x20, 0x40, 0x10], [0x40, 0x30, 0x10]),
+                ([0x10, 0x20, 0x30], [0x30, 0x20, 0x10]),
+            ],
+            hue : 30.0,
+            saturation : 0.8,
+            brightness : 1.1,
+            keep : ColorKey::Magenta,
+        });
+    }
+
+    #[test]
+    fn comments_in_quotes_are_kept() {
+        assert_eq!(strip_comment("\"a # b\"  # comment"), "\"a # b\"");
+        assert_eq!(strip_comment("#408020 # comment"), "#408020");
+        assert_eq!(strip_comment("'#408020'"), "'#408020'");
+        assert_eq!(strip_comment("30"), "30");
+    }
+}
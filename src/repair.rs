@@ -0,0 +1,337 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Recovering what can be saved from damaged archives, such as partial
+//! downloads or corrupted backups.
+
+use builder::ZfsBuilder;
+use std::collections::HashSet;
+use std::io;
+use std::ops::Range;
+use std::time::SystemTime;
+use ZfsEntry;
+use ZfsHeader;
+use RECORD_FIELDS_SIZE;
+
+/// An entry found in a damaged archive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecoveredEntry
+{
+    pub name : String,
+    pub offset : usize,
+    pub size : usize,
+    pub timestamp : SystemTime,
+    pub flags : u32,
+    /// True if the entry was found by its signature, rather than in a file
+    /// table. Carved entries are named after their offset, in hex (shortened
+    /// if that won't fit in the archive's names).
+    pub carved : bool,
+}
+
+/// Everything that could be recovered from a damaged archive.
+#[derive(Clone, Debug)]
+pub struct Recovery
+{
+    pub version : u32,
+    pub max_filename_len : u32,
+    pub files_per_table : u32,
+    pub entries : Vec<RecoveredEntry>,
+    /// How many table records were found, but pointed outside the archive
+    /// or repeated an earlier entry's name.
+    pub dropped : usize,
+    /// How many carved files were left out, as no name for them would fit
+    /// in the archive's names.
+    pub unnamed : usize,
+}
+
+/// Reads as many file table records as possible, stopping at the first
+/// table which is out of bounds or has been seen before. Returns the
+/// records, and the byte ranges the header and tables take up.
+fn read_tables(data : &[u8], header : &ZfsHeader) -> (Vec<ZfsEntry>, Vec<Range<usize>>) {
+    let record_size = (header.max_filename_len + header.record_fields_size) as usize;
+    let table_size = 4 + record_size * header.files_per_table as usize;
+    let mut records = Vec::new();
+    // The header is 28 bytes.
+    let mut structure = Vec::new();
+    structure.push(0..28);
+    let mut visited = HashSet::new();
+    let mut offset = header.filetable_offset as usize;
+    while records.len() < header.num_files as usize && offset + table_size <= data.len() && visited.insert(offset) {
+        structure.push(offset..offset + table_size);
+        let next_offset = u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]) as usize;
+        let mut found = 0;
+        for i in 0..header.files_per_table as usize {
+            let record_offset = offset + 4 + i * record_size;
+            match ZfsEntry::read_record(&mut &data[record_offset..], header, record_offset as u64) {
+                Ok(Some(entry)) => records.push(entry),
+                _ => break,
+            }
+            found += 1;
+        }
+        if found < header.files_per_table as usize || next_offset == 0 {
+            break;
+        }
+        offset = next_offset;
+    }
+    (records, structure)
+}
+
+/// If there's a RIM or WAV file starting at `offset`, returns its size.
+fn carve_at(data : &[u8], offset : usize) -> Option<usize> {
+    let rest = &data[offset..];
+    let u16_at = |at : usize| u16::from_le_bytes([rest[at], rest[at + 1]]) as usize;
+    let size = if rest.starts_with(b"RIMF") && rest.len() >= 16 {
+        let (width, height, pitch, format) = (u16_at(8), u16_at(10), u16_at(12), u16_at(14));
+        if width == 0 || height == 0 || pitch < width * 2 || format > 1 {
+            return None;
+        }
+        16 + pitch * height
+    } else if rest.starts_with(b"RIFF") && rest.len() >= 12 && &rest[8..12] == b"WAVE" {
+        8 + u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize
+    } else {
+        return None;
+    };
+    if size <= rest.len() {
+        Some(size)
+    } else {
+        None
+    }
+}
+
+/// Names a carved entry after its offset, as briefly as it takes to fit in
+/// `max_len` bytes without reusing a name, or returns None if nothing will.
+fn carved_name(offset : usize, extension : &str, max_len : usize, names : &HashSet<String>) -> Option<String> {
+    let candidates = vec![format!("{:08x}.{}", offset, extension), format!("{:x}.{}", offset, extension), format!("{:x}", offset)];
+    candidates.into_iter().find(|name| name.len() <= max_len && !names.contains(name))
+}
+
+/// Sorts `ranges`, merging any which overlap or touch.
+fn merge_ranges(mut ranges : Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.retain(|range| !range.is_empty());
+    ranges.sort_by_key(|range| range.start);
+    let mut merged : Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// Recovers entries from the contents of a damaged archive.
+///
+/// Entries are taken from whatever file tables can still be read, keeping
+/// those which lie within the data. If `carve` is set, RIM images and WAV
+/// sounds which aren't part of any recovered entry are found by their
+/// signatures, too.
+pub fn recover(data : &[u8], carve : bool) -> Recovery {
+    let mut header = ZfsHeader::read(&mut &data[..]).ok()
+        .filter(|header| header.files_per_table > 0);
+    if let Some(ref mut header) = header {
+        // If the layout of an unknown version can't be worked out, trying
+        // version 1's is better than recovering nothing from the tables.
+        let _ = header.detect_layout(&mut io::Cursor::new(data));
+    }
+    let mut recovery = Recovery {
+        // Recovered archives are written with version 1's records, so keep
+        // the version only if its records were the same.
+        version : header.as_ref().filter(|header| header.record_fields_size == RECORD_FIELDS_SIZE).map_or(1, |header| header.version),
+        max_filename_len : header.as_ref().map_or(16, |header| header.max_filename_len),
+        files_per_table : header.as_ref().map_or(100, |header| header.files_per_table),
+        entries : Vec::new(),
+        dropped : 0,
+        unnamed : 0,
+    };
+
+    let (records, mut covered) = match header {
+        Some(ref header) => read_tables(data, header),
+        None => (Vec::new(), Vec::new()),
+    };
+    let mut names = HashSet::new();
+    for record in records {
+        if record.offset + record.size > data.len() || !names.insert(record.name.clone()) {
+            recovery.dropped += 1;
+            continue;
+        }
+        covered.push(record.offset..record.offset + record.size);
+        recovery.entries.push(RecoveredEntry {
+            name : record.name,
+            offset : record.offset,
+            size : record.size,
+            timestamp : record.timestamp,
+            flags : record.flags.raw(),
+            carved : false,
+        });
+    }
+
+    if carve {
+        // The ranges are merged and sorted, so `next` is the first one which
+        // doesn't end before `offset`: everything before it is behind us.
+        let covered = merge_ranges(covered);
+        let mut next = 0;
+        let mut offset = 0;
+        while offset + 4 <= data.len() {
+            while next < covered.len() && covered[next].end <= offset {
+                next += 1;
+            }
+            // Skip over anything already accounted for.
+            if let Some(range) = covered.get(next).filter(|range| range.start <= offset) {
+                offset = range.end;
+                continue;
+            }
+            let next_start = covered.get(next).map_or(data.len(), |range| range.start);
+            match carve_at(data, offset) {
+                Some(size) if offset + size <= next_start => {
+                    let extension = if data[offset..].starts_with(b"RIMF") { "rim" } else { "wav" };
+                    match carved_name(offset, extension, recovery.max_filename_len as usize, &names) {
+                        Some(name) => {
+                            names.insert(name.clone());
+                            recovery.entries.push(RecoveredEntry {
+                                name,
+                                offset,
+                                size,
+                                timestamp : SystemTime::UNIX_EPOCH,
+                                flags : 0,
+                                carved : true,
+                            });
+                        },
+                        None => recovery.unnamed += 1,
+                    }
+                    offset += size;
+                },
+                _ => offset += 1,
+            }
+        }
+    }
+    recovery
+}
+
+impl Recovery
+{
+    /// Puts the recovered entries into a new archive with the same layout
+    /// as the damaged one, given its contents.
+    pub fn to_builder(&self, data : &[u8]) -> io::Result<ZfsBuilder> {
+        let mut builder = ZfsBuilder::with_layout(self.version, self.max_filename_len, self.files_per_table)?;
+        for entry in &self.entries {
+            let entry_data = data[entry.offset..entry.offset + entry.size].to_vec();
+            builder.add_with_flags(&entry.name, entry_data, entry.timestamp, entry.flags)?;
+        }
+        Ok(builder)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use rim::{RimFormat, RimImage};
+
+    fn rim() -> Vec<u8> {
+        let image = RimImage::new(1, 3, 2, RimFormat::Rgb565, vec![1, 2, 3, 4, 5, 6]).unwrap();
+        let mut data = Vec::new();
+        image.to_stream_aligned(&mut data, 4).unwrap();
+        data
+    }
+
+    fn wav() -> Vec<u8> {
+        b"RIFF\x0c\0\0\0WAVEfmt data".to_vec()
+    }
+
+    /// An archive with `max_filename_len`-byte names and two entries to a
+    /// table: two text files in the first table, and a RIM and a WAV in the
+    /// second, which has been wiped. Returns it, and where the RIM and WAV
+    /// are.
+    fn damaged_archive(max_filename_len : u32) -> (Vec<u8>, usize, usize) {
+        let mut builder = ZfsBuilder::with_layout(1, max_filename_len, 2).unwrap();
+        builder.add("a.txt", b"first".to_vec(), SystemTime::UNIX_EPOCH).unwrap();
+        builder.add("b.txt", b"second".to_vec(), SystemTime::UNIX_EPOCH).unwrap();
+        builder.add("c.rim", rim(), SystemTime::UNIX_EPOCH).unwrap();
+        builder.add("d.wav", wav(), SystemTime::UNIX_EPOCH).unwrap();
+        let mut data = Vec::new();
+        builder.write(&mut data).unwrap();
+
+        let u32_at = |data : &[u8], at : usize| u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]) as usize;
+        let second_table = u32_at(&data, u32_at(&data, 24));
+        let table_size = 4 + 2 * (max_filename_len as usize + RECORD_FIELDS_SIZE as usize);
+        let rim_offset = u32_at(&data, second_table + 4 + max_filename_len as usize);
+        let wav_offset = rim_offset + rim().len();
+        for byte in &mut data[second_table..second_table + table_size] {
+            *byte = 0;
+        }
+        (data, rim_offset, wav_offset)
+    }
+
+    fn names(recovery : &Recovery) -> Vec<(&str, bool)> {
+        recovery.entries.iter().map(|entry| (entry.name.as_str(), entry.carved)).collect()
+    }
+
+    #[test]
+    fn orphaned_files_are_carved() {
+        let (data, rim_offset, wav_offset) = damaged_archive(16);
+        let recovery = recover(&data, true);
+        let rim_name = format!("{:08x}.rim", rim_offset);
+        let wav_name = format!("{:08x}.wav", wav_offset);
+        assert_eq!(names(&recovery), [("a.txt", false), ("b.txt", false), (rim_name.as_str(), true), (wav_name.as_str(), true)]);
+        assert_eq!((recovery.entries[2].offset, recovery.entries[2].size), (rim_offset, rim().len()));
+        assert_eq!((recovery.entries[3].offset, recovery.entries[3].size), (wav_offset, wav().len()));
+        assert_eq!((recovery.dropped, recovery.unnamed), (0, 0));
+
+        let uncarved = recover(&data, false);
+        assert_eq!(names(&uncarved), [("a.txt", false), ("b.txt", false)]);
+    }
+
+    #[test]
+    fn carved_names_fit_short_name_fields() {
+        let (data, rim_offset, wav_offset) = damaged_archive(8);
+        let recovery = recover(&data, true);
+        let rim_name = format!("{:x}.rim", rim_offset);
+        let wav_name = format!("{:x}.wav", wav_offset);
+        assert_eq!(names(&recovery), [("a.txt", false), ("b.txt", false), (rim_name.as_str(), true), (wav_name.as_str(), true)]);
+        // Which means they can go into a new archive.
+        let builder = recovery.to_builder(&data).unwrap();
+        assert_eq!(builder.len(), 4);
+    }
+
+    #[test]
+    fn truncated_files_are_not_carved() {
+        let (mut data, _, wav_offset) = damaged_archive(16);
+        data.truncate(wav_offset + 10);
+        let recovery = recover(&data, true);
+        assert_eq!(recovery.entries.iter().filter(|entry| entry.carved).count(), 1);
+        assert!(recovery.entries.iter().all(|entry| !entry.name.ends_with(".wav")));
+    }
+
+    #[test]
+    fn carved_names_are_shortened_or_given_up() {
+        let mut names = HashSet::new();
+        assert_eq!(carved_name(0x1234, "rim", 16, &names).as_deref(), Some("00001234.rim"));
+        assert_eq!(carved_name(0x1234, "rim", 8, &names).as_deref(), Some("1234.rim"));
+        assert_eq!(carved_name(0x1234, "rim", 4, &names).as_deref(), Some("1234"));
+        assert_eq!(carved_name(0x1234, "rim", 3, &names), None);
+        names.insert("1234.rim".to_string());
+        assert_eq!(carved_name(0x1234, "rim", 8, &names).as_deref(), Some("1234"));
+    }
+
+    #[test]
+    fn ranges_are_merged() {
+        assert_eq!(merge_ranges(vec![10..20, 0..5, 15..30, 5..5, 30..31, 40..50]), [0..5, 10..31, 40..50]);
+        let nested = merge_ranges(vec![0..100, 10..20, 50..60]);
+        assert_eq!((nested.len(), &nested[0]), (1, &(0..100)));
+    }
+}
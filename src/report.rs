@@ -0,0 +1,129 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use job::Progress;
+use std::time::Duration;
+
+/// What happened to a single entry during extraction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExtractStatus
+{
+    /// The entry was written out unchanged.
+    Extracted,
+    /// The entry was converted (e.g. RIM to PNG) as it was written out.
+    Converted,
+    Skipped,
+    Failed(String),
+}
+
+impl ExtractStatus
+{
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ExtractStatus::Extracted => "extracted",
+            ExtractStatus::Converted => "converted",
+            ExtractStatus::Skipped => "skipped",
+            ExtractStatus::Failed(_) => "failed",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ExtractRecord
+{
+    /// The entry's position in the archive's file table.
+    pub index : usize,
+    pub name : String,
+    pub status : ExtractStatus,
+    pub bytes_read : u64,
+    pub bytes_written : u64,
+    /// The CRC-32 of the entry's data, if it was read.
+    pub crc32 : Option<u32>,
+    /// The progress line for the entry (e.g. "Extracting file …"), if it was
+    /// extracted or converted.
+    pub message : Option<String>,
+}
+
+impl ExtractRecord
+{
+    /// Logs the record's progress line to `progress`, if it has one.
+    pub fn log(&self, progress : &Progress) {
+        if let Some(ref message) = self.message {
+            progress.info(message.clone());
+        }
+    }
+}
+
+/// Totals for a whole extraction run.
+#[derive(Clone, Debug, Default)]
+pub struct ExtractStats
+{
+    pub processed : usize,
+    pub extracted : usize,
+    pub converted : usize,
+    pub skipped : usize,
+    pub failed : usize,
+    pub bytes_read : u64,
+    pub bytes_written : u64,
+    pub elapsed : Duration,
+}
+
+impl ExtractStats
+{
+    pub fn add(&mut self, record : &ExtractRecord) {
+        self.processed += 1;
+        match record.status {
+            ExtractStatus::Extracted => self.extracted += 1,
+            ExtractStatus::Converted => self.converted += 1,
+            ExtractStatus::Skipped => self.skipped += 1,
+            ExtractStatus::Failed(_) => self.failed += 1,
+        }
+        self.bytes_read += record.bytes_read;
+        self.bytes_written += record.bytes_written;
+    }
+
+    /// Bytes read from the archive per second of wall time.
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.bytes_read as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+/// The results of extracting an archive: one record per entry, in table order.
+///
+/// Records are kept sorted by index however they're added, so that reports
+/// from two runs over the same archive can be compared directly.
+#[derive(Clone, Debug, Default)]
+pub struct ExtractReport
+{
+    pub records : Vec<ExtractRecord>,
+    pub stats : ExtractStats,
+}
+
+impl ExtractReport
+{
+    pub fn push(&mut self, record : ExtractRecord) {
+        self.stats.add(&record);
+        let position = self.records.partition_point(|existing| existing.index <= record.index);
+        self.records.insert(position, record);
+    }
+}
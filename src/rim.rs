@@ -0,0 +1,479 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use encode;
+use json::JsonValue;
+use limits::Limits;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::str::FromStr;
+use ImageFormat;
+
+/// The pixel formats a RIM image can be stored in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RimFormat
+{
+    Rgb555,
+    Rgb565,
+}
+
+impl RimFormat
+{
+    pub fn from_raw(raw : u16) -> io::Result<RimFormat> {
+        match raw {
+            0 => Ok(RimFormat::Rgb555),
+            1 => Ok(RimFormat::Rgb565),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown RIM pixel format {}", raw))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RimFormat::Rgb555 => "RGB555",
+            RimFormat::Rgb565 => "RGB565",
+        }
+    }
+
+    pub fn to_raw(self) -> u16 {
+        match self {
+            RimFormat::Rgb555 => 0,
+            RimFormat::Rgb565 => 1,
+        }
+    }
+
+    /// Converts a pixel in this format to 8-bit RGBA (which is always opaque).
+    pub fn decode_pixel(self, px : u16) -> [u8; 4] {
+        match self {
+            RimFormat::Rgb555 => convert_555_888(px),
+            RimFormat::Rgb565 => convert_565_888(px),
+        }
+    }
+
+    /// Converts an 8-bit RGB (or RGBA) colour to a pixel in this format.
+    pub fn encode_pixel(self, rgb : &[u8]) -> u16 {
+        match self {
+            RimFormat::Rgb555 => convert_888_555(rgb),
+            RimFormat::Rgb565 => convert_888_565(rgb),
+        }
+    }
+}
+
+/// Which pixels of a RIM image are made transparent when it's converted to
+/// RGBA. RIM images have no alpha channel, so sprites use a key colour.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorKey
+{
+    /// Every pixel is opaque.
+    None,
+    /// Bright magenta (full red and blue, no green) is transparent.
+    Magenta,
+    /// Pixels which are zero (black) are transparent.
+    Zero,
+    /// Pixels with exactly this raw 16-bit value are transparent.
+    Raw(u16),
+}
+
+impl ColorKey
+{
+    pub fn matches(self, px : u16, format : RimFormat) -> bool {
+        match (self, format) {
+            (ColorKey::None, _) => false,
+            // The top bit of RGB555 pixels is unused.
+            (ColorKey::Magenta, RimFormat::Rgb555) => px & 0x7fff == 0x7c1f,
+            (ColorKey::Magenta, RimFormat::Rgb565) => px == 0xf81f,
+            (ColorKey::Zero, _) => px == 0,
+            (ColorKey::Raw(key), _) => px == key,
+        }
+    }
+}
+
+impl FromStr for ColorKey
+{
+    type Err = io::Error;
+
+    /// Parses "none", "magenta", "zero" (or "black"), or a raw pixel value
+    /// in hex, like "0x7c1f".
+    fn from_str(name : &str) -> io::Result<ColorKey> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" => Ok(ColorKey::None),
+            "magenta" => Ok(ColorKey::Magenta),
+            "zero" | "black" => Ok(ColorKey::Zero),
+            other => match other.strip_prefix("0x").map(|hex| u16::from_str_radix(hex, 16)) {
+                Some(Ok(px)) => Ok(ColorKey::Raw(px)),
+                _ => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                    format!("Unknown colour key \"{}\" (expected none, magenta, zero, or a pixel value like 0x7c1f)", name))),
+            },
+        }
+    }
+}
+
+fn convert_555_888(px555 : u16) -> [u8; 4] {
+    let red = (px555 >> 10) & 31;
+    let green = (px555 >> 5) & 31;
+    let blue = px555 & 31;
+    [(red << 3) as u8, (green << 3) as u8, (blue << 3) as u8, 255]
+}
+
+fn convert_565_888(px565 : u16) -> [u8; 4] {
+    let red = (px565 >> 11) & 31;
+    let green = (px565 >> 5) & 63;
+    let blue = px565 & 31;
+    [(red << 3) as u8, (green << 2) as u8, (blue << 3) as u8, 255]
+}
+
+fn convert_888_555(rgba : &[u8]) -> u16 {
+    ((rgba[0] as u16 >> 3) << 10) | ((rgba[1] as u16 >> 3) << 5) | (rgba[2] as u16 >> 3)
+}
+
+fn convert_888_565(rgba : &[u8]) -> u16 {
+    ((rgba[0] as u16 >> 3) << 11) | ((rgba[1] as u16 >> 2) << 5) | (rgba[2] as u16 >> 3)
+}
+
+/// Decodes a PNG of any colour type into 8-bit RGBA.
+fn decode_png_rgba(reader : impl Read) -> io::Result<(u32, u32, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut png_reader) = decoder.read_info()?;
+    let mut buffer = vec![0; info.buffer_size()];
+    png_reader.next_frame(&mut buffer)?;
+
+    if let png::ColorType::RGBA = info.color_type {
+        return Ok((info.width, info.height, buffer));
+    }
+
+    let mut rgba = Vec::with_capacity(info.width as usize * info.height as usize * 4);
+    match info.color_type {
+        png::ColorType::RGB => for px in buffer.chunks(3) {
+            rgba.extend_from_slice(&[px[0], px[1], px[2], 255]);
+        },
+        png::ColorType::GrayscaleAlpha => for px in buffer.chunks(2) {
+            rgba.extend_from_slice(&[px[0], px[0], px[0], px[1]]);
+        },
+        png::ColorType::Grayscale => for &px in &buffer {
+            rgba.extend_from_slice(&[px, px, px, 255]);
+        },
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Could not expand indexed PNG")),
+    }
+    Ok((info.width, info.height, rgba))
+}
+
+/// A decoded RIM image: the game's native 16-bit image format.
+///
+/// The pixel data is stored unconverted, one `u16` per pixel, with any row
+/// padding from the file's pitch removed.
+pub struct RimImage
+{
+    pub version : u32,
+    pub width : u16,
+    pub height : u16,
+    pub format : RimFormat,
+    pub data : Vec<u16>,
+}
+
+impl RimImage
+{
+    /// Creates an image from raw pixel data, checking that there is exactly
+    /// one pixel for every position in the image.
+    pub fn new(version : u32, width : u16, height : u16, format : RimFormat, data : Vec<u16>) -> io::Result<RimImage> {
+        let expected = width as usize * height as usize;
+        if data.len() != expected {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("RIM image is {}×{} but has {} pixels (expected {})", width, height, data.len(), expected)));
+        }
+        Ok(RimImage { version, width, height, format, data })
+    }
+
+    /// Reads a RIM image, within the default `Limits`. Rows are read in one
+    /// go, so there's no need to wrap `reader` in a `BufReader`.
+    pub fn from_stream(reader : &mut impl Read) -> io::Result<RimImage> {
+        RimImage::from_stream_with_limits(reader, &Limits::default())
+    }
+
+    /// Reads a RIM image, failing if it's bigger than `limits` allow.
+    pub fn from_stream_with_limits(reader : &mut impl Read, limits : &Limits) -> io::Result<RimImage> {
+        let rim_sig = reader.read_u32::<LittleEndian>()?;
+
+        // 'RIMF'
+        if rim_sig != 0x464d4952 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid RIM signature"));
+        }
+
+        let rim_ver = reader.read_u32::<LittleEndian>()?;
+        let rim_width = reader.read_u16::<LittleEndian>()?;
+        let rim_height = reader.read_u16::<LittleEndian>()?;
+        let rim_pitch = reader.read_u16::<LittleEndian>()?;
+        let rim_fmt = RimFormat::from_raw(reader.read_u16::<LittleEndian>()?)?;
+
+        if rim_width as u32 > limits.max_image_width || rim_height as u32 > limits.max_image_height {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("RIM image is {}×{}, bigger than the {}×{} allowed", rim_width, rim_height, limits.max_image_width, limits.max_image_height)));
+        }
+        let row_bytes = rim_width as u64 * 2;
+        if (rim_pitch as u64) < row_bytes {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("RIM pitch {} is too small for width {}", rim_pitch, rim_width)));
+        }
+
+        // Read a whole row (including any padding) at a time, rather than a
+        // pixel at a time, so that unbuffered readers aren't slow. The pixels
+        // are only allocated as the rows turn up, in case the data is short.
+        let mut data = Vec::new();
+        let mut row = vec![0u8; rim_pitch as usize];
+        for _line_num in 0..rim_height {
+            reader.read_exact(&mut row).map_err(|err| match err.kind() {
+                io::ErrorKind::UnexpectedEof => io::Error::new(io::ErrorKind::UnexpectedEof, "RIM image data is truncated"),
+                _ => err,
+            })?;
+            data.extend(row[..row_bytes as usize].chunks_exact(2).map(|px| u16::from_le_bytes([px[0], px[1]])));
+        }
+
+        RimImage::new(rim_ver, rim_width, rim_height, rim_fmt, data)
+    }
+
+    /// Creates an image from 8-bit-per-channel RGBA data, converting it to the
+    /// given 16-bit format. Alpha is discarded, as RIM images have none.
+    pub fn from_rgba_bytes(width : u16, height : u16, rgba : &[u8], format : RimFormat) -> io::Result<RimImage> {
+        let expected = width as usize * height as usize * 4;
+        if rgba.len() != expected {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("RGBA data for a {}×{} image should be {} bytes, not {}", width, height, expected, rgba.len())));
+        }
+
+        let convert = match format {
+            RimFormat::Rgb555 => convert_888_555,
+            RimFormat::Rgb565 => convert_888_565,
+        };
+        let data = rgba.chunks(4).map(convert).collect();
+        RimImage::new(1, width, height, format, data)
+    }
+
+    /// Reads a PNG image, converting it to the given 16-bit format.
+    pub fn from_png(reader : impl Read, format : RimFormat) -> io::Result<RimImage> {
+        let (width, height, rgba) = decode_png_rgba(reader)?;
+        if width > u16::MAX as u32 || height > u16::MAX as u32 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("{}×{} is too large for a RIM image", width, height)));
+        }
+        RimImage::from_rgba_bytes(width as u16, height as u16, &rgba, format)
+    }
+
+    /// Whether the PNG read from `reader` shows this image, as extracting it
+    /// would have: the same size, and the same pixels once converted to this
+    /// image's format. Transparent pixels match anything, as a colour key may
+    /// have been used.
+    pub fn same_as_png(&self, reader : impl Read) -> io::Result<bool> {
+        let (width, height, rgba) = decode_png_rgba(reader)?;
+        if (width, height) != (self.width as u32, self.height as u32) || self.data.len() * 4 != rgba.len() {
+            return Ok(false);
+        }
+        Ok(rgba.chunks(4).zip(&self.data).all(|(png_px, &px)| png_px[3] == 0 || self.format.encode_pixel(png_px) == px))
+    }
+
+    /// Writes the image as a RIM file, with no padding between rows.
+    pub fn to_stream(&self, writer : &mut impl Write) -> io::Result<()> {
+        self.to_stream_aligned(writer, 2)
+    }
+
+    /// Writes the image as a RIM file, padding each row so that the pitch is
+    /// a multiple of `pitch_alignment` bytes.
+    pub fn to_stream_aligned(&self, writer : &mut impl Write, pitch_alignment : u16) -> io::Result<()> {
+        let num_pixels = self.width as usize * self.height as usize;
+        if self.data.len() < num_pixels {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("RIM image is {}×{} but only has {} pixels", self.width, self.height, self.data.len())));
+        }
+        if pitch_alignment == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "RIM pitch alignment must not be zero"));
+        }
+
+        let row_bytes = self.width as u32 * 2;
+        let pitch = row_bytes.div_ceil(pitch_alignment as u32) * pitch_alignment as u32;
+        if pitch > u16::MAX as u32 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("RIM image width {} is too large for a pitch alignment of {}", self.width, pitch_alignment)));
+        }
+
+        // 'RIMF'
+        writer.write_u32::<LittleEndian>(0x464d4952)?;
+        writer.write_u32::<LittleEndian>(self.version)?;
+        writer.write_u16::<LittleEndian>(self.width)?;
+        writer.write_u16::<LittleEndian>(self.height)?;
+        writer.write_u16::<LittleEndian>(pitch as u16)?;
+        writer.write_u16::<LittleEndian>(self.format.to_raw())?;
+
+        let padding = vec![0u8; (pitch - row_bytes) as usize];
+        let mut row = Vec::with_capacity(pitch as usize);
+        if self.width > 0 {
+            for line in self.data[..num_pixels].chunks(self.width as usize) {
+                row.clear();
+                for px in line {
+                    row.extend_from_slice(&px.to_le_bytes());
+                }
+                row.extend_from_slice(&padding);
+                writer.write_all(&row)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the image as a RIM file. This is the same as `to_stream()`.
+    pub fn write(&self, writer : &mut impl Write) -> io::Result<()> {
+        self.to_stream(writer)
+    }
+
+    /// Converts the image to 8-bit-per-channel RGBA, as used by PNG.
+    ///
+    /// Fails (rather than panicking) if `data` has been left shorter than the
+    /// image's dimensions.
+    pub fn to_rgba_bytes(&self) -> io::Result<Vec<u8>> {
+        self.to_rgba_bytes_with(ColorKey::None)
+    }
+
+    /// Converts the image to 8-bit-per-channel RGBA, making pixels which
+    /// match `color_key` fully transparent.
+    pub fn to_rgba_bytes_with(&self, color_key : ColorKey) -> io::Result<Vec<u8>> {
+        let num_pixels = self.width as usize * self.height as usize;
+        if self.data.len() < num_pixels {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("RIM image is {}×{} but only has {} pixels", self.width, self.height, self.data.len())));
+        }
+
+        let convert = match self.format {
+            RimFormat::Rgb555 => convert_555_888,
+            RimFormat::Rgb565 => convert_565_888,
+        };
+        let mut rgba = Vec::with_capacity(num_pixels * 4);
+        for &px in &self.data[..num_pixels] {
+            if color_key.matches(px, self.format) {
+                rgba.extend_from_slice(&[0, 0, 0, 0]);
+            } else {
+                rgba.extend_from_slice(&convert(px));
+            }
+        }
+        Ok(rgba)
+    }
+
+    /// Encodes the image as an 8-bit RGBA PNG.
+    pub fn write_png<W : Write>(&self, writer : W) -> io::Result<()> {
+        self.write_png_with(writer, ColorKey::None)
+    }
+
+    /// Encodes the image as an 8-bit RGBA PNG, making pixels which match
+    /// `color_key` fully transparent.
+    pub fn write_png_with<W : Write>(&self, writer : W, color_key : ColorKey) -> io::Result<()> {
+        let rgba = self.to_rgba_bytes_with(color_key)?;
+        encode::write_png(writer, self.width as u32, self.height as u32, &rgba)
+    }
+
+    /// Writes the raw 16-bit pixels, little-endian, with no header and no
+    /// padding between rows.
+    pub fn write_raw16(&self, writer : &mut impl Write) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(self.data.len() * 2);
+        for px in &self.data {
+            bytes.extend_from_slice(&px.to_le_bytes());
+        }
+        writer.write_all(&bytes)
+    }
+
+    /// Describes the data written by `write_raw16()`, for a sidecar file.
+    pub fn raw16_metadata(&self) -> JsonValue {
+        JsonValue::object(vec![
+            ("version", self.version.into()),
+            ("width", (self.width as u32).into()),
+            ("height", (self.height as u32).into()),
+            ("format", self.format.name().into()),
+            ("pitch", (self.width as u32 * 2).into()),
+            ("byte_order", "little".into()),
+        ])
+    }
+
+    /// Encodes the image in the given format, making pixels which match
+    /// `color_key` fully transparent. (`RimCopy` writes just the PNG, and
+    /// `Raw16` has no transparency.)
+    pub fn write_as<W : Write>(&self, mut writer : W, format : ImageFormat, color_key : ColorKey) -> io::Result<()> {
+        let (width, height) = (self.width as u32, self.height as u32);
+        match format {
+            ImageFormat::Png | ImageFormat::RimCopy => self.write_png_with(writer, color_key),
+            ImageFormat::Tga => encode::write_tga(&mut writer, width, height, &self.to_rgba_bytes_with(color_key)?),
+            ImageFormat::Bmp => encode::write_bmp(&mut writer, width, height, &self.to_rgba_bytes_with(color_key)?),
+            ImageFormat::Qoi => encode::write_qoi(&mut writer, width, height, &self.to_rgba_bytes_with(color_key)?),
+            ImageFormat::Raw16 => self.write_raw16(&mut writer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn test_image(width : u16, height : u16, format : RimFormat) -> RimImage {
+        let data = (0..width as u32 * height as u32).map(|i| (i.wrapping_mul(2654435761) >> 17) as u16).collect();
+        RimImage::new(1, width, height, format, data).unwrap()
+    }
+
+    #[test]
+    fn encoded_images_decode_the_same() {
+        for &format in &[RimFormat::Rgb555, RimFormat::Rgb565] {
+            let image = test_image(5, 3, format);
+            // Odd widths need padding to a 4-byte pitch.
+            for &alignment in &[2, 4] {
+                let mut encoded = Vec::new();
+                image.to_stream_aligned(&mut encoded, alignment).unwrap();
+                let decoded = RimImage::from_stream(&mut &encoded[..]).unwrap();
+                assert_eq!((decoded.version, decoded.width, decoded.height, decoded.format), (1, 5, 3, format));
+                assert_eq!(decoded.data, image.data);
+            }
+        }
+    }
+
+    #[test]
+    fn rgba_round_trips_through_pixels() {
+        for &format in &[RimFormat::Rgb555, RimFormat::Rgb565] {
+            let image = test_image(4, 4, format);
+            let rgba = image.to_rgba_bytes().unwrap();
+            let encoded = RimImage::from_rgba_bytes(4, 4, &rgba, format).unwrap();
+            assert_eq!(encoded.data, image.data);
+        }
+    }
+
+    #[test]
+    fn extracted_pngs_match_their_images() {
+        let image = test_image(4, 3, RimFormat::Rgb555);
+        let mut png = Vec::new();
+        image.write_png_with(&mut png, ColorKey::Raw(image.data[0])).unwrap();
+        assert!(image.same_as_png(&png[..]).unwrap());
+
+        let mut data = image.data.clone();
+        data[5] ^= 0x421;
+        let edited = RimImage::new(1, 4, 3, RimFormat::Rgb555, data).unwrap();
+        assert!(!edited.same_as_png(&png[..]).unwrap());
+        let smaller = RimImage::new(1, 4, 2, RimFormat::Rgb555, image.data[..8].to_vec()).unwrap();
+        assert!(!smaller.same_as_png(&png[..]).unwrap());
+    }
+
+    #[test]
+    fn truncated_images_are_refused() {
+        let mut encoded = Vec::new();
+        test_image(4, 4, RimFormat::Rgb565).to_stream(&mut encoded).unwrap();
+        encoded.truncate(encoded.len() - 1);
+        assert!(RimImage::from_stream(&mut &encoded[..]).is_err());
+    }
+}
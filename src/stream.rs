@@ -0,0 +1,313 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::io;
+use std::io::Read;
+use job::JobEvent;
+use limits::Limits;
+use paths::OutputClaims;
+use ExtractOptions;
+use ExtractReport;
+use ZfsEntry;
+use ZfsHeader;
+
+/// Wraps a reader, keeping track of how far into the stream we are.
+struct CountingReader<R : Read>
+{
+    inner : R,
+    position : u64,
+}
+
+impl<R : Read> Read for CountingReader<R>
+{
+    fn read(&mut self, buf : &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl<R : Read> CountingReader<R>
+{
+    /// Moves forward to `offset` by reading and discarding bytes.
+    fn skip_to(&mut self, offset : u64, what : &str) -> io::Result<()> {
+        if offset < self.position {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("{} at offset {} is behind the current position ({}); this archive can't be read without seeking", what, offset, self.position)));
+        }
+        let to_skip = offset - self.position;
+        if io::copy(&mut self.by_ref().take(to_skip), &mut io::sink())? != to_skip {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("Archive ends before {} at offset {}", what, offset)));
+        }
+        Ok(())
+    }
+}
+
+/// Reads a ZFS archive from a stream which can't seek, such as a pipe.
+///
+/// Entries are returned in the order their data appears in the stream,
+/// which may differ from the order of the file table. This only works for
+/// archives where every file table comes before the data it describes,
+/// which is how the game's own archives (and `pack`'s) are laid out.
+///
+/// Data which more than one entry uses (as `pack` does for identical files)
+/// is kept in memory until the stream moves past it, as it can't be read
+/// twice.
+pub struct ZfsStream<R : Read>
+{
+    reader : CountingReader<R>,
+    header : ZfsHeader,
+    /// Entries we've read from a table, but not reached the data for yet,
+    /// sorted so that the one with the lowest offset is last.
+    pending : Vec<ZfsEntry>,
+    next_table_offset : Option<u64>,
+    entries_read : u32,
+    limits : Limits,
+    /// Data which more than one entry uses, and its offset.
+    shared : Option<(u64, Vec<u8>)>,
+}
+
+/// An entry from a `ZfsStream`, along with a reader over its data.
+pub struct ZfsStreamEntry<'a, R : Read + 'a>
+{
+    pub entry : ZfsEntry,
+    data : EntryData<'a, R>,
+}
+
+/// Where an entry's data is read from: straight from the stream, or from
+/// data kept because other entries use it too.
+enum EntryData<'a, R : Read + 'a>
+{
+    Stream(io::Take<&'a mut CountingReader<R>>),
+    Shared(io::Cursor<&'a [u8]>),
+}
+
+impl<'a, R : Read> Read for EntryData<'a, R>
+{
+    fn read(&mut self, buf : &mut [u8]) -> io::Result<usize> {
+        match self {
+            EntryData::Stream(data) => data.read(buf),
+            EntryData::Shared(data) => data.read(buf),
+        }
+    }
+}
+
+impl<'a, R : Read> Read for ZfsStreamEntry<'a, R>
+{
+    fn read(&mut self, buf : &mut [u8]) -> io::Result<usize> {
+        self.data.read(buf)
+    }
+}
+
+impl<R : Read> ZfsStream<R>
+{
+    /// Starts reading an archive, within the default `Limits`.
+    pub fn new(reader : R) -> io::Result<ZfsStream<R>> {
+        ZfsStream::with_limits(reader, Limits::default())
+    }
+
+    /// Starts reading an archive, failing once it breaks `limits`.
+    pub fn with_limits(reader : R, limits : Limits) -> io::Result<ZfsStream<R>> {
+        let mut reader = CountingReader { inner : reader, position : 0 };
+        let header = ZfsHeader::read(&mut reader)?;
+        if header.files_per_table == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "ZFS archive has empty file tables"));
+        }
+        // Working out an unknown layout means looking ahead at the data.
+        if !header.is_known_version() {
+            return Err(io::Error::new(io::ErrorKind::Unsupported,
+                format!("ZFS3 version {} archives can't be streamed: read it from a file instead", header.version)));
+        }
+        let next_table_offset = Some(header.filetable_offset as u64);
+        Ok(ZfsStream {
+            reader,
+            header,
+            pending : Vec::new(),
+            next_table_offset,
+            entries_read : 0,
+            limits,
+            shared : None,
+        })
+    }
+
+    fn read_table(&mut self, table_offset : u64) -> io::Result<()> {
+        self.reader.skip_to(table_offset, "file table")?;
+        let next_table_offset = self.reader.read_u32::<LittleEndian>()?;
+        self.next_table_offset = None;
+
+        let record_size = (self.header.max_filename_len + self.header.record_fields_size) as u64;
+        let mut table_ended = false;
+        for i in 0..self.header.files_per_table {
+            if self.entries_read == self.header.num_files {
+                break;
+            }
+            let table_entry_offset = table_offset + 4 + i as u64 * record_size;
+            match ZfsEntry::read_record(&mut self.reader, &self.header, table_entry_offset)? {
+                Some(_) if self.entries_read as usize >= self.limits.max_entries => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData,
+                        format!("Archive has more than {} entries, the most allowed", self.limits.max_entries)));
+                },
+                Some(entry) if entry.size as u64 > self.limits.max_entry_size => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData,
+                        format!("Entry \"{}\" is {} bytes, more than the {} allowed", entry.name, entry.size, self.limits.max_entry_size)));
+                },
+                Some(entry) => self.pending.push(entry),
+                None => {
+                    table_ended = true;
+                    break;
+                },
+            }
+            self.entries_read += 1;
+        }
+        self.pending.sort_by_key(|entry| std::cmp::Reverse((entry.offset, entry.table_entry_offset)));
+
+        // The last table has no next one, however many entries the header
+        // claims there are.
+        if !table_ended && self.entries_read < self.header.num_files && next_table_offset != 0 {
+            self.next_table_offset = Some(next_table_offset as u64);
+        }
+        Ok(())
+    }
+
+    /// Returns the next entry in the stream, or None once there are no more.
+    ///
+    /// Any of the previous entry's data which wasn't read is skipped.
+    pub fn next_entry(&mut self) -> io::Result<Option<ZfsStreamEntry<'_, R>>> {
+        loop {
+            let next_data_offset = self.pending.last().map(|entry| entry.offset as u64);
+            match (self.next_table_offset, next_data_offset) {
+                (Some(table_offset), Some(data_offset)) if table_offset <= data_offset => self.read_table(table_offset)?,
+                (Some(table_offset), None) => self.read_table(table_offset)?,
+                (_, Some(_)) => break,
+                (None, None) => return Ok(None),
+            }
+        }
+
+        let entry = self.pending.pop().unwrap();
+        let (offset, end) = (entry.offset as u64, entry.offset as u64 + entry.size as u64);
+        let in_shared = self.shared.as_ref().is_some_and(|(shared_offset, shared)| offset >= *shared_offset && end <= shared_offset + shared.len() as u64);
+        if !in_shared {
+            self.shared = None;
+            self.reader.skip_to(offset, &format!("data for \"{}\"", entry.name))?;
+
+            // Pending entries are sorted by offset, so any which use some of
+            // this entry's data (or each other's, following on) come next.
+            let mut shared_end = None;
+            for later in self.pending.iter().rev() {
+                if later.offset as u64 >= shared_end.unwrap_or(end) {
+                    break;
+                }
+                shared_end = Some(shared_end.unwrap_or(end).max(later.offset as u64 + later.size as u64));
+            }
+            let Some(shared_end) = shared_end else {
+                let data = EntryData::Stream(self.reader.by_ref().take(entry.size as u64));
+                return Ok(Some(ZfsStreamEntry { entry, data }));
+            };
+            let mut shared = Vec::new();
+            if self.reader.by_ref().take(shared_end - offset).read_to_end(&mut shared)? as u64 != shared_end - offset {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("Archive ends before the end of the data for \"{}\"", entry.name)));
+            }
+            self.shared = Some((offset, shared));
+        }
+
+        let (shared_offset, shared) = self.shared.as_ref().unwrap();
+        let start = (offset - shared_offset) as usize;
+        let data = EntryData::Shared(io::Cursor::new(&shared[start..start + entry.size]));
+        Ok(Some(ZfsStreamEntry { entry, data }))
+    }
+
+    /// Extracts every entry into `options.output_dir`, in stream order.
+    ///
+    /// The indices in the report are the order entries were found in the
+    /// stream, as the file table order isn't known up front.
+    pub fn extract_all_with(&mut self, options : &ExtractOptions) -> io::Result<ExtractReport> {
+        let start = std::time::Instant::now();
+        options.progress.send(JobEvent::Begin { steps : None, bytes : None });
+        let mut report = ExtractReport::default();
+        let mut claims = OutputClaims::default();
+        let mut index = 0;
+        while let Some(stream_entry) = self.next_entry()? {
+            let ZfsStreamEntry { entry, data } = stream_entry;
+            if options.selects(index, &entry.name) {
+                let collision = claims.claim(&entry, index, options);
+                let record = entry.extract_with(index, options, collision.as_ref(), || Ok(data));
+                record.log(&options.progress);
+                report.push(record);
+            }
+            index += 1;
+        }
+        report.stats.elapsed = start.elapsed();
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use builder::ZfsBuilder;
+    use std::time::UNIX_EPOCH;
+
+    /// Builds an archive of `names`, each entry holding its own name, with
+    /// two entries to a table.
+    fn archive(names : &[&str], dedup : bool) -> Vec<u8> {
+        let mut builder = ZfsBuilder::with_layout(1, 16, 2).unwrap();
+        builder.set_dedup(dedup);
+        for name in names {
+            let data = if dedup { b"shared".to_vec() } else { name.as_bytes().to_vec() };
+            builder.add(name, data, UNIX_EPOCH).unwrap();
+        }
+        let mut data = Vec::new();
+        builder.write(&mut data).unwrap();
+        data
+    }
+
+    fn read_all(data : &[u8]) -> io::Result<Vec<(String, Vec<u8>)>> {
+        let mut stream = ZfsStream::new(data)?;
+        let mut entries = Vec::new();
+        while let Some(mut stream_entry) = stream.next_entry()? {
+            let mut contents = Vec::new();
+            stream_entry.read_to_end(&mut contents)?;
+            entries.push((stream_entry.entry.name.clone(), contents));
+        }
+        Ok(entries)
+    }
+
+    #[test]
+    fn entries_are_read_in_order() {
+        let entries = read_all(&archive(&["a", "b", "c"], false)).unwrap();
+        assert_eq!(entries, [("a".to_string(), b"a".to_vec()), ("b".to_string(), b"b".to_vec()), ("c".to_string(), b"c".to_vec())]);
+    }
+
+    #[test]
+    fn shared_data_is_read_for_every_entry() {
+        let entries = read_all(&archive(&["a", "b", "c"], true)).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert!(entries.iter().all(|(_, contents)| contents == b"shared"));
+    }
+
+    #[test]
+    fn overstated_entry_counts_end_at_the_last_table() {
+        // Two full tables, but a header claiming there are five entries.
+        let mut data = archive(&["a", "b", "c", "d"], false);
+        data[16..20].copy_from_slice(&5u32.to_le_bytes());
+        let names : Vec<String> = read_all(&data).unwrap().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["a", "b", "c", "d"]);
+    }
+}
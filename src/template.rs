@@ -0,0 +1,130 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Templates for naming extracted files, so they can be laid out the way
+//! another engine expects.
+//!
+//! A template is text with fields in braces, like `{kind}/{stem}`. Any `/`
+//! or `\\` in the result makes a subdirectory. The fields are:
+//!
+//! - `{name}`: the entry's name, like `up01.rim`
+//! - `{stem}`: the name without its extension, like `up01`
+//! - `{ext}`: the name's extension, like `rim`
+//! - `{index}`: the entry's position in the archive
+//! - `{kind}`: what the entry is extracted as: `rim`, `text`, `sound`, or `other`
+//!
+//! Converted images have the image format's extension added to the result,
+//! just as they do to the entry's name, so `{kind}/{stem}` puts `up01.rim`
+//! in `rim/up01.png`.
+
+use kind::EntryKind;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field
+{
+    Name,
+    Stem,
+    Ext,
+    Index,
+    Kind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part
+{
+    Text(String),
+    Field(Field),
+}
+
+/// A parsed naming template.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameTemplate
+{
+    source : String,
+    parts : Vec<Part>,
+}
+
+impl NameTemplate
+{
+    /// Fills in the template for an entry.
+    pub fn expand(&self, name : &str, index : usize, kind : EntryKind) -> String {
+        let (stem, ext) = match name.rfind('.') {
+            Some(dot) => (&name[..dot], &name[dot + 1..]),
+            None => (name, ""),
+        };
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Field(Field::Name) => out.push_str(name),
+                Part::Field(Field::Stem) => out.push_str(stem),
+                Part::Field(Field::Ext) => out.push_str(ext),
+                Part::Field(Field::Index) => out.push_str(&index.to_string()),
+                Part::Field(Field::Kind) => out.push_str(kind.name()),
+            }
+        }
+        out
+    }
+}
+
+impl FromStr for NameTemplate
+{
+    type Err = io::Error;
+
+    fn from_str(source : &str) -> io::Result<NameTemplate> {
+        let invalid = |message : String| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid name template \"{}\": {}", source, message));
+        let mut parts = Vec::new();
+        let mut rest = source;
+        while let Some(open) = rest.find(['{', '}']) {
+            if rest[open..].starts_with('}') {
+                return Err(invalid("unmatched \"}\"".to_string()));
+            }
+            let close = match rest[open..].find('}') {
+                Some(close) => open + close,
+                None => return Err(invalid("unclosed \"{\"".to_string())),
+            };
+            if open > 0 {
+                parts.push(Part::Text(rest[..open].to_string()));
+            }
+            let field = match &rest[open + 1..close] {
+                "name" => Field::Name,
+                "stem" => Field::Stem,
+                "ext" => Field::Ext,
+                "index" => Field::Index,
+                "kind" => Field::Kind,
+                other => return Err(invalid(format!("unknown field \"{{{}}}\"", other))),
+            };
+            parts.push(Part::Field(field));
+            rest = &rest[close + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        Ok(NameTemplate { source : source.to_string(), parts })
+    }
+}
+
+impl fmt::Display for NameTemplate
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        f.pad(&self.source)
+    }
+}
@@ -0,0 +1,40 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Conversions between `SystemTime` and the timestamps stored in archives.
+//!
+//! ZFS records hold a modification time as an unsigned 32-bit count of
+//! seconds since 1970. These conversions never ask the system for the
+//! current time, so they work on targets without a clock.
+
+use std::time::{Duration, SystemTime};
+
+/// Turns a timestamp from an archive record into a `SystemTime`.
+pub fn from_zfs(secs : u32) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(secs as u64)
+}
+
+/// Turns a `SystemTime` into a timestamp for an archive record. Times
+/// before 1970 become 0, and times after 2106 are clamped to the latest
+/// one a record can hold.
+pub fn to_zfs(time : SystemTime) -> u32 {
+    match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(since) => since.as_secs().min(u32::MAX as u64) as u32,
+        Err(_) => 0,
+    }
+}
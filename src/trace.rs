@@ -0,0 +1,128 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Traces of the assets read through an `AssetVfs`, for working out (and
+//! benchmarking) the order a game loads its data in.
+//!
+//! A trace is saved as JSON:
+//!
+//! ```text
+//! {
+//!   "version": 1,
+//!   "accesses": [
+//!     { "time_us": 1520, "name": "up01.rim", "archive": "ctp/gl.zfs", "index": 3, "offset": 4096, "size": 52 },
+//!     { "time_us": 1811, "name": "intro.txt", "file": "ctp/intro.txt", "size": 310 },
+//!     { "time_us": 1900, "name": "missing.rim" }
+//!   ]
+//! }
+//! ```
+//!
+//! Times are microseconds since recording started. Assets which weren't
+//! found have no source.
+
+use json::JsonValue;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use vfs::AssetSource;
+
+/// The version of the trace format written by `AccessTrace::to_json()`.
+pub const TRACE_VERSION : u64 = 1;
+
+/// One read of an asset.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Access
+{
+    /// How long after recording started the read began.
+    pub time : Duration,
+    /// The name the asset was asked for by.
+    pub name : String,
+    /// Where it was read from, or None if it wasn't found.
+    pub source : Option<AssetSource>,
+    /// Where its data starts in the archive (or 0, for loose files).
+    pub offset : u64,
+    pub size : u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccessTrace
+{
+    pub accesses : Vec<Access>,
+}
+
+fn trace_error(message : String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid trace: {}", message))
+}
+
+impl AccessTrace
+{
+    pub fn to_json(&self) -> JsonValue {
+        let accesses = self.accesses.iter().map(|access| {
+            let mut fields = vec![
+                ("time_us", JsonValue::from(access.time.as_micros() as u64)),
+                ("name", access.name.as_str().into()),
+            ];
+            match access.source {
+                Some(AssetSource::Archive { ref path, index }) => {
+                    fields.push(("archive", path.to_string_lossy().as_ref().into()));
+                    fields.push(("index", index.into()));
+                    fields.push(("offset", access.offset.into()));
+                    fields.push(("size", access.size.into()));
+                },
+                Some(AssetSource::File(ref path)) => {
+                    fields.push(("file", path.to_string_lossy().as_ref().into()));
+                    fields.push(("size", access.size.into()));
+                },
+                None => {},
+            }
+            JsonValue::object(fields)
+        }).collect::<Vec<_>>();
+        JsonValue::object(vec![
+            ("version", TRACE_VERSION.into()),
+            ("accesses", JsonValue::Array(accesses)),
+        ])
+    }
+
+    /// Reads a trace written by `to_json()`.
+    pub fn parse(text : &str) -> io::Result<AccessTrace> {
+        let json = JsonValue::parse(text)?;
+        match json.get("version").and_then(JsonValue::as_u64) {
+            Some(TRACE_VERSION) => {},
+            Some(version) => return Err(trace_error(format!("version {} isn't supported (unciv reads version {})", version, TRACE_VERSION))),
+            None => return Err(trace_error("no version".to_string())),
+        }
+        let Some(items) = json.get("accesses").and_then(JsonValue::as_array) else {
+            return Err(trace_error("no accesses".to_string()));
+        };
+        let mut accesses = Vec::with_capacity(items.len());
+        for (i, item) in items.iter().enumerate() {
+            let number = |key : &str| item.get(key).and_then(JsonValue::as_u64).ok_or_else(|| trace_error(format!("access {} has no {}", i, key)));
+            let name = item.get("name").and_then(JsonValue::as_str).ok_or_else(|| trace_error(format!("access {} has no name", i)))?;
+            let (source, offset, size) = if let Some(archive) = item.get("archive").and_then(JsonValue::as_str) {
+                let source = AssetSource::Archive { path : PathBuf::from(archive), index : number("index")? as usize };
+                (Some(source), number("offset")?, number("size")?)
+            } else if let Some(file) = item.get("file").and_then(JsonValue::as_str) {
+                (Some(AssetSource::File(PathBuf::from(file))), 0, number("size")?)
+            } else {
+                (None, 0, 0)
+            };
+            accesses.push(Access { time : Duration::from_micros(number("time_us")?), name : name.to_string(), source, offset, size });
+        }
+        Ok(AccessTrace { accesses })
+    }
+}
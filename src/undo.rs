@@ -0,0 +1,459 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Undo journals for commands which edit archives in place.
+//!
+//! Edits go through a `JournaledWriter`, which saves the bytes each write
+//! will overwrite to a journal next to the archive (`gl.zfs.undo` for
+//! `gl.zfs`) before it's made, so that even an edit which fails or is cut
+//! short partway through can be put back. `undo_last()` puts back the last
+//! edit in the journal.
+//!
+//! The journal is a series of records, each starting with a four-byte tag.
+//! An edit is a `BEGN` record giving the archive's length beforehand, a
+//! `SAVE` record (offset, length, and bytes) for each range overwritten, and
+//! a `DONE` record giving the archive's length and CRC-32 afterwards. Edits
+//! are only undone while the archive still has that length and CRC-32, so
+//! that they're never written over an archive which has since been rebuilt.
+//! An edit with no `DONE` record didn't finish, and has to be undone before
+//! the archive can be edited again.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use Crc32Reader;
+
+const BEGIN_TAG : &[u8; 4] = b"BEGN";
+const SAVE_TAG : &[u8; 4] = b"SAVE";
+const DONE_TAG : &[u8; 4] = b"DONE";
+
+/// Where the undo journal for an archive is kept.
+pub fn journal_path(archive_path : &Path) -> PathBuf {
+    let mut path = archive_path.as_os_str().to_owned();
+    path.push(".undo");
+    PathBuf::from(path)
+}
+
+/// Returns the length and CRC-32 of everything in `archive`.
+fn archive_state(archive : &mut (impl Read + Seek)) -> io::Result<(u64, u32)> {
+    archive.seek(SeekFrom::Start(0))?;
+    let mut reader = Crc32Reader::new(archive);
+    let len = io::copy(&mut reader, &mut io::sink())?;
+    Ok((len, reader.crc32()))
+}
+
+/// One edit recorded in a journal.
+struct Session<'a>
+{
+    /// Where the edit starts in the journal.
+    start : u64,
+    original_len : u64,
+    saved : Vec<(u64, &'a [u8])>,
+    /// The archive's length and CRC-32 once the edit was done, or None if it
+    /// didn't finish.
+    edited : Option<(u64, u32)>,
+}
+
+fn corrupt() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "The undo journal is corrupt")
+}
+
+/// Finds the last edit in a journal's contents, if it has any.
+fn last_session(journal : &[u8]) -> io::Result<Option<Session<'_>>> {
+    let mut session : Option<Session> = None;
+    let mut reader = journal;
+    while !reader.is_empty() {
+        let start = (journal.len() - reader.len()) as u64;
+        let mut tag = [0; 4];
+        reader.read_exact(&mut tag).map_err(|_| corrupt())?;
+        match &tag {
+            BEGIN_TAG => {
+                let original_len = reader.read_u64::<LittleEndian>().map_err(|_| corrupt())?;
+                session = Some(Session { start, original_len, saved : Vec::new(), edited : None });
+            },
+            SAVE_TAG => {
+                let offset = reader.read_u64::<LittleEndian>().map_err(|_| corrupt())?;
+                let len = reader.read_u32::<LittleEndian>().map_err(|_| corrupt())? as usize;
+                let Some(session) = session.as_mut().filter(|session| session.edited.is_none()) else { return Err(corrupt()) };
+                if len > reader.len() {
+                    return Err(corrupt());
+                }
+                session.saved.push((offset, &reader[..len]));
+                reader = &reader[len..];
+            },
+            DONE_TAG => {
+                let len = reader.read_u64::<LittleEndian>().map_err(|_| corrupt())?;
+                let crc32 = reader.read_u32::<LittleEndian>().map_err(|_| corrupt())?;
+                let Some(session) = session.as_mut().filter(|session| session.edited.is_none()) else { return Err(corrupt()) };
+                session.edited = Some((len, crc32));
+            },
+            _ => return Err(corrupt()),
+        }
+    }
+    Ok(session)
+}
+
+/// Wraps an archive being edited, saving what each write overwrites to the
+/// journal before it's written.
+pub struct JournaledWriter<F : Read + Write + Seek>
+{
+    inner : F,
+    journal : File,
+    journal_path : PathBuf,
+    /// Where this edit starts in the journal.
+    session_start : u64,
+    /// Whether anything has been written to the journal for this edit yet.
+    /// Nothing is, until the archive is first written to.
+    begun : bool,
+    original_len : u64,
+    pos : u64,
+    saved : Vec<(u64, Vec<u8>)>,
+}
+
+impl<F : Read + Write + Seek> JournaledWriter<F>
+{
+    /// Starts an edit of `inner`, recording it in the journal at
+    /// `journal_path`. Fails if the last edit in the journal didn't finish,
+    /// as that has to be undone first.
+    pub fn new(mut inner : F, journal_path : &Path) -> io::Result<JournaledWriter<F>> {
+        let mut journal = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(journal_path)?;
+        let mut contents = Vec::new();
+        journal.read_to_end(&mut contents)?;
+        if let Some(Session { edited : None, .. }) = last_session(&contents)? {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                "The last edit to this archive didn't finish: undo it before making another"));
+        }
+        let session_start = contents.len() as u64;
+
+        let original_len = inner.seek(SeekFrom::End(0))?;
+        inner.seek(SeekFrom::Start(0))?;
+        Ok(JournaledWriter {
+            inner,
+            journal,
+            journal_path : journal_path.to_path_buf(),
+            session_start,
+            begun : false,
+            original_len,
+            pos : 0,
+            saved : Vec::new(),
+        })
+    }
+
+    /// Records the start of the edit in the journal, before the first write.
+    fn begin(&mut self) -> io::Result<()> {
+        if !self.begun {
+            self.journal.write_all(BEGIN_TAG)?;
+            self.journal.write_u64::<LittleEndian>(self.original_len)?;
+            self.journal.sync_data()?;
+            self.begun = true;
+        }
+        Ok(())
+    }
+
+    /// Finishes the edit, recording the archive's new length and CRC-32 in
+    /// the journal, and returns the wrapped archive. Nothing is left in the
+    /// journal if nothing was changed.
+    pub fn commit(mut self) -> io::Result<F> {
+        self.inner.flush()?;
+        if !self.begun {
+            self.remove_session()?;
+            return Ok(self.inner);
+        }
+        let (len, crc32) = archive_state(&mut self.inner)?;
+        if self.saved.is_empty() && len == self.original_len {
+            self.remove_session()?;
+            return Ok(self.inner);
+        }
+        self.journal.write_all(DONE_TAG)?;
+        self.journal.write_u64::<LittleEndian>(len)?;
+        self.journal.write_u32::<LittleEndian>(crc32)?;
+        self.journal.sync_all()?;
+        Ok(self.inner)
+    }
+
+    /// Takes this edit back out of the journal, deleting the journal if
+    /// that leaves it empty (as it is if `new()` only just created it).
+    fn remove_session(&mut self) -> io::Result<()> {
+        if self.session_start == 0 {
+            std::fs::remove_file(&self.journal_path)
+        } else if self.begun {
+            self.journal.set_len(self.session_start)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl JournaledWriter<File>
+{
+    /// Puts back everything overwritten so far, and removes the edit from
+    /// the journal, as if it had never been started. This is for edits
+    /// which fail partway through.
+    pub fn roll_back(mut self) -> io::Result<()> {
+        for (offset, bytes) in self.saved.iter().rev() {
+            self.inner.seek(SeekFrom::Start(*offset))?;
+            self.inner.write_all(bytes)?;
+        }
+        self.inner.set_len(self.original_len)?;
+        self.inner.sync_all()?;
+        self.remove_session()
+    }
+}
+
+impl<F : Read + Write + Seek> Read for JournaledWriter<F>
+{
+    fn read(&mut self, buf : &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl<F : Read + Write + Seek> Write for JournaledWriter<F>
+{
+    fn write(&mut self, buf : &[u8]) -> io::Result<usize> {
+        self.begin()?;
+        // Only bytes which were in the archive to start with need saving:
+        // anything after that is removed by truncating it.
+        if self.pos < self.original_len && !buf.is_empty() {
+            let len = (self.original_len - self.pos).min(buf.len() as u64) as usize;
+            let mut old = vec![0; len];
+            self.inner.read_exact(&mut old)?;
+            self.inner.seek(SeekFrom::Start(self.pos))?;
+            self.journal.write_all(SAVE_TAG)?;
+            self.journal.write_u64::<LittleEndian>(self.pos)?;
+            self.journal.write_u32::<LittleEndian>(len as u32)?;
+            self.journal.write_all(&old)?;
+            self.journal.sync_data()?;
+            self.saved.push((self.pos, old));
+        }
+        let written = self.inner.write(buf)?;
+        self.pos += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<F : Read + Write + Seek> Seek for JournaledWriter<F>
+{
+    fn seek(&mut self, pos : SeekFrom) -> io::Result<u64> {
+        self.pos = self.inner.seek(pos)?;
+        Ok(self.pos)
+    }
+}
+
+/// Undoes the last edit recorded in the journal, and removes it from the
+/// journal (deleting the journal once it's empty). Returns false if there
+/// was nothing to undo.
+///
+/// Fails, leaving the archive alone, if it's been changed some other way
+/// since the edit (e.g. rebuilt with `pack`). An edit which didn't finish
+/// can't be checked like that, so it's undone whatever the archive holds.
+pub fn undo_last(archive : &mut File, journal_path : &Path) -> io::Result<bool> {
+    let mut journal = match OpenOptions::new().read(true).write(true).open(journal_path) {
+        Ok(journal) => journal,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err),
+    };
+    let mut contents = Vec::new();
+    journal.read_to_end(&mut contents)?;
+    let Some(session) = last_session(&contents)? else { return Ok(false) };
+
+    if let Some(edited) = session.edited {
+        if archive_state(archive)? != edited {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                "The archive has changed since the last edit in its undo journal, so it can't be undone"));
+        }
+    }
+
+    // Put the bytes back in the reverse order they were saved, so that the
+    // oldest version of anything written twice wins.
+    for &(offset, bytes) in session.saved.iter().rev() {
+        archive.seek(SeekFrom::Start(offset))?;
+        archive.write_all(bytes)?;
+    }
+    archive.set_len(session.original_len)?;
+    archive.sync_all()?;
+
+    if session.start == 0 {
+        drop(journal);
+        std::fs::remove_file(journal_path)?;
+    } else {
+        journal.set_len(session.start)?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// An archive in the temporary folder, deleted along with its journal
+    /// when dropped.
+    struct TempArchive
+    {
+        path : PathBuf,
+    }
+
+    impl TempArchive
+    {
+        fn new(name : &str, contents : &[u8]) -> TempArchive {
+            let path = std::env::temp_dir().join(format!("unciv-undo-{}-{}.zfs", std::process::id(), name));
+            std::fs::write(&path, contents).unwrap();
+            TempArchive { path }
+        }
+
+        fn open(&self) -> File {
+            OpenOptions::new().read(true).write(true).open(&self.path).unwrap()
+        }
+
+        fn journal_path(&self) -> PathBuf {
+            journal_path(&self.path)
+        }
+
+        fn contents(&self) -> Vec<u8> {
+            std::fs::read(&self.path).unwrap()
+        }
+
+        /// Overwrites `at` with `bytes`, and appends `appended`, through a
+        /// journal, without committing the edit.
+        fn write(&self, at : u64, bytes : &[u8], appended : &[u8]) -> JournaledWriter<File> {
+            let mut writer = JournaledWriter::new(self.open(), &self.journal_path()).unwrap();
+            writer.seek(SeekFrom::Start(at)).unwrap();
+            writer.write_all(bytes).unwrap();
+            writer.seek(SeekFrom::End(0)).unwrap();
+            writer.write_all(appended).unwrap();
+            writer
+        }
+
+        /// Makes an edit, as `write()`, and commits it.
+        fn edit(&self, at : u64, bytes : &[u8], appended : &[u8]) {
+            self.write(at, bytes, appended).commit().unwrap();
+        }
+    }
+
+    impl Drop for TempArchive
+    {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+            let _ = std::fs::remove_file(self.journal_path());
+        }
+    }
+
+    const ORIGINAL : &[u8] = b"ZFS3 hello world, this is an archive";
+
+    #[test]
+    fn undo_restores_the_original() {
+        let archive = TempArchive::new("restore", ORIGINAL);
+        archive.edit(6, b"HELLO", b" with more data on the end");
+        assert_ne!(archive.contents(), ORIGINAL);
+
+        assert!(undo_last(&mut archive.open(), &archive.journal_path()).unwrap());
+        assert_eq!(archive.contents(), ORIGINAL);
+        assert!(!archive.journal_path().exists());
+        assert!(!undo_last(&mut archive.open(), &archive.journal_path()).unwrap());
+    }
+
+    #[test]
+    fn edits_are_undone_newest_first() {
+        let archive = TempArchive::new("order", ORIGINAL);
+        archive.edit(6, b"HELLO", b"");
+        let after_first = archive.contents();
+        // Overwrites some of the first edit, and grows the archive.
+        archive.edit(8, b"__", b"!!");
+
+        assert!(undo_last(&mut archive.open(), &archive.journal_path()).unwrap());
+        assert_eq!(archive.contents(), after_first);
+        assert!(undo_last(&mut archive.open(), &archive.journal_path()).unwrap());
+        assert_eq!(archive.contents(), ORIGINAL);
+        assert!(!archive.journal_path().exists());
+    }
+
+    #[test]
+    fn edits_which_change_nothing_leave_no_journal() {
+        let archive = TempArchive::new("unchanged", ORIGINAL);
+        JournaledWriter::new(archive.open(), &archive.journal_path()).unwrap().commit().unwrap();
+        assert!(!archive.journal_path().exists());
+    }
+
+    #[test]
+    fn undo_refuses_changed_archives() {
+        let archive = TempArchive::new("changed", ORIGINAL);
+        archive.edit(6, b"HELLO", b"");
+
+        // The same length, but different contents.
+        let mut changed = archive.contents();
+        changed[0] = b'X';
+        std::fs::write(&archive.path, &changed).unwrap();
+        assert!(undo_last(&mut archive.open(), &archive.journal_path()).is_err());
+        assert_eq!(archive.contents(), changed);
+
+        // Longer, but with the edited contents at the start.
+        changed[0] = b'Z';
+        changed.extend_from_slice(b"more");
+        std::fs::write(&archive.path, &changed).unwrap();
+        assert!(undo_last(&mut archive.open(), &archive.journal_path()).is_err());
+        assert_eq!(archive.contents(), changed);
+    }
+
+    #[test]
+    fn roll_back_puts_back_a_partial_edit() {
+        let archive = TempArchive::new("rollback", ORIGINAL);
+        archive.write(6, b"HELLO", b" partly written").roll_back().unwrap();
+        assert_eq!(archive.contents(), ORIGINAL);
+        assert!(!archive.journal_path().exists());
+
+        // Rolling back a second edit leaves the first in the journal.
+        archive.edit(0, b"zfs3", b"");
+        let after_first = archive.contents();
+        archive.write(6, b"HELLO", b"").roll_back().unwrap();
+        assert_eq!(archive.contents(), after_first);
+        assert!(undo_last(&mut archive.open(), &archive.journal_path()).unwrap());
+        assert_eq!(archive.contents(), ORIGINAL);
+    }
+
+    #[test]
+    fn unfinished_edits_must_be_undone_first() {
+        let archive = TempArchive::new("unfinished", ORIGINAL);
+        // Dropped without committing, as if the program had been killed.
+        drop(archive.write(6, b"HELLO", b" cut short"));
+        assert!(JournaledWriter::new(archive.open(), &archive.journal_path()).is_err());
+
+        assert!(undo_last(&mut archive.open(), &archive.journal_path()).unwrap());
+        assert_eq!(archive.contents(), ORIGINAL);
+        assert!(JournaledWriter::new(archive.open(), &archive.journal_path()).is_ok());
+    }
+
+    #[test]
+    fn corrupt_journals_are_refused() {
+        let archive = TempArchive::new("corrupt", ORIGINAL);
+        archive.edit(6, b"HELLO", b"");
+        let mut journal = std::fs::read(archive.journal_path()).unwrap();
+        journal.truncate(journal.len() - 20);
+        std::fs::write(archive.journal_path(), &journal).unwrap();
+        let edited = archive.contents();
+        assert!(undo_last(&mut archive.open(), &archive.journal_path()).is_err());
+        assert_eq!(archive.contents(), edited);
+    }
+}
@@ -0,0 +1,366 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Checking an archive's structure for corruption, e.g. from a broken
+//! download or a buggy third-party packer.
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::io::{Read, Seek};
+use ZfsEntry;
+use ZfsFile;
+use ZfsHeader;
+
+/// Something wrong with an archive, found by `ZfsFile::validate()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Problem
+{
+    /// A file table starts at `offset`, but doesn't fit in the archive.
+    TablePastEnd { offset : u64 },
+    /// The chain of file tables leads back to the table at `offset`.
+    TableLoop { offset : u64 },
+    /// The file tables ended before all the entries the header promised.
+    MissingEntries { expected : u32, found : usize },
+    /// An entry's data runs past the end of the archive.
+    EntryPastEnd { index : usize, name : String, end : u64, archive_size : u64 },
+    /// Two entries' data overlap.
+    Overlap { index : usize, name : String, other_index : usize, other_name : String },
+    /// An entry has the same name as an earlier one, which hides it.
+    DuplicateName { index : usize, name : String, first_index : usize },
+    /// A RIM entry's header describes an image which doesn't fit the
+    /// entry, or which leaves data over.
+    RimSizeMismatch { index : usize, name : String, expected : u64, size : u64 },
+    /// A RIM entry's header can't be read at all.
+    BadRimHeader { index : usize, name : String, reason : String },
+}
+
+impl Problem
+{
+    /// The index of the entry with the problem, if it's with an entry rather
+    /// than the archive as a whole.
+    pub fn index(&self) -> Option<usize> {
+        match *self {
+            Problem::TablePastEnd { .. } | Problem::TableLoop { .. } | Problem::MissingEntries { .. } => None,
+            Problem::EntryPastEnd { index, .. } | Problem::Overlap { index, .. } | Problem::DuplicateName { index, .. }
+            | Problem::RimSizeMismatch { index, .. } | Problem::BadRimHeader { index, .. } => Some(index),
+        }
+    }
+}
+
+impl fmt::Display for Problem
+{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::TablePastEnd { offset } => write!(f, "File table at {} runs past the end of the archive", offset),
+            Problem::TableLoop { offset } => write!(f, "File table chain loops back to the table at {}", offset),
+            Problem::MissingEntries { expected, found } => write!(f, "Header says there are {} entries, but the file tables only hold {}", expected, found),
+            Problem::EntryPastEnd { index, name, end, archive_size } =>
+                write!(f, "Entry {} (\"{}\") ends at {}, past the end of the archive ({})", index, name, end, archive_size),
+            Problem::Overlap { index, name, other_index, other_name } =>
+                write!(f, "Entry {} (\"{}\") overlaps entry {} (\"{}\")", index, name, other_index, other_name),
+            Problem::DuplicateName { index, name, first_index } =>
+                write!(f, "Entry {} has the same name (\"{}\") as entry {}", index, name, first_index),
+            Problem::RimSizeMismatch { index, name, expected, size } =>
+                write!(f, "Entry {} (\"{}\") is {} bytes, but its RIM header describes {} bytes", index, name, size, expected),
+            Problem::BadRimHeader { index, name, reason } => write!(f, "Entry {} (\"{}\"): {}", index, name, reason),
+        }
+    }
+}
+
+/// Walks the chain of file tables, checking each is in bounds and visited
+/// once, and returns the entries in the tables it could read.
+///
+/// Unlike `ZfsFile::from_stream()`, this carries on (with the entries found
+/// so far) when the tables are broken, so that the problem can be reported.
+fn check_tables(reader : &mut (impl Read + Seek), archive_size : u64, problems : &mut Vec<Problem>) -> io::Result<Vec<ZfsEntry>> {
+    reader.seek(io::SeekFrom::Start(0))?;
+    let mut header = ZfsHeader::read(reader)?;
+    header.detect_layout(reader)?;
+    let record_size = (header.max_filename_len + header.record_fields_size) as u64;
+    let table_size = 4 + record_size * header.files_per_table as u64;
+
+    let mut visited = HashSet::new();
+    let mut offset = header.filetable_offset as u64;
+    let mut entries = Vec::new();
+    while entries.len() < header.num_files as usize {
+        if offset + table_size > archive_size {
+            problems.push(Problem::TablePastEnd { offset });
+            break;
+        }
+        if !visited.insert(offset) {
+            problems.push(Problem::TableLoop { offset });
+            break;
+        }
+        reader.seek(io::SeekFrom::Start(offset))?;
+        let next_offset = reader.read_u32::<LittleEndian>()? as u64;
+        let mut records = 0;
+        for i in 0..header.files_per_table as u64 {
+            let table_entry_offset = offset + 4 + i * record_size;
+            reader.seek(io::SeekFrom::Start(table_entry_offset))?;
+            match ZfsEntry::read_record(reader, &header, table_entry_offset)? {
+                Some(entry) if entries.len() < header.num_files as usize => entries.push(entry),
+                _ => break,
+            }
+            records += 1;
+        }
+        if records < header.files_per_table as usize || next_offset == 0 {
+            break;
+        }
+        offset = next_offset;
+    }
+    if entries.len() < header.num_files as usize {
+        problems.push(Problem::MissingEntries { expected : header.num_files, found : entries.len() });
+    }
+    Ok(entries)
+}
+
+/// Checks an archive's structure, as `ZfsFile::validate()` does, without
+/// needing its file tables to be readable first. Entries in any tables
+/// before a broken one are still checked.
+///
+/// Returns how many entries were found, and the problems.
+pub fn validate_archive(reader : &mut (impl Read + Seek)) -> io::Result<(usize, Vec<Problem>)> {
+    let mut problems = Vec::new();
+    let archive_size = reader.seek(io::SeekFrom::End(0))?;
+    let files = check_tables(reader, archive_size, &mut problems)?;
+    check_entries(&files, reader, archive_size, &mut problems)?;
+    problems.sort_by_key(|problem| problem.index());
+    Ok((files.len(), problems))
+}
+
+/// Checks the entries found in an archive's file tables.
+fn check_entries(files : &[ZfsEntry], reader : &mut (impl Read + Seek), archive_size : u64, problems : &mut Vec<Problem>) -> io::Result<()> {
+    let mut first_with_name = HashMap::new();
+    for (index, entry) in files.iter().enumerate() {
+        if let Some(&first_index) = first_with_name.get(&entry.name) {
+            problems.push(Problem::DuplicateName { index, name : entry.name.clone(), first_index });
+        } else {
+            first_with_name.insert(entry.name.clone(), index);
+        }
+    }
+
+    let mut in_bounds = Vec::new();
+    for (index, entry) in files.iter().enumerate() {
+        let end = entry.offset as u64 + entry.size as u64;
+        if end > archive_size {
+            problems.push(Problem::EntryPastEnd { index, name : entry.name.clone(), end, archive_size });
+        } else {
+            in_bounds.push(index);
+        }
+    }
+
+    // Compare each entry with the one before it in the file, keeping
+    // track of whichever reaches furthest.
+    let mut by_offset : Vec<usize> = in_bounds.iter().cloned().filter(|&i| files[i].size > 0).collect();
+    by_offset.sort_by_key(|&i| (files[i].offset, i));
+    let mut furthest : Option<usize> = None;
+    for &index in &by_offset {
+        let entry = &files[index];
+        if let Some(other_index) = furthest {
+            let other = &files[other_index];
+            // Entries with exactly the same data can share it, as packers
+            // which deduplicate entries do.
+            let shared = entry.offset == other.offset && entry.size == other.size;
+            if !shared && entry.offset < other.offset + other.size {
+                problems.push(Problem::Overlap { index, name : entry.name.clone(), other_index, other_name : other.name.clone() });
+            }
+        }
+        if furthest.map_or(true, |other| entry.offset + entry.size > files[other].offset + files[other].size) {
+            furthest = Some(index);
+        }
+    }
+
+    for &index in &in_bounds {
+        let entry = &files[index];
+        let mut header = Vec::with_capacity(16);
+        entry.reader(reader)?.take(16).read_to_end(&mut header)?;
+        if !header.starts_with(b"RIMF") {
+            continue;
+        }
+        if header.len() < 16 {
+            problems.push(Problem::BadRimHeader { index, name : entry.name.clone(), reason : "RIM header is truncated".to_string() });
+            continue;
+        }
+        let field = |offset : usize| u16::from_le_bytes([header[offset], header[offset + 1]]) as u64;
+        let (width, height, pitch) = (field(8), field(10), field(12));
+        if pitch < width * 2 {
+            problems.push(Problem::BadRimHeader { index, name : entry.name.clone(), reason : format!("RIM pitch {} is too small for width {}", pitch, width) });
+            continue;
+        }
+        let expected = 16 + pitch * height;
+        if expected != entry.size as u64 {
+            problems.push(Problem::RimSizeMismatch { index, name : entry.name.clone(), expected, size : entry.size as u64 });
+        }
+    }
+
+    Ok(())
+}
+
+impl ZfsFile
+{
+    /// Checks the archive's structure, returning everything wrong with it.
+    ///
+    /// An empty list means no problems were found. Only the headers of RIM
+    /// entries are read, not their data.
+    pub fn validate(&self, reader : &mut (impl Read + Seek)) -> io::Result<Vec<Problem>> {
+        let mut problems = Vec::new();
+        let archive_size = reader.seek(io::SeekFrom::End(0))?;
+        check_tables(reader, archive_size, &mut problems)?;
+
+        check_entries(&self.files, reader, archive_size, &mut problems)?;
+        problems.sort_by_key(|problem| problem.index());
+        Ok(problems)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use builder::ZfsBuilder;
+    use std::time::UNIX_EPOCH;
+
+    /// Builds an archive holding `entries`, one to a table, so that the
+    /// tables can be rearranged.
+    fn archive(entries : &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = ZfsBuilder::with_layout(1, 16, 1).unwrap();
+        builder.set_dedup(false);
+        for &(name, data) in entries {
+            builder.add(name, data.to_vec(), UNIX_EPOCH).unwrap();
+        }
+        let mut data = Vec::new();
+        builder.write(&mut data).unwrap();
+        data
+    }
+
+    fn u32_at(data : &[u8], at : usize) -> u32 {
+        u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+    }
+
+    fn set_u32(data : &mut [u8], at : usize, value : u32) {
+        data[at..at + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Where each file table is, following the chain from the header.
+    fn tables(data : &[u8]) -> Vec<usize> {
+        let mut tables = Vec::new();
+        let mut offset = u32_at(data, 24) as usize;
+        while offset != 0 {
+            tables.push(offset);
+            offset = u32_at(data, offset) as usize;
+        }
+        tables
+    }
+
+    /// Where the offset and size fields of the record in the table at
+    /// `table` are.
+    fn record_fields(table : usize) -> (usize, usize) {
+        (table + 4 + 16, table + 4 + 16 + 8)
+    }
+
+    fn problems(data : Vec<u8>) -> Vec<Problem> {
+        validate_archive(&mut io::Cursor::new(data)).unwrap().1
+    }
+
+    const THREE : &[(&str, &[u8])] = &[("a.txt", b"first"), ("b.txt", b"second"), ("c.txt", b"third")];
+
+    #[test]
+    fn good_archives_have_no_problems() {
+        assert_eq!(problems(archive(THREE)), []);
+    }
+
+    #[test]
+    fn table_loops_are_found() {
+        let mut data = archive(THREE);
+        let tables = tables(&data);
+        set_u32(&mut data, tables[2], tables[0] as u32);
+        set_u32(&mut data, 16, 4);
+        assert_eq!(problems(data), [Problem::TableLoop { offset : tables[0] as u64 }, Problem::MissingEntries { expected : 4, found : 3 }]);
+    }
+
+    #[test]
+    fn tables_past_the_end_are_found() {
+        let mut data = archive(THREE);
+        let tables = tables(&data);
+        let past_end = data.len() as u32 - 2;
+        set_u32(&mut data, tables[1], past_end);
+        assert_eq!(problems(data), [Problem::TablePastEnd { offset : past_end as u64 }, Problem::MissingEntries { expected : 3, found : 2 }]);
+    }
+
+    #[test]
+    fn missing_entries_are_found() {
+        let mut data = archive(THREE);
+        set_u32(&mut data, 16, 5);
+        assert_eq!(problems(data), [Problem::MissingEntries { expected : 5, found : 3 }]);
+    }
+
+    #[test]
+    fn overlaps_and_entries_past_the_end_are_found() {
+        let mut data = archive(THREE);
+        let tables = tables(&data);
+        let (a_offset, _) = record_fields(tables[0]);
+        let (b_offset, _) = record_fields(tables[1]);
+        let (_, c_size) = record_fields(tables[2]);
+        let overlapping = u32_at(&data, a_offset) + 1;
+        set_u32(&mut data, b_offset, overlapping);
+        set_u32(&mut data, c_size, 1000);
+        let archive_size = data.len() as u64;
+        let c_end = u32_at(&data, record_fields(tables[2]).0) as u64 + 1000;
+        assert_eq!(problems(data), [
+            Problem::Overlap { index : 1, name : "b.txt".to_string(), other_index : 0, other_name : "a.txt".to_string() },
+            Problem::EntryPastEnd { index : 2, name : "c.txt".to_string(), end : c_end, archive_size },
+        ]);
+    }
+
+    #[test]
+    fn shared_data_is_not_an_overlap() {
+        let mut data = archive(THREE);
+        let tables = tables(&data);
+        let (a_offset, a_size) = record_fields(tables[0]);
+        let (b_offset, b_size) = record_fields(tables[1]);
+        let (shared_offset, shared_size) = (u32_at(&data, a_offset), u32_at(&data, a_size));
+        set_u32(&mut data, b_offset, shared_offset);
+        set_u32(&mut data, b_size, shared_size);
+        assert_eq!(problems(data), []);
+    }
+
+    #[test]
+    fn duplicate_names_are_found() {
+        let data = archive(&[("a.txt", b"first"), ("b.txt", b"second"), ("a.txt", b"third")]);
+        assert_eq!(problems(data), [Problem::DuplicateName { index : 2, name : "a.txt".to_string(), first_index : 0 }]);
+    }
+
+    #[test]
+    fn rim_sizes_are_checked() {
+        // A 3×2 image with a 6-byte pitch is 28 bytes.
+        let mut rim = b"RIMF\x01\0\0\0\x03\0\x02\0\x06\0\x01\0".to_vec();
+        rim.extend_from_slice(&[0; 12]);
+        let mut long_rim = rim.clone();
+        long_rim.push(0);
+        let mut narrow_rim = rim.clone();
+        narrow_rim[12] = 4;
+        let data = archive(&[("good.rim", &rim), ("long.rim", &long_rim), ("narrow.rim", &narrow_rim), ("short.rim", b"RIMF\x01")]);
+        assert_eq!(problems(data), [
+            Problem::RimSizeMismatch { index : 1, name : "long.rim".to_string(), expected : 28, size : 29 },
+            Problem::BadRimHeader { index : 2, name : "narrow.rim".to_string(), reason : "RIM pitch 4 is too small for width 3".to_string() },
+            Problem::BadRimHeader { index : 3, name : "short.rim".to_string(), reason : "RIM header is truncated".to_string() },
+        ]);
+    }
+}
@@ -0,0 +1,196 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! Looking up assets the way the game does: across several archives and
+//! loose directories, with later ones (like patches) taking priority.
+
+use archive::ZfsArchive;
+use paths::sanitize_entry_name;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use trace::{Access, AccessTrace};
+
+/// Where an asset was found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AssetSource
+{
+    /// The entry at `index` in the archive at `path`.
+    Archive { path : PathBuf, index : usize },
+    /// A loose file.
+    File(PathBuf),
+}
+
+enum Layer
+{
+    Archive
+    {
+        path : PathBuf,
+        archive : Box<ZfsArchive<io::BufReader<File>>>,
+        /// Entry indices by lowercased name, as the game ignores case.
+        names : HashMap<String, usize>,
+    },
+    Directory(PathBuf),
+}
+
+/// A trace being recorded.
+struct Recording
+{
+    started : Instant,
+    trace : AccessTrace,
+}
+
+/// A stack of archives and directories, searched from the most recently
+/// added down.
+#[derive(Default)]
+pub struct AssetVfs
+{
+    layers : Vec<Layer>,
+    recording : Option<Recording>,
+}
+
+impl AssetVfs
+{
+    pub fn new() -> AssetVfs {
+        AssetVfs::default()
+    }
+
+    /// Adds an archive on top of everything added so far.
+    pub fn add_archive(&mut self, path : impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref().to_path_buf();
+        let archive = ZfsArchive::open_indexed(&path)?;
+        let mut names = HashMap::new();
+        for (index, entry) in archive.entries().iter().enumerate() {
+            names.entry(entry.name.to_ascii_lowercase()).or_insert(index);
+        }
+        self.layers.push(Layer::Archive { path, archive : Box::new(archive), names });
+        Ok(())
+    }
+
+    /// Adds a directory of loose files on top of everything added so far.
+    ///
+    /// Names are looked up in the directory as given, so on case-sensitive
+    /// filesystems the case has to match.
+    pub fn add_directory(&mut self, path : impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        if !path.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} is not a directory", path.display())));
+        }
+        self.layers.push(Layer::Directory(path.to_path_buf()));
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// Finds the highest-priority source of an asset, ignoring case in
+    /// archive entry names.
+    pub fn resolve(&self, name : &str) -> Option<AssetSource> {
+        let lower_name = name.to_ascii_lowercase();
+        for layer in self.layers.iter().rev() {
+            match layer {
+                Layer::Archive { path, names, .. } => if let Some(&index) = names.get(&lower_name) {
+                    return Some(AssetSource::Archive { path : path.clone(), index });
+                },
+                Layer::Directory(dir) => if let Some(relative) = sanitize_entry_name(name) {
+                    let path = dir.join(relative);
+                    if path.is_file() {
+                        return Some(AssetSource::File(path));
+                    }
+                },
+            }
+        }
+        None
+    }
+
+    /// Finds every source of an asset, highest-priority (the one the game
+    /// uses) first. The rest are shadowed by it.
+    pub fn resolve_all(&self, name : &str) -> Vec<AssetSource> {
+        let lower_name = name.to_ascii_lowercase();
+        let mut sources = Vec::new();
+        for layer in self.layers.iter().rev() {
+            match layer {
+                Layer::Archive { path, names, .. } => if let Some(&index) = names.get(&lower_name) {
+                    sources.push(AssetSource::Archive { path : path.clone(), index });
+                },
+                Layer::Directory(dir) => if let Some(relative) = sanitize_entry_name(name) {
+                    let path = dir.join(relative);
+                    if path.is_file() {
+                        sources.push(AssetSource::File(path));
+                    }
+                },
+            }
+        }
+        sources
+    }
+
+    /// Starts recording every asset read into a trace (throwing away any
+    /// trace already being recorded).
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Recording { started : Instant::now(), trace : AccessTrace::default() });
+    }
+
+    /// Stops recording, and returns what was recorded (or None if nothing
+    /// was being recorded).
+    pub fn stop_recording(&mut self) -> Option<AccessTrace> {
+        self.recording.take().map(|recording| recording.trace)
+    }
+
+    /// Reads the highest-priority version of an asset.
+    pub fn read(&mut self, name : &str) -> io::Result<Vec<u8>> {
+        let time = self.recording.as_ref().map(|recording| recording.started.elapsed());
+        let result = self.read_layers(name);
+        if let (Some(time), Some(recording)) = (time, self.recording.as_mut()) {
+            let (source, offset, size) = match result {
+                Ok((ref source, offset, ref data)) => (Some(source.clone()), offset, data.len() as u64),
+                Err(_) => (None, 0, 0),
+            };
+            recording.trace.accesses.push(Access { time, name : name.to_string(), source, offset, size });
+        }
+        result.map(|(_, _, data)| data)
+    }
+
+    /// Reads an asset, returning where it came from and its offset in the
+    /// archive along with its data.
+    fn read_layers(&mut self, name : &str) -> io::Result<(AssetSource, u64, Vec<u8>)> {
+        let lower_name = name.to_ascii_lowercase();
+        for layer in self.layers.iter_mut().rev() {
+            match layer {
+                Layer::Archive { path, archive, names } => if let Some(&index) = names.get(&lower_name) {
+                    let offset = archive.entries()[index].offset as u64;
+                    return Ok((AssetSource::Archive { path : path.clone(), index }, offset, archive.read_data(index)?));
+                },
+                Layer::Directory(dir) => if let Some(relative) = sanitize_entry_name(name) {
+                    let path = dir.join(relative);
+                    if path.is_file() {
+                        let data = std::fs::read(&path)?;
+                        return Ok((AssetSource::File(path), 0, data));
+                    }
+                },
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::NotFound, format!("No asset named \"{}\"", name)))
+    }
+}
@@ -0,0 +1,116 @@
+/*
+ * unciv: An extractor for Civilization: Call to Power's zfs files.
+ *
+ * Copyright (c) 2023, David Gow <david@davidgow.net>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF OR
+ * IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+//! A JavaScript API, for reading archives and images in a web browser.
+//!
+//! Everything works on bytes which are already in memory (e.g. from a
+//! `File` the user picked, or a `fetch()`), since there's no file system to
+//! read them from.
+
+use rim::{ColorKey, RimImage};
+use std::io;
+use std::io::Cursor;
+use wasm_bindgen::prelude::*;
+use ZfsArchive;
+
+fn js_error(err : io::Error) -> JsError {
+    JsError::new(&err.to_string())
+}
+
+/// A ZFS archive, read from an array of bytes.
+#[wasm_bindgen(js_name = ZfsArchive)]
+pub struct Archive
+{
+    archive : ZfsArchive<Cursor<Vec<u8>>>,
+}
+
+/// The details of one of an archive's entries.
+#[wasm_bindgen(getter_with_clone)]
+pub struct Entry
+{
+    pub name : String,
+    /// Where the entry's data starts in the archive.
+    pub offset : u32,
+    pub size : u32,
+    /// When the entry was last modified, in seconds since 1970.
+    pub timestamp : u32,
+    pub flags : u32,
+}
+
+/// A decoded image: `rgba` holds width × height 8-bit RGBA pixels, ready
+/// for an `ImageData`.
+#[wasm_bindgen(getter_with_clone)]
+pub struct Image
+{
+    pub width : u16,
+    pub height : u16,
+    pub rgba : Vec<u8>,
+}
+
+#[wasm_bindgen(js_class = ZfsArchive)]
+impl Archive
+{
+    /// Reads the file table of the archive in `data`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(data : Vec<u8>) -> Result<Archive, JsError> {
+        Ok(Archive { archive : ZfsArchive::new(Cursor::new(data)).map_err(js_error)? })
+    }
+
+    /// The number of entries in the archive.
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> usize {
+        self.archive.len()
+    }
+
+    /// The details of the entry at `index`, or undefined if there isn't one.
+    pub fn entry(&self, index : usize) -> Option<Entry> {
+        self.archive.by_index(index).map(|entry| Entry {
+            name : entry.name.clone(),
+            offset : entry.offset as u32,
+            size : entry.size as u32,
+            timestamp : entry.zfs_timestamp(),
+            flags : entry.flags.raw(),
+        })
+    }
+
+    /// The index of the entry called exactly `name`, or undefined if there
+    /// isn't one.
+    pub fn find(&self, name : &str) -> Option<usize> {
+        self.archive.index_of(name)
+    }
+
+    /// Reads the data of the entry at `index`.
+    pub fn read(&mut self, index : usize) -> Result<Vec<u8>, JsError> {
+        self.archive.read_data(index).map_err(js_error)
+    }
+}
+
+/// Decodes the RIM image in `data` (e.g. an entry's data from
+/// `ZfsArchive.read()`).
+///
+/// `color_key` says which pixels become transparent: "none" (or undefined),
+/// "magenta", "zero", or a raw pixel value like "0x7c1f".
+#[wasm_bindgen(js_name = decodeRim)]
+pub fn decode_rim(data : &[u8], color_key : Option<String>) -> Result<Image, JsError> {
+    let color_key = match color_key {
+        Some(color_key) => color_key.parse().map_err(js_error)?,
+        None => ColorKey::None,
+    };
+    let image = RimImage::from_stream(&mut &data[..]).map_err(js_error)?;
+    Ok(Image { width : image.width, height : image.height, rgba : image.to_rgba_bytes_with(color_key).map_err(js_error)? })
+}